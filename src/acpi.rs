@@ -0,0 +1,233 @@
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+
+use crate::memory::phys_to_virt;
+use crate::warn;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::convert::Infallible;
+use core::mem::size_of;
+use fadt::Fadt;
+use hpet::Hpet;
+use madt::Madt;
+use x86_64::PhysAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// no RSDP in the BIOS area
+    NoRsdp,
+    /// a table with this signature has a wrong checksum
+    BadChecksum([u8; 4]),
+    /// a table is shorter than its header says it has to be
+    BadLength([u8; 4]),
+    /// a table describes hardware in a way the kernel can't use
+    Unsupported([u8; 4]),
+    NoFadt,
+    /// the DSDT has no `\_S5` object describing how to power off
+    NoS5,
+    /// the firmware didn't switch to ACPI mode
+    Timeout,
+}
+
+/// Root System Description Pointer, found by scanning the BIOS area
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // only valid for revision 2 and later
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the RSDP in ACPI 1.0
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Header every system description table starts with
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// The tables found through the RSDT or XSDT
+pub struct Acpi {
+    pub revision: u8,
+    tables: Vec<PhysAddr>,
+    madt: Option<Madt>,
+    fadt: Option<Fadt>,
+    hpet: Option<Hpet>,
+}
+
+static ACPI: OnceCell<Acpi> = OnceCell::uninit();
+
+/// Reads a `T` from physical memory, without alignment requirements
+///
+/// `T` must be valid for any bit pattern
+unsafe fn read_phys<T: Copy>(addr: PhysAddr) -> T {
+    core::ptr::read_unaligned(phys_to_virt(addr).as_ptr())
+}
+
+/// Returns the bytes at `addr` through the physical memory mapping
+unsafe fn phys_slice(addr: PhysAddr, len: usize) -> &'static [u8] {
+    core::slice::from_raw_parts(phys_to_virt(addr).as_ptr(), len)
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Looks for the RSDP in the first KiB of the EBDA and in 0xe0000..0x100000
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = unsafe { read_phys::<u16>(PhysAddr::new(0x40e)) } as u64 * 16;
+    let areas = [(ebda, ebda + 1024), (0xe0000, 0x100000)];
+
+    areas
+        .iter()
+        .filter(|(start, _)| *start != 0)
+        .flat_map(|&(start, end)| (start..end).step_by(16))
+        .map(PhysAddr::new)
+        .find(|&addr| {
+            let bytes = unsafe { phys_slice(addr, RSDP_V1_LENGTH) };
+            &bytes[..8] == b"RSD PTR " && checksum_ok(bytes)
+        })
+}
+
+/// Reads the header of the table at `addr` and validates the checksum of the whole table
+pub(crate) fn read_table(addr: PhysAddr) -> Result<SdtHeader, AcpiError> {
+    let header: SdtHeader = unsafe { read_phys(addr) };
+    if (header.length as usize) < size_of::<SdtHeader>() {
+        return Err(AcpiError::BadLength(header.signature));
+    }
+    if !checksum_ok(unsafe { phys_slice(addr, header.length as usize) }) {
+        return Err(AcpiError::BadChecksum(header.signature));
+    }
+    Ok(header)
+}
+
+impl Acpi {
+    fn parse() -> Result<Self, AcpiError> {
+        let rsdp_address = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+        let rsdp: Rsdp = unsafe { read_phys(rsdp_address) };
+
+        // ACPI 2.0 and later have a 64 bit XSDT, which is preferred
+        let (root, entry_size) = if rsdp.revision >= 2 {
+            let bytes = unsafe { phys_slice(rsdp_address, size_of::<Rsdp>()) };
+            if !checksum_ok(bytes) {
+                return Err(AcpiError::BadChecksum(*b"RSD "));
+            }
+            (PhysAddr::new(rsdp.xsdt_address), 8)
+        } else {
+            (PhysAddr::new(rsdp.rsdt_address as u64), 4)
+        };
+
+        let header = read_table(root)?;
+        let entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+        let first_entry = root + size_of::<SdtHeader>();
+        let tables = (0..entries)
+            .map(|i| {
+                let entry = first_entry + i * entry_size;
+                match entry_size {
+                    8 => unsafe { read_phys::<u64>(entry) },
+                    _ => unsafe { read_phys::<u32>(entry) as u64 },
+                }
+            })
+            .map(PhysAddr::new)
+            .collect();
+
+        let mut acpi = Acpi {
+            revision: rsdp.revision,
+            tables,
+            madt: None,
+            fadt: None,
+            hpet: None,
+        };
+        acpi.madt = acpi.find(b"APIC").map(Madt::parse).transpose()?;
+        acpi.fadt = acpi.parse_optional(b"FACP", Fadt::parse);
+        acpi.hpet = acpi.parse_optional(b"HPET", Hpet::parse);
+        Ok(acpi)
+    }
+
+    /// Parses a table the kernel can do without, a broken one is skipped
+    fn parse_optional<T>(
+        &self,
+        signature: &[u8; 4],
+        parse: fn(PhysAddr) -> Result<T, AcpiError>,
+    ) -> Option<T> {
+        match parse(self.find(signature)?) {
+            Ok(table) => Some(table),
+            Err(err) => {
+                let name = core::str::from_utf8(signature).unwrap_or("?");
+                warn!("ignoring the {} table: {:?}", name, err);
+                None
+            }
+        }
+    }
+
+    /// Returns the address of the first table with `signature`
+    pub fn find(&self, signature: &[u8; 4]) -> Option<PhysAddr> {
+        self.tables
+            .iter()
+            .copied()
+            .find(|&addr| unsafe { read_phys::<[u8; 4]>(addr) } == *signature)
+    }
+
+    /// Returns the signatures of all tables
+    pub fn signatures(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+        self.tables
+            .iter()
+            .map(|&addr| unsafe { read_phys::<[u8; 4]>(addr) })
+    }
+
+    pub fn madt(&self) -> Option<&Madt> {
+        self.madt.as_ref()
+    }
+
+    pub fn fadt(&self) -> Option<&Fadt> {
+        self.fadt.as_ref()
+    }
+
+    pub fn hpet(&self) -> Option<&Hpet> {
+        self.hpet.as_ref()
+    }
+}
+
+/// Finds and parses the ACPI tables
+///
+/// The heap has to be initialized. Only call once.
+pub fn init() -> Result<(), AcpiError> {
+    let acpi = Acpi::parse()?;
+    ACPI.try_init_once(|| acpi)
+        .expect("acpi::init should only be called once");
+    Ok(())
+}
+
+/// Returns the tables found by `init`, `None` if there are none
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.try_get().ok()
+}
+
+/// Turns the machine off through the PM1 control block described by the FADT
+///
+/// Only returns if that is not possible.
+pub fn shutdown() -> Result<Infallible, AcpiError> {
+    get()
+        .and_then(|acpi| acpi.fadt())
+        .ok_or(AcpiError::NoFadt)?
+        .power_off()
+}
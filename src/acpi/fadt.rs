@@ -0,0 +1,175 @@
+use super::{phys_slice, read_phys, read_table, AcpiError, SdtHeader};
+use core::convert::Infallible;
+use core::mem::size_of;
+use x86_64::{
+    instructions::{hlt, interrupts, port::Port},
+    PhysAddr,
+};
+
+/// Fixed ACPI Description Table, describes the power management hardware
+///
+/// Only the fields the kernel uses, the offsets are from the ACPI specification.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub dsdt: PhysAddr,
+    pub sci_interrupt: u16,
+    /// port to write `acpi_enable` to, 0 if the system is always in ACPI mode
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm_timer_block: u32,
+    /// RTC register holding the century, 0 if there is none
+    pub century: u8,
+    /// IA-PC boot architecture flags, see `has_8042`
+    pub boot_flags: u16,
+}
+
+// field offsets
+const DSDT: usize = 40;
+const SCI_INTERRUPT: usize = 46;
+const SMI_COMMAND: usize = 48;
+const ACPI_ENABLE: usize = 52;
+const PM1A_CONTROL_BLOCK: usize = 64;
+const PM1B_CONTROL_BLOCK: usize = 68;
+const PM_TIMER_BLOCK: usize = 76;
+const CENTURY: usize = 108;
+const BOOT_FLAGS: usize = 109;
+const X_DSDT: usize = 140;
+
+/// Tables of ACPI 1.0 end before the boot flags
+const BOOT_FLAGS_END: usize = 111;
+
+const BOOT_FLAG_8042: u16 = 1 << 1;
+
+// PM1 control register bits
+const SCI_ENABLED: u16 = 1 << 0;
+const SLEEP_TYPE_SHIFT: u16 = 10;
+const SLEEP_ENABLE: u16 = 1 << 13;
+
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+
+impl Fadt {
+    pub(super) fn parse(addr: PhysAddr) -> Result<Self, AcpiError> {
+        let header = read_table(addr)?;
+        let length = header.length as usize;
+        if length < PM_TIMER_BLOCK + 4 {
+            return Err(AcpiError::BadLength(header.signature));
+        }
+        let field = |offset: usize| addr + offset;
+
+        let mut dsdt = PhysAddr::new(unsafe { read_phys::<u32>(field(DSDT)) } as u64);
+        if length >= X_DSDT + 8 {
+            let x_dsdt: u64 = unsafe { read_phys(field(X_DSDT)) };
+            if x_dsdt != 0 {
+                dsdt = PhysAddr::new(x_dsdt);
+            }
+        }
+
+        let (century, boot_flags) = if length >= BOOT_FLAGS_END {
+            unsafe { (read_phys(field(CENTURY)), read_phys(field(BOOT_FLAGS))) }
+        } else {
+            (0, 0)
+        };
+
+        unsafe {
+            Ok(Fadt {
+                dsdt,
+                sci_interrupt: read_phys(field(SCI_INTERRUPT)),
+                smi_command: read_phys(field(SMI_COMMAND)),
+                acpi_enable: read_phys(field(ACPI_ENABLE)),
+                pm1a_control_block: read_phys(field(PM1A_CONTROL_BLOCK)),
+                pm1b_control_block: read_phys(field(PM1B_CONTROL_BLOCK)),
+                pm_timer_block: read_phys(field(PM_TIMER_BLOCK)),
+                century,
+                boot_flags,
+            })
+        }
+    }
+
+    /// Whether a PS/2 controller is present
+    ///
+    /// Firmware without the boot flags leaves it at 0, so only trust a set bit.
+    pub fn has_8042(&self) -> bool {
+        self.boot_flags & BOOT_FLAG_8042 != 0
+    }
+
+    /// Switches from legacy mode to ACPI mode if the firmware didn't already
+    fn enable_acpi_mode(&self) -> Result<(), AcpiError> {
+        let mut control: Port<u16> = Port::new(self.pm1a_control_block as u16);
+        if unsafe { control.read() } & SCI_ENABLED != 0 || self.smi_command == 0 {
+            return Ok(());
+        }
+
+        unsafe { Port::<u8>::new(self.smi_command as u16).write(self.acpi_enable) };
+        for _ in 0..1_000_000 {
+            if unsafe { control.read() } & SCI_ENABLED != 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(AcpiError::Timeout)
+    }
+
+    /// Enters sleep state S5 (soft off)
+    pub(super) fn power_off(&self) -> Result<Infallible, AcpiError> {
+        let (sleep_type_a, sleep_type_b) = self.s5_sleep_types()?;
+        self.enable_acpi_mode()?;
+
+        interrupts::disable();
+        unsafe {
+            Port::<u16>::new(self.pm1a_control_block as u16)
+                .write((sleep_type_a << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+            if self.pm1b_control_block != 0 {
+                Port::<u16>::new(self.pm1b_control_block as u16)
+                    .write((sleep_type_b << SLEEP_TYPE_SHIFT) | SLEEP_ENABLE);
+            }
+        }
+        // the machine is turned off while we wait
+        loop {
+            hlt();
+        }
+    }
+
+    /// Finds the `\_S5` package in the DSDT and returns SLP_TYPa and SLP_TYPb
+    ///
+    /// The DSDT is AML byte code. Instead of interpreting it, this looks for the
+    /// encoding `Name(_S5, Package() { a, b, ... })` that firmware uses in practice.
+    fn s5_sleep_types(&self) -> Result<(u16, u16), AcpiError> {
+        let header = read_table(self.dsdt)?;
+        let aml =
+            unsafe { &phys_slice(self.dsdt, header.length as usize)[size_of::<SdtHeader>()..] };
+
+        let position = aml
+            .windows(4)
+            .enumerate()
+            .find(|&(i, name)| {
+                name == b"_S5_"
+                    && i >= 1
+                    && (aml[i - 1] == AML_NAME_OP || (i >= 2 && aml[i - 2] == AML_NAME_OP))
+                    && aml.get(i + 4) == Some(&AML_PACKAGE_OP)
+            })
+            .map(|(i, _)| i)
+            .ok_or(AcpiError::NoS5)?;
+
+        // skip the package length (the top two bits of the first byte count the
+        // following bytes) and the number of elements
+        let mut i = position + 5;
+        let length_bytes = (*aml.get(i).ok_or(AcpiError::NoS5)? >> 6) as usize;
+        i += 1 + length_bytes + 1;
+
+        let mut read_integer = || {
+            if aml.get(i) == Some(&AML_BYTE_PREFIX) {
+                i += 1;
+            }
+            let value = aml.get(i).copied().ok_or(AcpiError::NoS5);
+            i += 1;
+            value.map(u16::from)
+        };
+        let sleep_type_a = read_integer()?;
+        let sleep_type_b = read_integer()?;
+        Ok((sleep_type_a, sleep_type_b))
+    }
+}
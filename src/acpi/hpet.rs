@@ -0,0 +1,45 @@
+use super::{read_phys, read_table, AcpiError};
+use x86_64::PhysAddr;
+
+/// High Precision Event Timer description
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    /// physical address of the memory mapped registers
+    pub address: PhysAddr,
+    pub hpet_number: u8,
+    /// minimum clock ticks in periodic mode without losing interrupts
+    pub minimum_tick: u16,
+}
+
+// field offsets
+const EVENT_TIMER_BLOCK_ID: usize = 36;
+const BASE_ADDRESS_SPACE: usize = 40;
+const BASE_ADDRESS: usize = 44;
+const HPET_NUMBER: usize = 52;
+const MINIMUM_TICK: usize = 53;
+const TABLE_END: usize = 56;
+
+/// The register block is in system memory, not in I/O space
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+impl Hpet {
+    pub(super) fn parse(addr: PhysAddr) -> Result<Self, AcpiError> {
+        let header = read_table(addr)?;
+        if (header.length as usize) < TABLE_END {
+            return Err(AcpiError::BadLength(header.signature));
+        }
+        let address_space: u8 = unsafe { read_phys(addr + BASE_ADDRESS_SPACE) };
+        let block_id: u32 = unsafe { read_phys(addr + EVENT_TIMER_BLOCK_ID) };
+        if address_space != ADDRESS_SPACE_MEMORY || block_id == 0 {
+            return Err(AcpiError::Unsupported(header.signature));
+        }
+
+        unsafe {
+            Ok(Hpet {
+                address: PhysAddr::new(read_phys(addr + BASE_ADDRESS)),
+                hpet_number: read_phys(addr + HPET_NUMBER),
+                minimum_tick: read_phys(addr + MINIMUM_TICK),
+            })
+        }
+    }
+}
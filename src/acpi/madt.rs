@@ -0,0 +1,140 @@
+use super::{read_phys, read_table, AcpiError, SdtHeader};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use x86_64::PhysAddr;
+
+// MADT entry types
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+/// Processor can be started
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+/// Processor is disabled but can be enabled by the OS
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// Multiple APIC Description Table, lists the interrupt controllers and CPUs
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    /// the 8259 PICs are installed as well and have to be masked
+    pub has_legacy_pics: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// first global system interrupt handled by this I/O APIC
+    pub gsi_base: u32,
+}
+
+/// An ISA interrupt that is not identity mapped to a global system interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags, polarity in bits 0-1 and trigger mode in bits 2-3
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+impl Madt {
+    pub(super) fn parse(addr: PhysAddr) -> Result<Self, AcpiError> {
+        let header = read_table(addr)?;
+        // the header is followed by the local APIC address and flags
+        let fields_end = size_of::<SdtHeader>() + 8;
+        if (header.length as usize) < fields_end {
+            return Err(AcpiError::BadLength(header.signature));
+        }
+        let local_apic_address: u32 = unsafe { read_phys(addr + size_of::<SdtHeader>()) };
+        let flags: u32 = unsafe { read_phys(addr + size_of::<SdtHeader>() + 4usize) };
+
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(local_apic_address as u64),
+            has_legacy_pics: flags & 1 != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+        };
+
+        let mut offset = fields_end;
+        while offset + 2 <= header.length as usize {
+            let entry = addr + offset;
+            let entry_type: u8 = unsafe { read_phys(entry) };
+            let length = unsafe { read_phys::<u8>(entry + 1usize) } as usize;
+            if length < 2 || offset + length > header.length as usize {
+                return Err(AcpiError::BadLength(header.signature));
+            }
+
+            match entry_type {
+                ENTRY_LOCAL_APIC if length >= 8 => {
+                    let processor_id: u8 = unsafe { read_phys(entry + 2usize) };
+                    let apic_id: u8 = unsafe { read_phys(entry + 3usize) };
+                    let flags: u32 = unsafe { read_phys(entry + 4usize) };
+                    madt.processors.push(Processor {
+                        processor_id,
+                        apic_id,
+                        enabled: flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0,
+                    });
+                }
+                ENTRY_IO_APIC if length >= 12 => {
+                    let id: u8 = unsafe { read_phys(entry + 2usize) };
+                    let address: u32 = unsafe { read_phys(entry + 4usize) };
+                    let gsi_base: u32 = unsafe { read_phys(entry + 8usize) };
+                    madt.io_apics.push(IoApicEntry {
+                        id,
+                        address: PhysAddr::new(address as u64),
+                        gsi_base,
+                    });
+                }
+                ENTRY_INTERRUPT_OVERRIDE if length >= 10 => {
+                    let bus: u8 = unsafe { read_phys(entry + 2usize) };
+                    let irq: u8 = unsafe { read_phys(entry + 3usize) };
+                    let gsi: u32 = unsafe { read_phys(entry + 4usize) };
+                    let flags: u16 = unsafe { read_phys(entry + 8usize) };
+                    madt.overrides.push(InterruptOverride {
+                        bus,
+                        irq,
+                        gsi,
+                        flags,
+                    });
+                }
+                ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE if length >= 12 => {
+                    let address: u64 = unsafe { read_phys(entry + 4usize) };
+                    madt.local_apic_address = PhysAddr::new(address);
+                }
+                _ => {}
+            }
+            offset += length;
+        }
+
+        Ok(madt)
+    }
+
+    /// Returns the override for the ISA interrupt `irq`, if there is one
+    pub fn interrupt_override(&self, irq: u8) -> Option<&InterruptOverride> {
+        self.overrides.iter().find(|o| o.irq == irq)
+    }
+}
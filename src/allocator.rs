@@ -47,11 +47,18 @@ use linked_list::LinkedListAllocator;
 static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
 
 pub const HEAP_START: usize = 0x4444_4444_0000;
+/// Initial size of the heap
 pub const HEAP_SIZE: usize = 100 * 1024;
+/// The heap grows on demand up to this size
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+/// Minimum number of bytes mapped each time the heap grows
+const HEAP_GROW_SIZE: usize = 64 * 1024;
 
+use crate::memory;
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+        Size4KiB,
     },
     VirtAddr,
 };
@@ -81,3 +88,67 @@ pub fn init_heap(
     }
     Ok(())
 }
+
+/// Maps `size` more bytes at `heap_end`, the current end of the heap
+///
+/// Fails if the global page table is not initialized yet (see `memory::init_global`),
+/// the heap would exceed `HEAP_MAX_SIZE` or memory ran out. Called with the
+/// allocator locked, so it must not allocate.
+fn grow_heap(heap_end: usize, size: usize) -> bool {
+    if heap_end + size > HEAP_START + HEAP_MAX_SIZE {
+        return false;
+    }
+    let pages = Page::<Size4KiB>::range(
+        Page::containing_address(VirtAddr::new(heap_end as u64)),
+        Page::containing_address(VirtAddr::new((heap_end + size) as u64)),
+    );
+
+    memory::try_with_mapper(|mapper, frames| {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        for page in pages {
+            let mapped = frames.allocate_frame().and_then(|frame| {
+                match unsafe { mapper.map_to(page, frame, flags, frames) } {
+                    Ok(flush) => {
+                        flush.flush();
+                        Some(())
+                    }
+                    Err(_) => {
+                        unsafe { frames.deallocate_frame(frame) };
+                        None
+                    }
+                }
+            });
+            if mapped.is_none() {
+                // undo the pages mapped so far, the heap keeps its old size
+                for page in Page::range(pages.start, page) {
+                    let (frame, flush) = mapper.unmap(page).expect("heap page not mapped");
+                    flush.flush();
+                    unsafe { frames.deallocate_frame(frame) };
+                }
+                return false;
+            }
+        }
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// Heap usage, see `stats`
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    /// bytes mapped, the heap grows up to `HEAP_MAX_SIZE`
+    pub size: usize,
+    /// bytes allocated
+    pub used: usize,
+}
+
+/// Returns how much of the heap is in use
+pub fn stats() -> HeapStats {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let allocator = ALLOCATOR.lock();
+        HeapStats {
+            size: allocator.size(),
+            used: allocator.used(),
+        }
+    })
+}
@@ -1,10 +1,11 @@
-use super::Locked;
+use super::{align_up, Locked, HEAP_GROW_SIZE};
 use alloc::alloc::GlobalAlloc;
 use core::{
     alloc::Layout,
     mem,
     ptr::{self, NonNull},
 };
+use x86_64::instructions::interrupts;
 
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 1024, 2048];
 
@@ -15,6 +16,8 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    /// bytes handed out and not freed yet, free blocks in the lists don't count
+    used: usize,
 }
 
 /// Choose an appropriate block size
@@ -25,6 +28,14 @@ fn list_index(layout: &Layout) -> Option<usize> {
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
 }
 
+/// Bytes an allocation with `layout` takes up
+fn block_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
+    }
+}
+
 impl FixedSizeBlockAllocator {
     /// makes an emtpy new allocator
     pub const fn new() -> Self {
@@ -32,15 +43,37 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            used: 0,
         }
     }
 
+    /// Bytes mapped for the heap
+    pub fn size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+
+    /// Bytes of the allocations that are alive, rounded up to the block size
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
     /// caller must guarantee that the heap is valid and unused. method must only be called once
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // grow the heap by enough for the allocation, even if it has to be aligned
+        let size = align_up(layout.size() + layout.align(), 4096).max(HEAP_GROW_SIZE);
+        if !super::grow_heap(self.fallback_allocator.top(), size) {
+            return ptr::null_mut();
+        }
+        unsafe { self.fallback_allocator.extend(size) };
+
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
             Err(_) => ptr::null_mut(),
@@ -48,10 +81,22 @@ impl FixedSizeBlockAllocator {
     }
 }
 
+// The lock is held with interrupts disabled, so a preempted thread can never
+// hold it while the interrupted code (or the next thread) allocates.
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupts::without_interrupts(|| self.alloc_inner(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupts::without_interrupts(|| self.dealloc_inner(ptr, layout))
+    }
+}
+
+impl Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc_inner(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => match allocator.list_heads[index].take() {
                 Some(node) => {
                     allocator.list_heads[index] = node.next.take();
@@ -65,11 +110,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             },
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.used += block_size(&layout);
         }
+        ptr
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.used -= block_size(&layout);
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
@@ -0,0 +1,136 @@
+pub mod symbols;
+
+use crate::interrupts::InterruptStackFrame;
+use crate::memory;
+use core::arch::asm;
+use core::fmt;
+use x86_64::VirtAddr;
+
+/// Most frames a backtrace keeps
+const MAX_FRAMES: usize = 32;
+
+/// Return addresses of a call stack, newest first
+///
+/// Found by following the frame pointers, the target has them enabled for the
+/// kernel and `core`. Printed with the symbols `symbols::lookup` finds.
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+    /// whether the first frame is the faulting instruction instead of a return address
+    faulting: bool,
+}
+
+impl Backtrace {
+    /// The stack of the function that calls it, starting with its caller
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        let mut backtrace = Backtrace::empty();
+        backtrace.walk(rbp);
+        backtrace
+    }
+
+    /// The stack of the code `stack_frame` interrupted, has to be called by the exception handler
+    ///
+    /// Starts with the faulting instruction. The stack of user code isn't followed.
+    #[inline(always)]
+    pub fn interrupted(stack_frame: &InterruptStackFrame) -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        let mut backtrace = Backtrace::empty();
+        backtrace.faulting = true;
+        backtrace.push(stack_frame.instruction_pointer());
+        // the entry stubs leave rbp alone, so the handler saved the interrupted one first
+        if !stack_frame.from_user_mode() && is_frame(rbp) {
+            backtrace.walk(unsafe { *(rbp as *const u64) });
+        }
+        backtrace
+    }
+
+    fn empty() -> Self {
+        Backtrace {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+            faulting: false,
+        }
+    }
+
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+
+    fn push(&mut self, address: u64) {
+        if self.len < MAX_FRAMES {
+            self.frames[self.len] = address;
+            self.len += 1;
+        }
+    }
+
+    /// Follows the frame pointers from `rbp` until the chain ends or leaves mapped memory
+    fn walk(&mut self, mut rbp: u64) {
+        while self.len < MAX_FRAMES && is_frame(rbp) {
+            let frame = rbp as *const u64;
+            let (next, return_address) = unsafe { (*frame, *frame.add(1)) };
+            if return_address == 0 {
+                break;
+            }
+            self.push(return_address);
+            // callers are further up the stack
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+    }
+}
+
+/// Whether `rbp` can point to a saved frame pointer and return address
+fn is_frame(rbp: u64) -> bool {
+    let mapped = |addr| VirtAddr::try_new(addr).map_or(false, memory::is_mapped);
+    rbp != 0 && rbp % 8 == 0 && mapped(rbp) && mapped(rbp + 8)
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "backtrace:")?;
+        for (i, &address) in self.frames().iter().enumerate() {
+            write!(f, "{:>4}: {:#018x}", i, address)?;
+            // a return address can be the first byte after the function
+            let symbol = match (i, self.faulting) {
+                (0, true) => symbols::lookup(address),
+                _ => symbols::lookup(address.saturating_sub(1)),
+            };
+            match symbol {
+                Some(symbol) => writeln!(f, " {}+{:#x}", symbol.name(), address - symbol.address)?,
+                None => writeln!(f, " <unknown>")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_capture() {
+    /// Starts the backtrace with a return address in `test_capture`
+    #[inline(never)]
+    fn capture() -> Backtrace {
+        Backtrace::capture()
+    }
+
+    let backtrace = capture();
+    let &caller = backtrace.frames().first().expect("empty backtrace");
+    assert!(caller > test_capture as *const () as u64);
+    let symbol = symbols::lookup(caller - 1).expect("no symbol for the caller");
+    assert!(symbol.name().ends_with("test_capture"));
+}
+
+#[test_case]
+fn test_lookup() {
+    // `tools/runner.sh` embeds the table, it needs python3
+    assert!(symbols::count() > 0, "no symbol table embedded");
+    let address = test_lookup as *const () as u64;
+    let symbol = symbols::lookup(address + 1).expect("no symbol for test_lookup");
+    assert_eq!(symbol.address, address);
+    assert!(symbol.name().ends_with("test_lookup"));
+}
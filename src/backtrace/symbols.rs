@@ -0,0 +1,98 @@
+use core::{mem, ptr, str};
+
+/// Bytes reserved for the table, `tools/embed_symbols.py` fails if it needs more
+pub const TABLE_SIZE: usize = 512 * 1024;
+/// Longest name a lookup returns, longer ones are cut off
+const MAX_NAME: usize = 128;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"KSYM");
+/// magic and number of entries
+const HEADER_SIZE: usize = 8;
+/// address, size, name offset and name length
+const ENTRY_SIZE: usize = 24;
+
+#[repr(C, align(8))]
+struct Table([u8; TABLE_SIZE]);
+
+/// The function symbols of the kernel, sorted by address
+///
+/// Written into the linked binary by `tools/embed_symbols.py`, so it stays
+/// zero if the script didn't run. It is read with volatile reads, the compiler
+/// would otherwise assume the zeros.
+#[used]
+#[link_section = ".ksyms"]
+static TABLE: Table = Table([0; TABLE_SIZE]);
+
+/// A function the kernel was linked with
+pub struct Symbol {
+    pub address: u64,
+    /// bytes of code, 0 if unknown
+    pub size: u64,
+    name: [u8; MAX_NAME],
+    name_len: usize,
+}
+
+impl Symbol {
+    pub fn name(&self) -> &str {
+        let name = &self.name[..self.name_len];
+        // a name cut off in the middle of a character
+        match str::from_utf8(name) {
+            Ok(name) => name,
+            Err(err) => str::from_utf8(&name[..err.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+/// Reads a `T` at `offset` of the table, `offset` has to be aligned for `T`
+fn read<T: Copy>(offset: usize) -> Option<T> {
+    if offset + mem::size_of::<T>() > TABLE_SIZE {
+        return None;
+    }
+    let base = ptr::addr_of!(TABLE.0) as *const u8;
+    Some(unsafe { ptr::read_volatile(base.add(offset) as *const T) })
+}
+
+/// Number of symbols, 0 if the table wasn't embedded
+pub fn count() -> usize {
+    match read::<u32>(0) {
+        Some(MAGIC) => read::<u32>(4).map_or(0, |count| count as usize),
+        _ => 0,
+    }
+}
+
+fn entry_address(index: usize) -> u64 {
+    read(HEADER_SIZE + index * ENTRY_SIZE).unwrap_or(u64::MAX)
+}
+
+fn entry(index: usize) -> Option<Symbol> {
+    let offset = HEADER_SIZE + index * ENTRY_SIZE;
+    let name_offset = read::<u32>(offset + 16)? as usize;
+    let name_len = (read::<u32>(offset + 20)? as usize).min(MAX_NAME);
+    let mut symbol = Symbol {
+        address: read(offset)?,
+        size: read(offset + 8)?,
+        name: [0; MAX_NAME],
+        name_len,
+    };
+    for i in 0..name_len {
+        symbol.name[i] = read(name_offset + i)?;
+    }
+    Some(symbol)
+}
+
+/// Returns the function `address` lies in
+pub fn lookup(address: u64) -> Option<Symbol> {
+    // the first entry behind `address`
+    let (mut low, mut high) = (0, count());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if entry_address(middle) <= address {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    let symbol = entry(low.checked_sub(1)?)?;
+    let end = symbol.address.saturating_add(symbol.size);
+    (symbol.size == 0 || address < end).then_some(symbol)
+}
@@ -0,0 +1,123 @@
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::registers::model_specific::{GsBase, KernelGsBase};
+use x86_64::VirtAddr;
+
+/// Thread id of cpus that don't run threads, the scheduler only runs on the bootstrap processor
+const NO_THREAD: u64 = u64::MAX;
+
+/// State that every cpu keeps for itself, reachable through the GS base
+///
+/// Interrupts and syscalls from ring 3 `swapgs`, so in the kernel GS always
+/// points to the block of the running cpu.
+#[repr(C)]
+pub struct Cpu {
+    /// address of this block, read through `gs:0` by `current`
+    this: AtomicU64,
+    index: usize,
+    apic_id: u8,
+    current_thread: AtomicU64,
+    /// whether the cpu is inside a print, see `emergency::enter`
+    printing: AtomicBool,
+}
+
+impl Cpu {
+    fn new(index: usize, apic_id: u8) -> Self {
+        Cpu {
+            this: AtomicU64::new(0),
+            index,
+            apic_id,
+            current_thread: AtomicU64::new(NO_THREAD),
+            printing: AtomicBool::new(false),
+        }
+    }
+
+    /// Points the GS base of the running cpu at this block
+    fn install(&'static self) {
+        let addr = VirtAddr::from_ptr(self);
+        self.this.store(addr.as_u64(), Ordering::Relaxed);
+        GsBase::write(addr);
+        // what user code sees after the first `swapgs`
+        KernelGsBase::write(VirtAddr::zero());
+    }
+
+    /// 0 for the bootstrap processor, the application processors count up from 1
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn apic_id(&self) -> u8 {
+        self.apic_id
+    }
+
+    pub fn is_bsp(&self) -> bool {
+        self.index == 0
+    }
+
+    /// Id of the thread running on this cpu, see `thread::current_id`
+    pub(crate) fn current_thread(&self) -> u64 {
+        self.current_thread.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_current_thread(&self, id: u64) {
+        self.current_thread.store(id, Ordering::Relaxed);
+    }
+
+    /// Marks the cpu as printing, returns false if it already was
+    pub(crate) fn start_printing(&self) -> bool {
+        !self.printing.swap(true, Ordering::Acquire)
+    }
+
+    pub(crate) fn stop_printing(&self) {
+        self.printing.store(false, Ordering::Release);
+    }
+}
+
+lazy_static! {
+    static ref BSP: Cpu = Cpu::new(0, initial_apic_id());
+}
+
+/// Number of cpus that called `init` or `init_ap`
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the APIC id of the running cpu through CPUID, works before the APIC is mapped
+fn initial_apic_id() -> u8 {
+    (unsafe { __cpuid(1) }.ebx >> 24) as u8
+}
+
+/// Sets up the block of the bootstrap processor, has to come before anything calls `current`
+pub fn init() {
+    BSP.install();
+    ONLINE.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Sets up the block of an application processor, the heap has to be initialized
+pub(crate) fn init_ap(index: usize) -> &'static Cpu {
+    let cpu: &'static Cpu = Box::leak(Box::new(Cpu::new(index, initial_apic_id())));
+    cpu.install();
+    ONLINE.fetch_add(1, Ordering::SeqCst);
+    cpu
+}
+
+/// Returns the block of the running cpu
+pub fn current() -> &'static Cpu {
+    let this: u64;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) this, options(nostack, readonly, preserves_flags)) };
+    unsafe { &*(this as *const Cpu) }
+}
+
+/// Returns the block of the running cpu, `None` before `init` or `init_ap` ran on it
+pub fn try_current() -> Option<&'static Cpu> {
+    match GsBase::read().is_null() {
+        true => None,
+        false => Some(current()),
+    }
+}
+
+/// Number of cpus that are running
+pub fn count() -> usize {
+    ONLINE.load(Ordering::SeqCst)
+}
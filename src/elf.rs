@@ -0,0 +1,182 @@
+pub mod loader;
+
+use core::mem::size_of;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+pub const PT_LOAD: u32 = 1;
+
+/// Segment permission bits
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    Not64Bit,
+    NotLittleEndian,
+    BadVersion,
+    NotExecutable,
+    WrongMachine,
+    BadProgramHeaders,
+    SegmentOutOfBounds,
+}
+
+/// ELF64 file header
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Header {
+    pub ident: [u8; 16],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// ELF64 program header, describes a segment
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// A validated ELF64 executable for x86_64
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: Header,
+}
+
+/// Reads a `T` from `data` at `offset`, without alignment requirements
+///
+/// `T` must be valid for any bit pattern
+fn read<T: Copy>(data: &[u8], offset: u64) -> Option<T> {
+    let offset = usize::try_from(offset).ok()?;
+    let end = offset.checked_add(size_of::<T>())?;
+    let bytes = data.get(offset..end)?;
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+impl<'a> ElfFile<'a> {
+    /// Checks the header and all program headers
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header: Header = read(data, 0).ok_or(ElfError::TooShort)?;
+
+        if header.ident[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != CLASS_64 {
+            return Err(ElfError::Not64Bit);
+        }
+        if header.ident[5] != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if header.ident[6] != VERSION_CURRENT || header.version != VERSION_CURRENT as u32 {
+            return Err(ElfError::BadVersion);
+        }
+        if header.elf_type != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if header.machine != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+        if header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err(ElfError::BadProgramHeaders);
+        }
+
+        let elf = ElfFile { data, header };
+        for index in 0..header.phnum {
+            let ph = elf
+                .program_header(index)
+                .ok_or(ElfError::BadProgramHeaders)?;
+            if ph.p_type != PT_LOAD {
+                continue;
+            }
+            let file_end = ph.offset.checked_add(ph.filesz);
+            let mem_end = ph.vaddr.checked_add(ph.memsz);
+            match (file_end, mem_end) {
+                (Some(file_end), Some(_))
+                    if file_end <= data.len() as u64 && ph.filesz <= ph.memsz => {}
+                _ => return Err(ElfError::SegmentOutOfBounds),
+            }
+        }
+
+        Ok(elf)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn entry_point(&self) -> u64 {
+        self.header.entry
+    }
+
+    fn program_header(&self, index: u16) -> Option<ProgramHeader> {
+        let offset = self
+            .header
+            .phoff
+            .checked_add(index as u64 * size_of::<ProgramHeader>() as u64)?;
+        read(self.data, offset)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.header.phnum).filter_map(move |index| self.program_header(index))
+    }
+
+    /// Returns the `PT_LOAD` segments
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        self.program_headers().filter(|ph| ph.p_type == PT_LOAD)
+    }
+
+    /// Returns the bytes of the segment stored in the file
+    ///
+    /// Can be shorter than the segment in memory, the rest is zeroed
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        &self.data[ph.offset as usize..(ph.offset + ph.filesz) as usize]
+    }
+}
+
+#[test_case]
+fn test_parse_embedded_program() {
+    let elf = ElfFile::parse(loader::HELLO).expect("parsing failed");
+    assert!(elf.load_segments().count() > 0);
+    assert!(crate::usermode::is_user_range(elf.entry_point(), 1));
+}
+
+#[test_case]
+fn test_reject_invalid() {
+    assert_eq!(
+        ElfFile::parse(&[0x7f, b'E']).err(),
+        Some(ElfError::TooShort)
+    );
+
+    let mut data = [0u8; 64];
+    assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::BadMagic));
+
+    data[0..4].copy_from_slice(&ELF_MAGIC);
+    data[4] = 1;
+    assert_eq!(ElfFile::parse(&data).err(), Some(ElfError::Not64Bit));
+}
@@ -0,0 +1,290 @@
+use super::{ElfError, ElfFile, ProgramHeader, PF_W, PF_X};
+use crate::{
+    error,
+    memory::{self, address_space::AddressSpace},
+    thread,
+    thread::{ExitStatus, ThreadId},
+    usermode,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{mapper::MapToError, Page, PageTableFlags, PhysFrame, Size4KiB},
+    VirtAddr,
+};
+
+/// Test program, see `user/hello.s`
+pub static HELLO: &[u8] = include_bytes!("../../user/hello.elf");
+
+pub const USER_STACK_TOP: u64 = 0x0000_3fff_ffff_0000;
+pub const USER_STACK_SIZE: u64 = 16 * 4096;
+
+// auxiliary vector entry types
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// a segment lies (partly) outside of user space
+    NotInUserSpace,
+    Map(MapToError<Size4KiB>),
+    /// argv and envp don't fit on the user stack
+    ArgumentsTooLong,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        LoadError::Elf(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for LoadError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        LoadError::Map(err)
+    }
+}
+
+/// A program mapped into memory, ready to be run
+pub struct Program {
+    entry: VirtAddr,
+    stack_pointer: VirtAddr,
+}
+
+impl Program {
+    /// Jumps to the entry point in ring 3
+    pub fn run(self) -> ! {
+        unsafe { usermode::enter_user_mode(self.entry, self.stack_pointer) }
+    }
+}
+
+/// Maps the executable and a user stack with `argv`, `envp` and the auxiliary vector
+///
+/// `address_space` has to be active, the stack is written through it.
+pub fn load(
+    address_space: &mut AddressSpace,
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Program, LoadError> {
+    let elf = ElfFile::parse(data)?;
+    if !usermode::is_user_range(elf.entry_point(), 1) {
+        return Err(LoadError::NotInUserSpace);
+    }
+
+    // ranges that may be partly mapped when loading fails
+    let mut mapped = Vec::new();
+    let result = map_program(address_space, &elf, argv, envp, &mut mapped);
+    if result.is_err() {
+        for (start, len) in mapped {
+            address_space
+                .unmap(start, len)
+                .expect("unmapping a partly loaded program failed");
+        }
+    }
+    let stack_pointer = result?;
+
+    Ok(Program {
+        entry: VirtAddr::new(elf.entry_point()),
+        stack_pointer,
+    })
+}
+
+/// Maps the segments and the stack, adding every range it touches to `mapped`
+fn map_program(
+    address_space: &mut AddressSpace,
+    elf: &ElfFile,
+    argv: &[&str],
+    envp: &[&str],
+    mapped: &mut Vec<(VirtAddr, u64)>,
+) -> Result<VirtAddr, LoadError> {
+    for ph in elf.load_segments() {
+        if ph.memsz == 0 {
+            continue;
+        }
+        if !usermode::is_user_range(ph.vaddr, ph.memsz) {
+            return Err(LoadError::NotInUserSpace);
+        }
+        mapped.push((VirtAddr::new(ph.vaddr), ph.memsz));
+        map_segment(address_space, elf, &ph)?;
+    }
+    let stack_bottom = VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE);
+    mapped.push((stack_bottom, USER_STACK_SIZE));
+    setup_stack(address_space, elf, argv, envp)
+}
+
+/// Runs the program in a new kernel thread with its own address space
+pub fn spawn(data: &'static [u8], argv: &[&str], envp: &[&str]) -> ThreadId {
+    let argv: Vec<String> = argv.iter().map(|&s| String::from(s)).collect();
+    let envp: Vec<String> = envp.iter().map(|&s| String::from(s)).collect();
+
+    thread::spawn_thread(move || {
+        let program = AddressSpace::new()
+            .map_err(LoadError::Map)
+            .and_then(|address_space| {
+                let address_space = Arc::new(Mutex::new(address_space));
+                thread::set_address_space(address_space.clone());
+
+                let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+                let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+                let mut address_space = address_space.lock();
+                load(&mut address_space, data, &argv, &envp)
+            });
+        // `run` never returns, so nothing would be dropped after it
+        drop((argv, envp));
+
+        match program {
+            Ok(program) => program.run(),
+            Err(err) => {
+                error!("failed to load program: {:?}", err);
+                thread::exit_with(ExitStatus::Killed);
+            }
+        }
+    })
+}
+
+/// Maps a segment and copies its data, `ph` has to be non-empty and in user space
+fn map_segment(
+    address_space: &mut AddressSpace,
+    elf: &ElfFile,
+    ph: &ProgramHeader,
+) -> Result<(), LoadError> {
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if ph.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if ph.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let data = elf.segment_data(ph);
+    let segment_start = ph.vaddr;
+    let pages = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(VirtAddr::new(segment_start)),
+        Page::containing_address(VirtAddr::new(segment_start + ph.memsz - 1)),
+    );
+
+    for page in pages {
+        let frame = map_user_page(address_space, page, flags)?;
+
+        // copy the part of the file data that lands in this page
+        let page_start = page.start_address().as_u64();
+        let copy_start = page_start.max(segment_start);
+        let copy_end = (page_start + 4096).min(segment_start + data.len() as u64);
+        if copy_start < copy_end {
+            let src =
+                &data[(copy_start - segment_start) as usize..(copy_end - segment_start) as usize];
+            let dst = memory::phys_to_virt(frame.start_address()) + (copy_start - page_start);
+            unsafe {
+                core::ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr(), src.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Maps a zeroed frame at `page`
+///
+/// Segments can share a page, in that case the existing frame is reused and
+/// the flags of both segments are combined.
+fn map_user_page(
+    address_space: &mut AddressSpace,
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<PhysFrame, MapToError<Size4KiB>> {
+    match address_space.translate(page) {
+        Some((frame, old_flags)) => {
+            let no_execute = old_flags & flags & PageTableFlags::NO_EXECUTE;
+            let combined = ((old_flags | flags) - PageTableFlags::NO_EXECUTE) | no_execute;
+            address_space
+                .protect(page.start_address(), 4096, combined)
+                .expect("page is mapped");
+            Ok(frame)
+        }
+        None => {
+            address_space.map(page.start_address(), 4096, flags)?;
+            let (frame, _) = address_space.translate(page).expect("page was just mapped");
+            Ok(frame)
+        }
+    }
+}
+
+/// Lays out the initial stack as described by the System V ABI
+///
+/// From the stack pointer upwards: argc, argv pointers, NULL, envp pointers,
+/// NULL, auxiliary vector, and the strings above that.
+fn setup_stack(
+    address_space: &mut AddressSpace,
+    elf: &ElfFile,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<VirtAddr, LoadError> {
+    let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE;
+    address_space.map(VirtAddr::new(stack_bottom), USER_STACK_SIZE, flags)?;
+
+    let mut sp = USER_STACK_TOP;
+    let mut push_strings = |strings: &[&str]| -> Result<Vec<u64>, LoadError> {
+        let mut pointers = Vec::with_capacity(strings.len());
+        for s in strings {
+            sp = sp
+                .checked_sub(s.len() as u64 + 1)
+                .filter(|&sp| sp >= stack_bottom)
+                .ok_or(LoadError::ArgumentsTooLong)?;
+            unsafe {
+                let dst = sp as *mut u8;
+                core::ptr::copy_nonoverlapping(s.as_ptr(), dst, s.len());
+                dst.add(s.len()).write(0);
+            }
+            pointers.push(sp);
+        }
+        Ok(pointers)
+    };
+    let argv_pointers = push_strings(argv)?;
+    let envp_pointers = push_strings(envp)?;
+
+    let header = elf.header();
+    let mut auxv = Vec::new();
+    // the program headers are only in memory if a segment covers them
+    if let Some(ph) = elf
+        .load_segments()
+        .find(|ph| ph.offset <= header.phoff && header.phoff < ph.offset + ph.filesz)
+    {
+        auxv.push((AT_PHDR, ph.vaddr + (header.phoff - ph.offset)));
+    }
+    auxv.push((AT_PHENT, header.phentsize as u64));
+    auxv.push((AT_PHNUM, header.phnum as u64));
+    auxv.push((AT_PAGESZ, 4096));
+    auxv.push((AT_ENTRY, elf.entry_point()));
+    auxv.push((AT_NULL, 0));
+
+    let mut words = Vec::new();
+    words.push(argv_pointers.len() as u64);
+    words.extend_from_slice(&argv_pointers);
+    words.push(0);
+    words.extend_from_slice(&envp_pointers);
+    words.push(0);
+    for (key, value) in auxv {
+        words.push(key);
+        words.push(value);
+    }
+
+    // rsp has to be 16 byte aligned at the entry point
+    let sp = sp
+        .checked_sub(words.len() as u64 * 8)
+        .map(|sp| sp & !0xf)
+        .filter(|&sp| sp >= stack_bottom)
+        .ok_or(LoadError::ArgumentsTooLong)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(words.as_ptr(), sp as *mut u64, words.len());
+    }
+
+    Ok(VirtAddr::new(sp))
+}
@@ -0,0 +1,262 @@
+use crate::backtrace::Backtrace;
+use crate::cpu::{self, Cpu};
+use crate::framebuffer::{font, Framebuffer, Rgb};
+use crate::log;
+use crate::vga_buffer::{cp437, Color};
+use core::fmt::{self, Write};
+use core::hint;
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::{Port, PortReadOnly};
+
+const SERIAL_PORT: u16 = 0x3f8;
+/// Line status bit that is set once the port can take the next byte
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+/// Polls of the line status before a byte is sent anyway
+const SERIAL_TIMEOUT: usize = 100_000;
+/// Attempts to lock `SCREEN` before the output is dropped, another cpu might be writing
+const SCREEN_TIMEOUT: usize = 10_000_000;
+
+const TEXT_BUFFER: usize = 0xb8000;
+const TEXT_WIDTH: usize = 80;
+const TEXT_HEIGHT: usize = 25;
+
+/// Panics reported so far
+static PANICS: AtomicUsize = AtomicUsize::new(0);
+static SCREEN: Mutex<Screen> = Mutex::new(Screen::new());
+
+/// Keeps the running cpu marked as printing, see `enter`
+pub struct PrintGuard {
+    cpu: Option<&'static Cpu>,
+}
+
+impl Drop for PrintGuard {
+    fn drop(&mut self) {
+        if let Some(cpu) = self.cpu {
+            cpu.stop_printing();
+        }
+    }
+}
+
+/// Has to be held while printing takes its locks
+///
+/// Returns `None` if the output has to go through `ScreenWriter` or
+/// `SerialWriter` instead: the kernel panicked, or the cpu is already printing
+/// further up the stack, so a fault or an NMI interrupted it while it might
+/// hold the locks.
+pub fn enter() -> Option<PrintGuard> {
+    if is_panicking() {
+        return None;
+    }
+    match cpu::try_current() {
+        Some(cpu) if !cpu.start_printing() => None,
+        cpu => Some(PrintGuard { cpu }),
+    }
+}
+
+pub fn is_panicking() -> bool {
+    PANICS.load(Ordering::SeqCst) > 0
+}
+
+/// Writes to COM1 without taking a lock
+pub struct SerialWriter;
+
+impl fmt::Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = Port::<u8>::new(SERIAL_PORT);
+        let mut line_status = PortReadOnly::<u8>::new(SERIAL_PORT + 5);
+        for byte in s.bytes() {
+            for _ in 0..SERIAL_TIMEOUT {
+                if unsafe { line_status.read() } & LSR_TRANSMIT_EMPTY != 0 {
+                    break;
+                }
+                hint::spin_loop();
+            }
+            unsafe { data.write(byte) };
+        }
+        Ok(())
+    }
+}
+
+/// Writes to the screen in white on red, bypassing the consoles
+///
+/// Starts at the top and wraps around at the bottom. Escape sequences are
+/// skipped.
+pub struct ScreenWriter;
+
+impl fmt::Write for ScreenWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        interrupts::without_interrupts(|| {
+            for _ in 0..SCREEN_TIMEOUT {
+                if let Some(mut screen) = SCREEN.try_lock() {
+                    s.chars().for_each(|c| screen.write_char(c));
+                    return;
+                }
+                hint::spin_loop();
+            }
+        });
+        Ok(())
+    }
+}
+
+struct Screen {
+    /// where to draw once the display is in graphics mode
+    framebuffer: Option<Framebuffer>,
+    row: usize,
+    column: usize,
+    /// whether the first line was cleared
+    started: bool,
+    /// inside an escape sequence
+    escape: bool,
+}
+
+impl Screen {
+    const fn new() -> Self {
+        Screen {
+            framebuffer: None,
+            row: 0,
+            column: 0,
+            started: false,
+            escape: false,
+        }
+    }
+
+    /// Columns and rows of text
+    fn size(&self) -> (usize, usize) {
+        match &self.framebuffer {
+            Some(framebuffer) => {
+                let font = font::default();
+                (
+                    framebuffer.width() / font.width(),
+                    framebuffer.height() / font.height(),
+                )
+            }
+            None => (TEXT_WIDTH, TEXT_HEIGHT),
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        if self.escape {
+            // CSI sequences end with a letter
+            self.escape = !c.is_ascii_alphabetic();
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            self.clear_line();
+        }
+        let (columns, _) = self.size();
+        match c {
+            '\x1b' => self.escape = true,
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            c => {
+                if self.column >= columns {
+                    self.new_line();
+                }
+                self.put(self.row, self.column, c);
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Moves to the next line and clears it, the output wraps to the top
+    fn new_line(&mut self) {
+        let (_, rows) = self.size();
+        self.column = 0;
+        self.row = (self.row + 1) % rows;
+        self.clear_line();
+    }
+
+    fn clear_line(&mut self) {
+        let (columns, _) = self.size();
+        for column in 0..columns {
+            self.put(self.row, column, ' ');
+        }
+    }
+
+    fn put(&mut self, row: usize, column: usize, c: char) {
+        match &mut self.framebuffer {
+            Some(framebuffer) => draw_char(framebuffer, row, column, c),
+            None => {
+                let color = (Color::Red as u16) << 4 | Color::White as u16;
+                let byte = cp437::from_char(c).unwrap_or(0xfe);
+                let cell = (TEXT_BUFFER as *mut u16).wrapping_add(row * TEXT_WIDTH + column);
+                unsafe { ptr::write_volatile(cell, color << 8 | u16::from(byte)) };
+            }
+        }
+    }
+}
+
+fn draw_char(framebuffer: &mut Framebuffer, row: usize, column: usize, c: char) {
+    let font = font::default();
+    let glyph = font.glyph(c).or_else(|| font.glyph('?'));
+    let (foreground, background) = (Rgb::from(Color::White), Rgb::from(Color::Red));
+    let (x, y) = (column * font.width(), row * font.height());
+    let bytes_per_row = (font.width() + 7) / 8;
+    for dy in 0..font.height() {
+        for dx in 0..font.width() {
+            let byte = glyph.and_then(|glyph| glyph.get(dy * bytes_per_row + dx / 8));
+            let set = byte.map_or(false, |byte| byte & (0x80 >> (dx % 8)) != 0);
+            let color = if set { foreground } else { background };
+            framebuffer.put_pixel(x + dx, y + dy, color);
+        }
+    }
+}
+
+/// Lets `ScreenWriter` draw into the framebuffer, called when the display switches to graphics
+pub(crate) fn set_framebuffer(framebuffer: Framebuffer) {
+    interrupts::without_interrupts(|| {
+        let mut screen = SCREEN.lock();
+        screen.framebuffer = Some(framebuffer);
+        screen.row = 0;
+        screen.column = 0;
+        screen.started = false;
+    });
+}
+
+/// Prints the panic message and a backtrace to the screen and COM1, the kernel log to COM1
+///
+/// Takes none of the locks printing normally takes, from now on all output
+/// bypasses them.
+pub fn report_panic(info: &PanicInfo) {
+    match PANICS.fetch_add(1, Ordering::SeqCst) {
+        0 => {
+            let backtrace = Backtrace::capture();
+            let _ = writeln!(ScreenWriter, "{}\n{}", info, backtrace);
+            let _ = writeln!(SerialWriter, "{}\n{}\nkernel log:", info, backtrace);
+            let _ = log::dump(&mut SerialWriter);
+        }
+        1 => {
+            let _ = writeln!(ScreenWriter, "panic during panic: {}", info);
+            let _ = writeln!(SerialWriter, "panic during panic: {}", info);
+        }
+        // formatting the message might be what panics
+        _ => {
+            let _ = SerialWriter.write_str("panic during panic\n");
+        }
+    }
+}
+
+#[test_case]
+fn test_nested_print_bypasses_locks() {
+    struct Nested;
+
+    impl fmt::Display for Nested {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            assert!(enter().is_none());
+            crate::println!("nested print");
+            crate::serial_println!("nested print");
+            // the handler logs the exception
+            x86_64::instructions::interrupts::int3();
+            f.write_str("outer print")
+        }
+    }
+
+    crate::println!("{}", Nested);
+    crate::serial_println!("{}", Nested);
+    assert!(enter().is_some());
+}
@@ -0,0 +1,221 @@
+pub mod bochs;
+pub mod compositor;
+pub mod console;
+pub mod font;
+pub mod panel;
+pub mod surface;
+
+use crate::vga_buffer::Color;
+use core::ptr;
+use surface::{Rect, Surface};
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+use x86_64::VirtAddr;
+
+#[derive(Debug)]
+pub enum FramebufferError {
+    /// there is no Bochs or QEMU display adapter
+    NoDevice,
+    /// only 24 and 32 bits per pixel are supported
+    UnsupportedDepth(u8),
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for FramebufferError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        FramebufferError::Map(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+}
+
+impl From<Color> for Rgb {
+    /// The colors the VGA text mode shows
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Black => Rgb::new(0x00, 0x00, 0x00),
+            Color::Blue => Rgb::new(0x00, 0x00, 0xaa),
+            Color::Green => Rgb::new(0x00, 0xaa, 0x00),
+            Color::Cyan => Rgb::new(0x00, 0xaa, 0xaa),
+            Color::Red => Rgb::new(0xaa, 0x00, 0x00),
+            Color::Magenta => Rgb::new(0xaa, 0x00, 0xaa),
+            Color::Brown => Rgb::new(0xaa, 0x55, 0x00),
+            Color::LightGray => Rgb::new(0xaa, 0xaa, 0xaa),
+            Color::DarkGray => Rgb::new(0x55, 0x55, 0x55),
+            Color::LightBlue => Rgb::new(0x55, 0x55, 0xff),
+            Color::LightGreen => Rgb::new(0x55, 0xff, 0x55),
+            Color::LightCyan => Rgb::new(0x55, 0xff, 0xff),
+            Color::LightRed => Rgb::new(0xff, 0x55, 0x55),
+            Color::Pink => Rgb::new(0xff, 0x55, 0xff),
+            Color::Yellow => Rgb::new(0xff, 0xff, 0x55),
+            Color::White => Rgb::new(0xff, 0xff, 0xff),
+        }
+    }
+}
+
+/// Pixels `Framebuffer::copy_from` converts at once
+const COPY_CHUNK: usize = 256;
+
+/// Linear framebuffer with 24 or 32 bits per pixel, blue in the lowest byte
+pub struct Framebuffer {
+    base: *mut u8,
+    width: usize,
+    height: usize,
+    /// bytes from one line to the next
+    pitch: usize,
+    bytes_per_pixel: usize,
+}
+
+// the memory belongs to the framebuffer alone
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// # Safety
+    ///
+    /// `base` has to point to `pitch * height` bytes of mapped pixel memory
+    /// that nothing else uses
+    pub unsafe fn new(
+        base: VirtAddr,
+        width: usize,
+        height: usize,
+        pitch: usize,
+        bits_per_pixel: u8,
+    ) -> Result<Self, FramebufferError> {
+        if !matches!(bits_per_pixel, 24 | 32) {
+            return Err(FramebufferError::UnsupportedDepth(bits_per_pixel));
+        }
+        Ok(Framebuffer {
+            base: base.as_mut_ptr(),
+            width,
+            height,
+            pitch,
+            bytes_per_pixel: usize::from(bits_per_pixel / 8),
+        })
+    }
+
+    /// A second handle on the same pixels, for `emergency::ScreenWriter`
+    ///
+    /// Safety: drawing through both at once mixes up the picture, the handle is
+    /// only for output that must show even if the owner's lock is held.
+    pub(crate) unsafe fn alias(&self) -> Framebuffer {
+        Framebuffer {
+            base: self.base,
+            width: self.width,
+            height: self.height,
+            pitch: self.pitch,
+            bytes_per_pixel: self.bytes_per_pixel,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u8 {
+        debug_assert!(x < self.width && y < self.height);
+        unsafe { self.base.add(y * self.pitch + x * self.bytes_per_pixel) }
+    }
+
+    /// Sets the pixel at `x`, `y`, pixels outside the screen are ignored
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let pixel = self.pixel_ptr(x, y);
+        unsafe {
+            if self.bytes_per_pixel == 4 {
+                let value = u32::from_le_bytes([color.b, color.g, color.r, 0]);
+                ptr::write_volatile(pixel as *mut u32, value);
+            } else {
+                ptr::write_volatile(pixel, color.b);
+                ptr::write_volatile(pixel.add(1), color.g);
+                ptr::write_volatile(pixel.add(2), color.r);
+            }
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let pixel = self.pixel_ptr(x, y);
+        let [b, g, r] = unsafe {
+            [
+                ptr::read_volatile(pixel),
+                ptr::read_volatile(pixel.add(1)),
+                ptr::read_volatile(pixel.add(2)),
+            ]
+        };
+        Some(Rgb::new(r, g, b))
+    }
+
+    /// Fills the part of the rectangle that is on the screen
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let right = x.saturating_add(width).min(self.width);
+        let bottom = y.saturating_add(height).min(self.height);
+        for y in y..bottom {
+            for x in x..right {
+                self.put_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Copies `rect` of `surface` to the same place on the screen, alpha is ignored
+    pub fn copy_from(&mut self, surface: &Surface, rect: Rect) {
+        let screen = Rect::new(0, 0, self.width as i32, self.height as i32);
+        let rect = rect.intersect(&screen).intersect(&surface.rect());
+        if rect.is_empty() {
+            return;
+        }
+        // each piece of a line is converted first and then copied at once
+        let mut buffer = [0u8; COPY_CHUNK * 4];
+        for y in rect.y..rect.bottom() {
+            let row = match surface.row(y) {
+                Some(row) => &row[rect.x as usize..rect.right() as usize],
+                None => continue,
+            };
+            let mut x = rect.x as usize;
+            for pixels in row.chunks(COPY_CHUNK) {
+                let bytes = &mut buffer[..pixels.len() * self.bytes_per_pixel];
+                for (pixel, out) in pixels
+                    .iter()
+                    .zip(bytes.chunks_exact_mut(self.bytes_per_pixel))
+                {
+                    out[..3].copy_from_slice(&[pixel.b, pixel.g, pixel.r]);
+                }
+                let target = self.pixel_ptr(x, y as usize);
+                unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), target, bytes.len()) };
+                x += pixels.len();
+            }
+        }
+    }
+
+    /// Moves the picture `lines` pixels up and fills the lines at the bottom with `fill`
+    pub fn scroll_up(&mut self, lines: usize, fill: Rgb) {
+        let lines = lines.min(self.height);
+        unsafe {
+            ptr::copy(
+                self.base.add(lines * self.pitch),
+                self.base,
+                (self.height - lines) * self.pitch,
+            );
+        }
+        self.fill_rect(0, self.height - lines, self.width, lines, fill);
+    }
+}
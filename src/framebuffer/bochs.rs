@@ -0,0 +1,106 @@
+use super::{Framebuffer, FramebufferError};
+use crate::{memory::mmio, pci};
+use spin::Mutex;
+use x86_64::{instructions::port::Port, PhysAddr, VirtAddr};
+
+// the display interface of the Bochs and QEMU standard VGA adapters
+const INDEX_PORT: u16 = 0x01ce;
+const DATA_PORT: u16 = 0x01cf;
+
+// registers
+const ID: u16 = 0;
+const X_RESOLUTION: u16 = 1;
+const Y_RESOLUTION: u16 = 2;
+const BITS_PER_PIXEL: u16 = 3;
+const ENABLE: u16 = 4;
+const VIRTUAL_WIDTH: u16 = 6;
+const X_OFFSET: u16 = 8;
+const Y_OFFSET: u16 = 9;
+
+// versions of the interface
+const FIRST_ID: u16 = 0xb0c0;
+const LAST_ID: u16 = 0xb0c5;
+
+// bits of the enable register
+const ENABLED: u16 = 1 << 0;
+const LINEAR_FRAMEBUFFER: u16 = 1 << 6;
+
+// the framebuffer is BAR 0 of the PCI device
+const VENDOR_ID: u16 = 0x1234;
+const DEVICE_ID: u16 = 0x1111;
+
+/// Where the framebuffer is mapped and how many bytes, later modes reuse it if it is big enough
+static MAPPING: Mutex<Option<(VirtAddr, usize)>> = Mutex::new(None);
+
+fn read(register: u16) -> u16 {
+    unsafe {
+        Port::<u16>::new(INDEX_PORT).write(register);
+        Port::<u16>::new(DATA_PORT).read()
+    }
+}
+
+fn write(register: u16, value: u16) {
+    unsafe {
+        Port::<u16>::new(INDEX_PORT).write(register);
+        Port::<u16>::new(DATA_PORT).write(value);
+    }
+}
+
+pub fn is_present() -> bool {
+    (FIRST_ID..=LAST_ID).contains(&read(ID))
+}
+
+/// Maps the first `size` bytes of the framebuffer at `phys`, mappings are never removed
+fn map(phys: PhysAddr, size: usize) -> Result<VirtAddr, FramebufferError> {
+    let mut mapping = MAPPING.lock();
+    match *mapping {
+        Some((base, mapped)) if mapped >= size => Ok(base),
+        _ => {
+            let base = mmio::map(phys, size as u64)?;
+            *mapping = Some((base, size));
+            Ok(base)
+        }
+    }
+}
+
+/// Switches to a graphics mode and maps its linear framebuffer
+///
+/// Text mode is gone afterwards, `vga_buffer` output isn't visible anymore.
+pub fn set_mode(
+    width: u16,
+    height: u16,
+    bits_per_pixel: u8,
+) -> Result<Framebuffer, FramebufferError> {
+    if !matches!(bits_per_pixel, 24 | 32) {
+        return Err(FramebufferError::UnsupportedDepth(bits_per_pixel));
+    }
+    if !is_present() {
+        return Err(FramebufferError::NoDevice);
+    }
+    let phys = pci::find(VENDOR_ID, DEVICE_ID)
+        .and_then(|device| device.memory_bar(0))
+        .ok_or(FramebufferError::NoDevice)?;
+
+    let pitch = usize::from(width) * usize::from(bits_per_pixel / 8);
+    let size = pitch * usize::from(height);
+    let base = map(phys, size)?;
+
+    write(ENABLE, 0);
+    write(X_RESOLUTION, width);
+    write(Y_RESOLUTION, height);
+    write(BITS_PER_PIXEL, u16::from(bits_per_pixel));
+    write(VIRTUAL_WIDTH, width);
+    write(X_OFFSET, 0);
+    write(Y_OFFSET, 0);
+    write(ENABLE, ENABLED | LINEAR_FRAMEBUFFER);
+
+    unsafe {
+        Framebuffer::new(
+            base,
+            usize::from(width),
+            usize::from(height),
+            pitch,
+            bits_per_pixel,
+        )
+    }
+}
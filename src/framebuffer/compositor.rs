@@ -0,0 +1,204 @@
+use super::surface::{Rect, Rgba, Surface};
+use super::Framebuffer;
+use crate::emergency;
+use crate::task::timer;
+use crate::time::Duration;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// More damaged rectangles than this are merged into one
+const MAX_DAMAGE: usize = 16;
+/// Time between two compositions by `run`
+const FRAME_INTERVAL: Duration = Duration::from_millis(20);
+
+static COMPOSITOR: Mutex<Option<Compositor>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowId(u64);
+
+struct Window {
+    id: WindowId,
+    x: i32,
+    y: i32,
+    surface: Surface,
+}
+
+impl Window {
+    fn rect(&self) -> Rect {
+        self.surface.rect().offset(self.x, self.y)
+    }
+}
+
+/// Stacks windows on the screen
+///
+/// Windows are drawn into a back buffer, only the finished picture of the
+/// damaged parts is copied to the framebuffer.
+pub struct Compositor {
+    framebuffer: Framebuffer,
+    back: Surface,
+    /// the bottom one first
+    windows: Vec<Window>,
+    next_id: u64,
+    /// parts of the screen that have to be composed again
+    damage: Vec<Rect>,
+    background: Rgba,
+}
+
+impl Compositor {
+    pub fn new(framebuffer: Framebuffer, background: Rgba) -> Self {
+        let back = Surface::new(framebuffer.width(), framebuffer.height(), background);
+        let mut compositor = Compositor {
+            framebuffer,
+            back,
+            windows: Vec::new(),
+            next_id: 0,
+            damage: Vec::new(),
+            background,
+        };
+        compositor.add_damage(compositor.back.rect());
+        compositor
+    }
+
+    pub fn screen(&self) -> Rect {
+        self.back.rect()
+    }
+
+    /// Returns a pixel of the picture composed last
+    pub fn screen_pixel(&self, x: i32, y: i32) -> Option<Rgba> {
+        self.back.pixel(x, y)
+    }
+
+    /// Creates a window on top of the others, filled with `fill`
+    pub fn create_window(&mut self, rect: Rect, fill: Rgba) -> WindowId {
+        let id = WindowId(self.next_id);
+        self.next_id += 1;
+        let surface = Surface::new(
+            rect.width.max(0) as usize,
+            rect.height.max(0) as usize,
+            fill,
+        );
+        self.windows.push(Window {
+            id,
+            x: rect.x,
+            y: rect.y,
+            surface,
+        });
+        self.add_damage(rect);
+        id
+    }
+
+    fn find(&self, id: WindowId) -> Option<usize> {
+        self.windows.iter().position(|window| window.id == id)
+    }
+
+    pub fn remove_window(&mut self, id: WindowId) {
+        if let Some(i) = self.find(id) {
+            let window = self.windows.remove(i);
+            self.add_damage(window.rect());
+        }
+    }
+
+    /// Returns the surface of the window, changes show after `damage_window`
+    pub fn window_mut(&mut self, id: WindowId) -> Option<&mut Surface> {
+        let i = self.find(id)?;
+        Some(&mut self.windows[i].surface)
+    }
+
+    /// Marks `rect` of the window as changed, in the coordinates of the window
+    pub fn damage_window(&mut self, id: WindowId, rect: Rect) {
+        if let Some(i) = self.find(id) {
+            let window = &self.windows[i];
+            let damage = rect
+                .intersect(&window.surface.rect())
+                .offset(window.x, window.y);
+            self.add_damage(damage);
+        }
+    }
+
+    pub fn move_window(&mut self, id: WindowId, x: i32, y: i32) {
+        if let Some(i) = self.find(id) {
+            let old = self.windows[i].rect();
+            self.windows[i].x = x;
+            self.windows[i].y = y;
+            let new = self.windows[i].rect();
+            self.add_damage(old);
+            self.add_damage(new);
+        }
+    }
+
+    /// Puts the window on top of the others
+    pub fn raise_window(&mut self, id: WindowId) {
+        if let Some(i) = self.find(id) {
+            let window = self.windows.remove(i);
+            self.add_damage(window.rect());
+            self.windows.push(window);
+        }
+    }
+
+    fn add_damage(&mut self, rect: Rect) {
+        let rect = rect.intersect(&self.screen());
+        if rect.is_empty() {
+            return;
+        }
+        if self.damage.len() == MAX_DAMAGE {
+            let all = self
+                .damage
+                .drain(..)
+                .fold(rect, |all, rect| all.union(&rect));
+            self.damage.push(all);
+        } else {
+            self.damage.push(rect);
+        }
+    }
+
+    /// Draws the damaged parts of the screen again
+    pub fn compose(&mut self) {
+        for area in core::mem::take(&mut self.damage) {
+            self.back.fill_rect(area, self.background);
+            for window in &self.windows {
+                self.back
+                    .blit_clipped(&window.surface, window.x, window.y, area);
+            }
+            self.framebuffer.copy_from(&self.back, area);
+        }
+    }
+}
+
+/// Makes the compositor the owner of the screen
+pub fn init(framebuffer: Framebuffer, background: Rgba) {
+    let compositor = Compositor::new(framebuffer, background);
+    interrupts::without_interrupts(|| *COMPOSITOR.lock() = Some(compositor));
+}
+
+/// Runs `f` with the compositor, `None` if there is none
+///
+/// Also `None` while the cpu is printing, see `emergency::enter`. Must not
+/// print, `print!` draws through the compositor too.
+pub fn with<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Compositor) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let _guard = emergency::enter()?;
+        COMPOSITOR.lock().as_mut().map(f)
+    })
+}
+
+/// Like `with`, for the console, which `vga_buffer` calls with a `PrintGuard` held
+pub(super) fn with_printing<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut Compositor) -> R,
+{
+    interrupts::without_interrupts(|| COMPOSITOR.lock().as_mut().map(f))
+}
+
+/// Shows the damage collected since the last frame, every `FRAME_INTERVAL`
+///
+/// Drawing into windows only adds damage, so many changes in between cost one
+/// composition. Ends at once if there is no compositor.
+pub async fn run() {
+    while with(|compositor| compositor.compose()).is_some() {
+        timer::sleep(FRAME_INTERVAL).await;
+    }
+}
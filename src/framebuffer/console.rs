@@ -0,0 +1,430 @@
+use super::compositor::{self, WindowId};
+use super::font::{self, Font};
+use super::surface::{Rect, Rgba, Surface};
+use super::{bochs, FramebufferError, Rgb};
+use crate::emergency;
+use crate::vga_buffer::ansi::{Action, Csi, Parser};
+use crate::vga_buffer::{self, Attributes, CONSOLE_COUNT, SCROLLBACK_LINES};
+use alloc::{collections::VecDeque, vec, vec::Vec};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+const TAB_WIDTH: usize = 8;
+/// Shown for characters the font doesn't have
+const REPLACEMENT: char = '■';
+
+static CONSOLES: Mutex<Option<Consoles>> = Mutex::new(None);
+
+/// A text console for every virtual console of `vga_buffer`, the active one is drawn
+struct Consoles {
+    consoles: Vec<FramebufferConsole>,
+    active: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    foreground: Rgb,
+    background: Rgb,
+}
+
+/// Text console drawn with a bitmap font into a window of the compositor
+///
+/// Understands the same control characters and escape sequences as
+/// `vga_buffer::Writer` and keeps a scrollback history like it. Changes are
+/// collected and drawn at the end of every write, if the console is visible.
+pub struct FramebufferConsole {
+    window: WindowId,
+    font: &'static Font,
+    columns: usize,
+    rows: usize,
+    cells: Vec<Cell>,
+    /// cells that changed since they were drawn
+    dirty: Vec<bool>,
+    /// lines the text moved up since it was drawn
+    scrolled: usize,
+    column: usize,
+    row: usize,
+    /// cursor position stored by `ESC [ s`
+    saved_position: (usize, usize),
+    attributes: Attributes,
+    parser: Parser,
+    cursor_visible: bool,
+    /// lines that scrolled off the top, the newest last
+    scrollback: VecDeque<Vec<Cell>>,
+    /// lines the view is scrolled back into the history
+    scroll_offset: usize,
+    /// whether this is the virtual console on the screen
+    visible: bool,
+}
+
+impl FramebufferConsole {
+    /// Creates a console for a window of `width` x `height` pixels, filled with its background
+    fn new(window: WindowId, width: usize, height: usize) -> Self {
+        let font = font::default();
+        let columns = width / font.width();
+        let rows = height / font.height();
+        let mut console = FramebufferConsole {
+            window,
+            font,
+            columns,
+            rows,
+            cells: Vec::new(),
+            dirty: vec![false; columns * rows],
+            scrolled: 0,
+            column: 0,
+            row: 0,
+            saved_position: (0, 0),
+            attributes: Attributes::DEFAULT,
+            parser: Parser::new(),
+            cursor_visible: true,
+            scrollback: VecDeque::new(),
+            scroll_offset: 0,
+            visible: false,
+        };
+        console.cells = vec![console.blank(); columns * rows];
+        console
+    }
+
+    /// Columns and rows of text
+    pub fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        // new output is always shown
+        if self.scroll_offset != 0 {
+            self.scroll_offset = 0;
+            self.mark_all_dirty();
+        }
+        // the cell under the cursor is drawn inverted
+        self.mark_dirty(self.row, self.column.min(self.columns - 1));
+        for c in s.chars() {
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => self.write_char(c),
+                Some(Action::Csi(csi)) => self.control_sequence(&csi),
+                None => {}
+            }
+        }
+        self.mark_dirty(self.row, self.column.min(self.columns - 1));
+        self.flush();
+    }
+
+    fn blank(&self) -> Cell {
+        let (foreground, background) = self.attributes.colors();
+        Cell {
+            c: ' ',
+            foreground: foreground.into(),
+            background: background.into(),
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            // moves back without erasing, like a terminal
+            '\x08' => self.column = self.column.min(self.columns - 1).saturating_sub(1),
+            '\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column = next.min(self.columns - 1);
+            }
+            '\0'..='\x1f' | '\x7f' => self.put_char(REPLACEMENT),
+            c => self.put_char(c),
+        }
+    }
+
+    fn put_char(&mut self, c: char) {
+        if self.column >= self.columns {
+            self.new_line();
+        }
+        let cell = Cell { c, ..self.blank() };
+        self.put(self.row, self.column, cell);
+        self.column += 1;
+    }
+
+    fn put(&mut self, row: usize, col: usize, cell: Cell) {
+        self.cells[row * self.columns + col] = cell;
+        self.mark_dirty(row, col);
+    }
+
+    fn mark_dirty(&mut self, row: usize, col: usize) {
+        self.dirty[row * self.columns + col] = true;
+    }
+
+    /// Redraws everything, without moving the pixels first
+    fn mark_all_dirty(&mut self) {
+        self.dirty.fill(true);
+        self.scrolled = 0;
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row < self.rows - 1 {
+            self.row += 1;
+            return;
+        }
+        self.push_scrollback();
+        // the pixels move with the cells when they are drawn
+        self.cells.copy_within(self.columns.., 0);
+        self.dirty.copy_within(self.columns.., 0);
+        self.scrolled += 1;
+        self.clear_cells(self.rows - 1, 0..self.columns);
+    }
+
+    /// Keeps the top line before it scrolls off, without the heap it is lost
+    fn push_scrollback(&mut self) {
+        let mut line = match self.scrollback.len() {
+            // the oldest line makes room and lends its allocation
+            SCROLLBACK_LINES => self.scrollback.pop_front().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        line.clear();
+        let reserved = line.try_reserve_exact(self.columns).is_ok();
+        if !reserved || self.scrollback.try_reserve(1).is_err() {
+            return;
+        }
+        line.extend_from_slice(&self.cells[..self.columns]);
+        self.scrollback.push_back(line);
+    }
+
+    /// Moves the view `lines` back into the history, or forward for negative values
+    fn scroll(&mut self, lines: isize) {
+        let offset = self.scroll_offset.saturating_add_signed(lines);
+        let offset = offset.min(self.scrollback.len());
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.mark_all_dirty();
+            self.flush();
+        }
+    }
+
+    /// Makes this the console on the screen and draws all of it
+    fn show(&mut self) {
+        self.visible = true;
+        self.mark_all_dirty();
+        self.flush();
+    }
+
+    /// The cell at `col` of `line`, counted from the oldest line of the history
+    fn view_cell(&self, line: usize, col: usize) -> Cell {
+        match self.scrollback.get(line) {
+            Some(cells) => cells[col],
+            None => self.cells[(line - self.scrollback.len()) * self.columns + col],
+        }
+    }
+
+    /// Draws the changes into the window, `compositor::run` shows them
+    fn flush(&mut self) {
+        if !self.visible {
+            return;
+        }
+        compositor::with_printing(|compositor| {
+            let surface = match compositor.window_mut(self.window) {
+                Some(surface) => surface,
+                None => return,
+            };
+            let damage = self.draw(surface);
+            compositor.damage_window(self.window, damage);
+        });
+    }
+
+    /// Returns the part of the surface that changed
+    fn draw(&mut self, surface: &mut Surface) -> Rect {
+        let (width, height) = (self.font.width(), self.font.height());
+        let mut damage = Rect::new(0, 0, 0, 0);
+        if self.scrolled > 0 {
+            let lines = self.scrolled.min(self.rows) * height;
+            surface.scroll_up(lines, self.blank().background.into());
+            damage = surface.rect();
+            self.scrolled = 0;
+        }
+
+        // the cursor is only shown below the history
+        let cursor = (self.row, self.column.min(self.columns - 1));
+        let show_cursor = self.cursor_visible && self.scroll_offset == 0;
+        let top = self.scrollback.len() - self.scroll_offset;
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                let i = row * self.columns + col;
+                if !self.dirty[i] {
+                    continue;
+                }
+                self.dirty[i] = false;
+                let inverted = show_cursor && (row, col) == cursor;
+                let (x, y) = (col * width, row * height);
+                let cell = self.view_cell(top + row, col);
+                draw_cell(surface, self.font, cell, inverted, x, y);
+                let rect = Rect::new(x as i32, y as i32, width as i32, height as i32);
+                damage = damage.union(&rect);
+            }
+        }
+        damage
+    }
+
+    fn clear_cells(&mut self, row: usize, cols: core::ops::Range<usize>) {
+        let blank = self.blank();
+        for col in cols {
+            self.put(row, col, blank);
+        }
+    }
+
+    fn control_sequence(&mut self, csi: &Csi) {
+        // a cursor behind the last column waits for the next character to wrap
+        let col = self.column.min(self.columns - 1);
+        let row = self.row;
+        let count = usize::from(csi.param(0, 1));
+
+        match (csi.private, csi.action) {
+            (false, 'A') => self.row = row.saturating_sub(count),
+            (false, 'B') => self.row = (row + count).min(self.rows - 1),
+            (false, 'C') => self.column = (col + count).min(self.columns - 1),
+            (false, 'D') => self.column = col.saturating_sub(count),
+            (false, 'E') => self.move_to(row + count, 0),
+            (false, 'F') => self.move_to(row.saturating_sub(count), 0),
+            (false, 'G') => self.move_to(row, count - 1),
+            (false, 'H' | 'f') => self.move_to(count - 1, usize::from(csi.param(1, 1)) - 1),
+            (false, 'J') => self.erase_display(csi.param(0, 0), row, col),
+            (false, 'K') => self.erase_line(csi.param(0, 0), row, col),
+            (false, 'm') => self.attributes.select_graphic_rendition(csi.params()),
+            (false, 's') => self.saved_position = (row, col),
+            (false, 'u') => (self.row, self.column) = self.saved_position,
+            (true, 'h' | 'l') if csi.param(0, 0) == 25 => self.cursor_visible = csi.action == 'h',
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = col.min(self.columns - 1);
+    }
+
+    /// 0 erases from the cursor to the end, 1 from the start to the cursor, 2 all of it
+    fn erase_display(&mut self, mode: u16, row: usize, col: usize) {
+        match mode {
+            0 => {
+                self.clear_cells(row, col..self.columns);
+                (row + 1..self.rows).for_each(|row| self.clear_cells(row, 0..self.columns));
+            }
+            1 => {
+                (0..row).for_each(|row| self.clear_cells(row, 0..self.columns));
+                self.clear_cells(row, 0..col + 1);
+            }
+            2 => (0..self.rows).for_each(|row| self.clear_cells(row, 0..self.columns)),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16, row: usize, col: usize) {
+        match mode {
+            0 => self.clear_cells(row, col..self.columns),
+            1 => self.clear_cells(row, 0..col + 1),
+            2 => self.clear_cells(row, 0..self.columns),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Switches the display to graphics, the virtual consoles show up there from now on
+///
+/// Returns the columns and rows of text. Needs the heap and the global page table.
+/// What the consoles showed in text mode isn't carried over.
+pub fn init(
+    width: u16,
+    height: u16,
+    bits_per_pixel: u8,
+) -> Result<(usize, usize), FramebufferError> {
+    let framebuffer = bochs::set_mode(width, height, bits_per_pixel)?;
+    let screen = Rect::new(
+        0,
+        0,
+        framebuffer.width() as i32,
+        framebuffer.height() as i32,
+    );
+    let background = Rgba::from(Rgb::from(Attributes::DEFAULT.colors().1));
+    emergency::set_framebuffer(unsafe { framebuffer.alias() });
+    compositor::init(framebuffer, background);
+    let window = compositor::with(|compositor| compositor.create_window(screen, background))
+        .expect("compositor not initialized");
+
+    let (width, height) = (screen.width as usize, screen.height as usize);
+    let mut consoles: Vec<_> = (0..CONSOLE_COUNT)
+        .map(|_| FramebufferConsole::new(window, width, height))
+        .collect();
+    let size = consoles[0].size();
+    interrupts::without_interrupts(|| {
+        vga_buffer::release_text_buffer();
+        let active = vga_buffer::active_console();
+        consoles[active].visible = true;
+        *CONSOLES.lock() = Some(Consoles { consoles, active });
+    });
+    compositor::with(|compositor| compositor.compose());
+    Ok(size)
+}
+
+pub fn is_active() -> bool {
+    interrupts::without_interrupts(|| CONSOLES.lock().is_some())
+}
+
+/// Called by `vga_buffer` for the output of virtual console `console`
+///
+/// Returns `false` in text mode, where `vga_buffer` shows the output itself.
+pub(crate) fn print_to(console: usize, args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| match CONSOLES.lock().as_mut() {
+        Some(consoles) => {
+            consoles.consoles[console].write_fmt(args).unwrap();
+            true
+        }
+        None => false,
+    })
+}
+
+/// Called by `vga_buffer` to show virtual console `console`
+pub(crate) fn switch(console: usize) {
+    interrupts::without_interrupts(|| {
+        if let Some(consoles) = CONSOLES.lock().as_mut() {
+            if console != consoles.active {
+                consoles.consoles[consoles.active].visible = false;
+                consoles.active = console;
+                consoles.consoles[console].show();
+            }
+        }
+    });
+}
+
+/// Called by `vga_buffer` to scroll the active console, returns `false` in text mode
+pub(crate) fn scroll(lines: isize) -> bool {
+    interrupts::without_interrupts(|| match CONSOLES.lock().as_mut() {
+        Some(consoles) => {
+            let active = consoles.active;
+            consoles.consoles[active].scroll(lines);
+            true
+        }
+        None => false,
+    })
+}
+
+fn draw_cell(surface: &mut Surface, font: &Font, cell: Cell, inverted: bool, x: usize, y: usize) {
+    let (foreground, background) = if inverted {
+        (cell.background, cell.foreground)
+    } else {
+        (cell.foreground, cell.background)
+    };
+    let (x, y) = (x as i32, y as i32);
+    let rect = Rect::new(x, y, font.width() as i32, font.height() as i32);
+    surface.fill_rect(rect, background.into());
+    let c = match font.glyph(cell.c) {
+        Some(_) => cell.c,
+        None => REPLACEMENT,
+    };
+    surface.draw_char(font, x, y, c, foreground.into());
+}
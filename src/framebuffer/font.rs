@@ -0,0 +1,138 @@
+use alloc::collections::BTreeMap;
+use lazy_static::lazy_static;
+
+/// 8x16 glyphs of code page 437, rendered from DejaVu Sans Mono (see font.LICENSE)
+pub static DEFAULT_FONT: &[u8] = include_bytes!("font.psf");
+
+lazy_static! {
+    static ref DEFAULT: Font = Font::parse(DEFAULT_FONT).expect("default font invalid");
+}
+
+/// The parsed `DEFAULT_FONT`
+pub fn default() -> &'static Font {
+    &DEFAULT
+}
+
+const PSF2_MAGIC: u32 = 0x864a_b572;
+const PSF2_HEADER_SIZE: usize = 32;
+const HAS_UNICODE_TABLE: u32 = 1 << 0;
+
+// markers in the unicode table
+const SEQUENCE_START: u8 = 0xfe;
+const SEPARATOR: u8 = 0xff;
+
+#[derive(Debug)]
+pub enum FontError {
+    /// not a PSF2 font
+    BadMagic,
+    Truncated,
+    InvalidUnicodeTable,
+}
+
+/// A bitmap font in the PSF2 format of the Linux console
+pub struct Font {
+    width: usize,
+    height: usize,
+    bytes_per_glyph: usize,
+    glyph_count: usize,
+    glyphs: &'static [u8],
+    /// glyph of each character, without a table the glyphs are in Unicode order
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, FontError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(FontError::Truncated)
+}
+
+impl Font {
+    pub fn parse(data: &'static [u8]) -> Result<Font, FontError> {
+        if read_u32(data, 0)? != PSF2_MAGIC {
+            return Err(FontError::BadMagic);
+        }
+        let header = |field: usize| read_u32(data, 4 * field).map(|value| value as usize);
+        let (header_size, glyph_count) = (header(2)?, header(4)?);
+        let flags = read_u32(data, 12)?;
+        let (bytes_per_glyph, height, width) = (header(5)?, header(6)?, header(7)?);
+        if bytes_per_glyph < height * ((width + 7) / 8) || header_size < PSF2_HEADER_SIZE {
+            return Err(FontError::Truncated);
+        }
+
+        let glyphs_end = header_size + glyph_count * bytes_per_glyph;
+        let glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(FontError::Truncated)?;
+        let unicode = if flags & HAS_UNICODE_TABLE != 0 {
+            Some(parse_unicode_table(&data[glyphs_end..], glyph_count)?)
+        } else {
+            None
+        };
+
+        Ok(Font {
+            width,
+            height,
+            bytes_per_glyph,
+            glyph_count,
+            glyphs,
+            unicode,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the rows of the glyph of `c`, the top one first
+    ///
+    /// Every row takes `(width + 7) / 8` bytes, the highest bit is the leftmost pixel.
+    pub fn glyph(&self, c: char) -> Option<&'static [u8]> {
+        let index = match &self.unicode {
+            Some(table) => *table.get(&c)?,
+            None => c as usize,
+        };
+        if index >= self.glyph_count {
+            return None;
+        }
+        let start = index * self.bytes_per_glyph;
+        Some(&self.glyphs[start..start + self.bytes_per_glyph])
+    }
+}
+
+/// Maps the characters to their glyphs, sequences of combining characters are skipped
+fn parse_unicode_table(
+    mut table: &[u8],
+    glyph_count: usize,
+) -> Result<BTreeMap<char, usize>, FontError> {
+    let mut map = BTreeMap::new();
+    for glyph in 0..glyph_count {
+        let end = table
+            .iter()
+            .position(|&byte| byte == SEPARATOR)
+            .ok_or(FontError::InvalidUnicodeTable)?;
+        let entry = &table[..end];
+        let single = match entry.iter().position(|&byte| byte == SEQUENCE_START) {
+            Some(start) => &entry[..start],
+            None => entry,
+        };
+        let chars = core::str::from_utf8(single).map_err(|_| FontError::InvalidUnicodeTable)?;
+        for c in chars.chars() {
+            map.entry(c).or_insert(glyph);
+        }
+        table = &table[end + 1..];
+    }
+    Ok(map)
+}
+
+#[test_case]
+fn test_default_font() {
+    let font = Font::parse(DEFAULT_FONT).expect("default font invalid");
+    assert_eq!((font.width(), font.height()), (8, 16));
+    assert!(font.glyph('A').unwrap().iter().any(|&row| row != 0));
+    assert_eq!(font.glyph('μ'), font.glyph('µ'));
+    assert!(font.glyph('€').is_none());
+}
@@ -0,0 +1,85 @@
+use super::compositor::{self, WindowId};
+use super::font::{self, Font};
+use super::surface::{Rect, Rgba, Surface};
+use crate::task::timer;
+use crate::time::{Duration, Instant};
+use crate::{allocator, cpu, task, thread};
+use alloc::{format, string::String, vec::Vec};
+
+/// Characters in the longest line
+const COLUMNS: i32 = 30;
+const LINES: i32 = 5;
+const PADDING: i32 = 6;
+/// Space between the panel and the edges of the screen
+const MARGIN: i32 = 8;
+
+const BACKGROUND: Rgba = Rgba::new(0x10, 0x20, 0x40, 0xc0);
+const BORDER: Rgba = Rgba::new(0x55, 0x55, 0xff, 0xff);
+const TITLE: Rgba = Rgba::new(0xff, 0xff, 0x55, 0xff);
+const TEXT: Rgba = Rgba::new(0xff, 0xff, 0xff, 0xff);
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn status_lines() -> Vec<String> {
+    let heap = allocator::stats();
+    let uptime = Instant::now().since_boot().as_secs();
+    Vec::from([
+        format!("cpus    {}", cpu::count()),
+        format!("heap    {} / {} KiB", heap.used / 1024, heap.size / 1024),
+        format!(
+            "tasks   {} async, {} threads",
+            task::count(),
+            thread::list().len()
+        ),
+        format!(
+            "uptime  {:02}:{:02}:{:02}",
+            uptime / 3600,
+            uptime / 60 % 60,
+            uptime % 60
+        ),
+    ])
+}
+
+fn draw(surface: &mut Surface, font: &Font, lines: &[String]) {
+    let rect = surface.rect();
+    surface.fill_rect(rect, BACKGROUND);
+    surface.draw_rect(rect, BORDER);
+
+    let line_height = font.height() as i32;
+    surface.draw_text(font, PADDING, PADDING, "status", TITLE);
+    for (i, line) in lines.iter().enumerate() {
+        let y = PADDING + (i as i32 + 1) * line_height;
+        surface.draw_text(font, PADDING, y, line, TEXT);
+    }
+}
+
+/// Shows CPU, heap and task numbers in a window at the top right corner
+///
+/// Ends at once if there is no compositor, the display is in text mode then.
+pub async fn run() {
+    let font = font::default();
+    let width = COLUMNS * font.width() as i32 + 2 * PADDING;
+    let height = LINES * font.height() as i32 + 2 * PADDING;
+    let window: Option<WindowId> = compositor::with(|compositor| {
+        let screen = compositor.screen();
+        let rect = Rect::new(screen.right() - width - MARGIN, MARGIN, width, height);
+        compositor.create_window(rect, Rgba::TRANSPARENT)
+    });
+    let window = match window {
+        Some(window) => window,
+        None => return,
+    };
+
+    loop {
+        // collected before locking the compositor, the heap has its own lock
+        let lines = status_lines();
+        compositor::with(|compositor| {
+            if let Some(surface) = compositor.window_mut(window) {
+                draw(surface, font, &lines);
+                let rect = surface.rect();
+                compositor.damage_window(window, rect);
+            }
+        });
+        timer::sleep(UPDATE_INTERVAL).await;
+    }
+}
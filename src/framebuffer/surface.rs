@@ -0,0 +1,300 @@
+use super::font::Font;
+use super::Rgb;
+use alloc::{vec, vec::Vec};
+
+/// A color that can be partly transparent, `a` 255 is opaque
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub const TRANSPARENT: Rgba = Rgba::new(0, 0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Rgba { r, g, b, a }
+    }
+
+    pub const fn rgb(self) -> Rgb {
+        Rgb::new(self.r, self.g, self.b)
+    }
+
+    /// Draws `self` over `below`, both with straight alpha
+    pub fn over(self, below: Rgba) -> Rgba {
+        match (self.a, below.a) {
+            (255, _) | (_, 0) => return self,
+            (0, _) => return below,
+            _ => {}
+        }
+        let (alpha, below_alpha) = (u32::from(self.a), u32::from(below.a));
+        // alpha of `below` that shows through, scaled by 255
+        let through = below_alpha * (255 - alpha) / 255;
+        let a = alpha + through;
+        let mix = |top: u8, bottom: u8| {
+            ((u32::from(top) * alpha + u32::from(bottom) * through) / a) as u8
+        };
+        Rgba::new(
+            mix(self.r, below.r),
+            mix(self.g, below.g),
+            mix(self.b, below.b),
+            a as u8,
+        )
+    }
+}
+
+impl From<Rgb> for Rgba {
+    fn from(color: Rgb) -> Self {
+        Rgba::new(color.r, color.g, color.b, 255)
+    }
+}
+
+/// A rectangle of pixels, it may reach past the edges of a surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+
+    /// The part both cover, empty if they don't overlap
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, (right - x).max(0), (bottom - y).max(0))
+    }
+
+    /// The smallest rectangle that covers both
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+/// Pixels in memory to draw into, drawing outside of it is clipped
+pub struct Surface {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+}
+
+impl Surface {
+    pub fn new(width: usize, height: usize, fill: Rgba) -> Self {
+        Surface {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.width as i32, self.height as i32)
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let (x, y) = (usize::try_from(x).ok()?, usize::try_from(y).ok()?);
+        (x < self.width && y < self.height).then(|| y * self.width + x)
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Option<Rgba> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Replaces the pixel, transparency included
+    /// Returns the pixels of line `y`
+    pub fn row(&self, y: i32) -> Option<&[Rgba]> {
+        let start = self.index(0, y)?;
+        Some(&self.pixels[start..start + self.width])
+    }
+
+    pub fn put_pixel(&mut self, x: i32, y: i32, color: Rgba) {
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i] = color;
+        }
+    }
+
+    /// Draws `color` over the pixel
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Rgba) {
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i] = color.over(self.pixels[i]);
+        }
+    }
+
+    /// Replaces the pixels of `rect`
+    pub fn fill_rect(&mut self, rect: Rect, color: Rgba) {
+        let rect = rect.intersect(&self.rect());
+        for y in rect.y..rect.bottom() {
+            let start = y as usize * self.width;
+            self.pixels[start + rect.x as usize..start + rect.right() as usize].fill(color);
+        }
+    }
+
+    /// Draws the outline of `rect`
+    pub fn draw_rect(&mut self, rect: Rect, color: Rgba) {
+        if rect.is_empty() {
+            return;
+        }
+        let (right, bottom) = (rect.right() - 1, rect.bottom() - 1);
+        self.draw_line(rect.x, rect.y, right, rect.y, color);
+        self.draw_line(rect.x, bottom, right, bottom, color);
+        // the corners are drawn by the horizontal lines already
+        if rect.height > 2 {
+            self.draw_line(rect.x, rect.y + 1, rect.x, bottom - 1, color);
+            self.draw_line(right, rect.y + 1, right, bottom - 1, color);
+        }
+    }
+
+    /// Draws a line including both ends with Bresenham's algorithm
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Rgba) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.blend_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws `source` over this surface with its top left corner at `x`, `y`
+    pub fn blit(&mut self, source: &Surface, x: i32, y: i32) {
+        self.blit_clipped(source, x, y, self.rect());
+    }
+
+    /// Like `blit`, but only changes the pixels inside `clip`
+    pub fn blit_clipped(&mut self, source: &Surface, x: i32, y: i32, clip: Rect) {
+        let area = source
+            .rect()
+            .offset(x, y)
+            .intersect(&clip)
+            .intersect(&self.rect());
+        for row in area.y..area.bottom() {
+            let target = row as usize * self.width;
+            let from = (row - y) as usize * source.width;
+            for col in area.x..area.right() {
+                let color = source.pixels[from + (col - x) as usize];
+                let pixel = &mut self.pixels[target + col as usize];
+                *pixel = color.over(*pixel);
+            }
+        }
+    }
+
+    /// Moves everything `lines` pixels up and fills the lines at the bottom with `fill`
+    pub fn scroll_up(&mut self, lines: usize, fill: Rgba) {
+        let lines = lines.min(self.height);
+        self.pixels.copy_within(lines * self.width.., 0);
+        let bottom = (self.height - lines) as i32;
+        self.fill_rect(Rect::new(0, bottom, self.width as i32, lines as i32), fill);
+    }
+
+    /// Draws the set pixels of the glyph of `c` with its top left corner at `x`, `y`
+    pub fn draw_char(&mut self, font: &Font, x: i32, y: i32, c: char, color: Rgba) {
+        let glyph = match font.glyph(c) {
+            Some(glyph) => glyph,
+            None => return,
+        };
+        let bytes_per_row = (font.width() + 7) / 8;
+        for row in 0..font.height() {
+            for col in 0..font.width() {
+                if glyph[row * bytes_per_row + col / 8] & (0x80 >> (col % 8)) != 0 {
+                    self.blend_pixel(x + col as i32, y + row as i32, color);
+                }
+            }
+        }
+    }
+
+    /// Draws `text` in one line, returns where the next character would go
+    pub fn draw_text(&mut self, font: &Font, x: i32, y: i32, text: &str, color: Rgba) -> i32 {
+        let mut x = x;
+        for c in text.chars() {
+            self.draw_char(font, x, y, c, color);
+            x += font.width() as i32;
+        }
+        x
+    }
+}
+
+#[test_case]
+fn test_blending() {
+    let white = Rgba::new(255, 255, 255, 255);
+    let half_black = Rgba::new(0, 0, 0, 128);
+    assert_eq!(half_black.over(white), Rgba::new(127, 127, 127, 255));
+    assert_eq!(white.over(half_black), white);
+    assert_eq!(Rgba::TRANSPARENT.over(white), white);
+}
+
+#[test_case]
+fn test_drawing_is_clipped() {
+    let red = Rgba::new(255, 0, 0, 255);
+    let mut surface = Surface::new(4, 4, Rgba::TRANSPARENT);
+    surface.fill_rect(Rect::new(-2, 2, 4, 10), red);
+    assert_eq!(surface.pixel(1, 3), Some(red));
+    assert_eq!(surface.pixel(2, 2), Some(Rgba::TRANSPARENT));
+
+    surface.draw_line(-1, -1, 4, 4, red);
+    assert_eq!(surface.pixel(0, 0), Some(red));
+    assert_eq!(surface.pixel(3, 3), Some(red));
+
+    let mut target = Surface::new(4, 4, Rgba::TRANSPARENT);
+    target.blit(&surface, 2, -2);
+    assert_eq!(target.pixel(1, 1), Some(Rgba::TRANSPARENT));
+    assert_eq!(target.pixel(2, 2), Some(Rgba::TRANSPARENT));
+    assert_eq!(target.pixel(3, 1), Some(red));
+}
@@ -1,43 +1,107 @@
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Page faults run on a separate stack, so a stack overflow can be reported
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 
 pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+    load(&GDT.0, &GDT.1);
+}
+
+/// Loads a GDT with its own TSS on an application processor
+///
+/// Every cpu needs its own TSS, the IST stacks are per cpu. The selectors are
+/// the same as on the bootstrap processor, so `selectors` stays valid. The heap
+/// has to be initialized, the tables are never freed. Application processors
+/// never run user code, so there is no stack for privilege changes.
+pub fn init_ap(double_fault_stack: VirtAddr, page_fault_stack: VirtAddr) {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack;
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = page_fault_stack;
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let gdt: &'static (GlobalDescriptorTable, Selectors) = Box::leak(Box::new(new_gdt(tss)));
+    load(&gdt.0, &gdt.1);
+}
+
+fn load(gdt: &'static GlobalDescriptorTable, selectors: &Selectors) {
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load();
+    gdt.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(selectors.code_selector);
+        SS::set_reg(selectors.data_selector);
+        DS::set_reg(selectors.data_selector);
+        ES::set_reg(selectors.data_selector);
+        load_tss(selectors.tss_selector);
     }
 }
 
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    // order matters: SYSCALL/SYSRET expect kernel data after kernel code
+    // and user code after user data (see `syscall::init`)
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+    let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            data_selector,
+            user_code_selector,
+            user_data_selector,
+            tss_selector,
+        },
+    )
+}
+
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(TSS.get());
+}
+
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// The TSS of the bootstrap processor
+///
+/// The stack pointers in it change on every context switch, so it lives in an
+/// `UnsafeCell`. Only the bootstrap processor touches it, with interrupts
+/// disabled or from the scheduler.
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+unsafe impl Sync for Tss {}
+
+impl Tss {
+    /// The GDT descriptor only needs the address, the cpu reads the rest
+    fn get(&self) -> &'static TaskStateSegment {
+        unsafe { &*(self.0.get() as *const TaskStateSegment) }
+    }
+
+    fn as_ptr(&self) -> *mut TaskStateSegment {
+        self.0.get()
+    }
 }
 
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: Tss = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
             const STACK_SIZE: usize = 4096 * 5;
@@ -48,6 +112,51 @@ lazy_static! {
             let stack_end = stack_start + STACK_SIZE;
             stack_end
         };
-        tss
+        // used until the scheduler gives every thread its own page fault stack
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            const STACK_SIZE: usize = 4096 * 2;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            let stack_end = stack_start + STACK_SIZE;
+            stack_end.align_down(16u64)
+        };
+        // used when an interrupt arrives in ring 3, replaced on every context switch
+        tss.privilege_stack_table[0] = {
+            const STACK_SIZE: usize = 4096 * 5;
+            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+            let stack_end = stack_start + STACK_SIZE;
+            // syscall_entry relies on an aligned stack
+            stack_end.align_down(16u64)
+        };
+        Tss(UnsafeCell::new(tss))
     };
 }
+
+/// Returns the stack the cpu switches to when leaving ring 3
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { (*TSS.as_ptr()).privilege_stack_table[0] }
+}
+
+/// Sets the stack the cpu switches to on interrupts and syscalls from ring 3
+pub fn set_kernel_stack(stack_top: VirtAddr) {
+    // the cpu only reads the TSS while entering the kernel from ring 3,
+    // which cannot happen while we are running in the kernel
+    unsafe { (*TSS.as_ptr()).privilege_stack_table[0] = stack_top };
+    crate::syscall::set_kernel_stack(stack_top);
+}
+
+/// Returns the stack the cpu switches to on page faults
+pub fn page_fault_stack() -> VirtAddr {
+    unsafe { (*TSS.as_ptr()).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] }
+}
+
+/// Sets the stack the cpu switches to on page faults
+///
+/// The page fault handler can be preempted, so every thread needs its own.
+pub fn set_page_fault_stack(stack_top: VirtAddr) {
+    // see `set_kernel_stack`, the cpu only reads it when a page fault arrives
+    unsafe { (*TSS.as_ptr()).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = stack_top };
+}
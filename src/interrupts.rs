@@ -1,15 +1,22 @@
-use crate::{gdt, hlt_loop, print, println};
+use crate::backtrace::Backtrace;
+use crate::memory::vma::{Access, FaultError};
+use crate::time::{rtc, TickSource};
+use crate::{cpu, error, gdt, info, memory, print, thread, usermode};
 use bitflags::bitflags;
 use core::arch::asm;
 use core::fmt::Debug;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
-use spin::{self, Mutex};
+use x86_64::instructions::interrupts;
 use x86_64::instructions::port::Port;
-use x86_64::registers::control;
+use x86_64::registers::{control, rflags::RFlags};
+use x86_64::VirtAddr;
 
+pub mod apic;
 pub mod idt;
 
+use apic::ApicError;
+
 #[repr(C)]
 pub struct InterruptStackFrame {
     instruction_pointer: u64,
@@ -37,6 +44,22 @@ impl Debug for InterruptStackFrame {
     }
 }
 
+impl InterruptStackFrame {
+    pub(crate) fn instruction_pointer(&self) -> u64 {
+        self.instruction_pointer
+    }
+
+    /// Whether the cpu was running in ring 3 when the interrupt arrived
+    pub(crate) fn from_user_mode(&self) -> bool {
+        self.code_segment & 0b11 == 3
+    }
+
+    /// Whether interrupts were enabled in the interrupted code
+    fn interrupts_enabled(&self) -> bool {
+        self.cpu_flags & RFlags::INTERRUPT_FLAG.bits() != 0
+    }
+}
+
 pub fn init_idt() {
     IDT.load();
 }
@@ -47,6 +70,11 @@ macro_rules! handler {
         extern "C" fn wrapper() -> ! {
             unsafe {
                 asm! {  "
+                // switch to the kernel GS if we came from ring 3 (CS is below rip)
+                test qword ptr [rsp + 8], 3;
+                jz 2f;
+                swapgs;
+                2:
                 push rax;
                 push rcx;
                 push rdx;
@@ -68,6 +96,10 @@ macro_rules! handler {
                 pop rdx;
                 pop rcx;
                 pop rax;
+                test qword ptr [rsp + 8], 3;
+                jz 3f;
+                swapgs;
+                3:
                 iretq",
                 sym $name, options(noreturn) };
             }
@@ -83,6 +115,11 @@ macro_rules! handler_with_error_code {
         extern "C" fn wrapper() -> ! {
             unsafe {
                 asm! {  "
+                // switch to the kernel GS if we came from ring 3 (CS is below error code and rip)
+                test qword ptr [rsp + 16], 3;
+                jz 2f;
+                swapgs;
+                2:
                 push rax;
                 push rcx;
                 push rdx;
@@ -108,6 +145,10 @@ macro_rules! handler_with_error_code {
                 pop rcx;
                 pop rax;
                 add rsp, 8; // remove error code
+                test qword ptr [rsp + 8], 3;
+                jz 3f;
+                swapgs;
+                3:
                 iretq",
                 sym $name, options(noreturn) };
             }
@@ -122,15 +163,22 @@ lazy_static! {
 
         // cpu exceptions
         idt.set_handler(0, handler!(divide_by_zero_handler));
-        idt.set_handler(3, handler!(breakpoint_handler));
+        idt.set_handler(3, handler!(breakpoint_handler)).set_privilege_level(3);
         idt.set_handler(6, handler!(invalid_opcode_handler));
         idt.set_handler(8, handler_with_error_code!(double_fault_handler)).
             set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-        idt.set_handler(14, handler_with_error_code!(page_fault_handler));
+        idt.set_handler(13, handler_with_error_code!(general_protection_fault_handler));
+        idt.set_handler(14, handler_with_error_code!(page_fault_handler))
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
 
         // hardware interrupts
         idt.set_handler(InterruptIndex::Timer.as_u8(), handler!(timer_interrupt_handler));
         idt.set_handler(InterruptIndex::Keyboard.as_u8(), handler!(keyboard_interrupt_handler));
+        idt.set_handler(InterruptIndex::Serial1.as_u8(), handler!(serial_interrupt_handler));
+        idt.set_handler(InterruptIndex::Rtc.as_u8(), handler!(rtc_interrupt_handler));
+        idt.set_handler(InterruptIndex::Mouse.as_u8(), handler!(mouse_interrupt_handler));
+        idt.set_handler(apic::WAKEUP_VECTOR, handler!(wakeup_interrupt_handler));
+        idt.set_handler(apic::SPURIOUS_VECTOR, handler!(spurious_interrupt_handler));
 
         idt
     };
@@ -155,36 +203,135 @@ bitflags! {
 //     }
 // }
 
+/// Terminates the current thread if the exception was caused by user code
+///
+/// Returns if the exception happened in the kernel.
+fn kill_user_thread(stack_frame: &InterruptStackFrame, exception: &str) {
+    if stack_frame.from_user_mode() {
+        error!(
+            "{} in user mode at {:#x}, killing thread {}",
+            exception,
+            stack_frame.instruction_pointer,
+            thread::current_id().as_u64()
+        );
+        thread::exit_with(thread::ExitStatus::Killed);
+    }
+}
+
 extern "C" fn double_fault_handler(stack_frame: &InterruptStackFrame, _error_code: u64) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT!\n{:#?}", stack_frame);
+    panic!(
+        "EXCEPTION: DOUBLE FAULT!\n{:#?}\n{}",
+        stack_frame,
+        Backtrace::interrupted(stack_frame)
+    );
+}
+
+extern "C" fn general_protection_fault_handler(stack_frame: &InterruptStackFrame, error_code: u64) {
+    kill_user_thread(stack_frame, "GENERAL PROTECTION FAULT");
+    panic!(
+        "EXCEPTION: GENERAL PROTECTION FAULT\nerror code: {:#x}\n{:#?}",
+        error_code, stack_frame
+    );
 }
 
-extern "C" fn page_fault_handler(stack_frame: &InterruptStackFrame, error_code: u64) -> () {
-    println!(
-        "\nEXCEPTION: PAGE FAULT while accessing {:?}\n\
-        error code: {:?}\n{:#?}",
-        control::Cr2::read(),
-        PageFaultErrorCode::from_bits(error_code).unwrap(),
-        stack_frame
+extern "C" fn page_fault_handler(stack_frame: &InterruptStackFrame, error_code: u64) {
+    let address = control::Cr2::read();
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        Access::Execute
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        Access::Write
+    } else {
+        Access::Read
+    };
+
+    if memory::stack::is_guard_page(address) {
+        report_stack_overflow(address);
+    }
+
+    let result = resolve_page_fault(stack_frame, address, access);
+    if result.is_ok() {
+        // retry the faulting instruction
+        return;
+    }
+
+    error!(
+        "EXCEPTION: PAGE FAULT while accessing {:?}\n\
+        error code: {:?}\nreason: {:?}\n{:#?}\n{}",
+        address,
+        error_code,
+        result,
+        stack_frame,
+        Backtrace::interrupted(stack_frame)
     );
-    hlt_loop();
+    kill_user_thread(stack_frame, "PAGE FAULT");
+    panic!("EXCEPTION: PAGE FAULT while accessing {:?}", address);
+}
+
+/// Terminates the thread that ran into the guard page at `address`
+fn report_stack_overflow(address: VirtAddr) -> ! {
+    let current = thread::current_id();
+    match thread::stack_owner(address) {
+        Some(owner) if owner == current => {
+            error!(
+                "EXCEPTION: stack overflow in thread {} (accessed {:?})",
+                owner.as_u64(),
+                address
+            );
+            thread::exit_with(thread::ExitStatus::Killed);
+        }
+        Some(owner) => panic!(
+            "EXCEPTION: thread {} accessed the stack guard page of thread {} at {:?}",
+            current.as_u64(),
+            owner.as_u64(),
+            address
+        ),
+        None => panic!(
+            "EXCEPTION: stack overflow on cpu {} (accessed {:?})",
+            cpu::current().index(),
+            address
+        ),
+    }
+}
+
+/// Maps the page if `address` lies in a reserved area of the current address space
+fn resolve_page_fault(
+    stack_frame: &InterruptStackFrame,
+    address: VirtAddr,
+    access: Access,
+) -> Result<(), FaultError> {
+    // threads, and with them user address spaces, only run on the bootstrap processor
+    if !usermode::is_user_range(address.as_u64(), 1) || !cpu::current().is_bsp() {
+        return Err(FaultError::NotReserved);
+    }
+    let address_space = thread::address_space().ok_or(FaultError::NotReserved)?;
+
+    // another thread might hold the lock, so it has to be able to run
+    if stack_frame.interrupts_enabled() {
+        interrupts::enable();
+    }
+    let result = address_space.lock().handle_fault(address, access);
+    interrupts::disable();
+    result
 }
 
 extern "C" fn divide_by_zero_handler(stack_frame: &InterruptStackFrame) -> ! {
-    println!("\nEXCEPTION: DIVIDE BY ZERO\n{:#?}", &*stack_frame);
+    kill_user_thread(stack_frame, "DIVIDE BY ZERO");
+    error!("EXCEPTION: DIVIDE BY ZERO\n{:#?}", &*stack_frame);
     loop {}
 }
 
 extern "C" fn breakpoint_handler(stack_frame: &InterruptStackFrame) {
-    println!(
-        "\nEXCEPTION: BREAKPOINT at {:#x}\n{:#?}",
+    info!(
+        "EXCEPTION: BREAKPOINT at {:#x}\n{:#?}",
         stack_frame.instruction_pointer, &*stack_frame
     );
 }
 
 extern "C" fn invalid_opcode_handler(stack_frame: &InterruptStackFrame) -> ! {
-    println!(
-        "\nEXCEPTION: INVALID OPCODE at {:#x}\n{:#?}",
+    kill_user_thread(stack_frame, "INVALID OPCODE");
+    error!(
+        "EXCEPTION: INVALID OPCODE at {:#x}\n{:#?}",
         stack_frame.instruction_pointer, &*stack_frame
     );
     loop {}
@@ -203,6 +350,9 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial1 = PIC_1_OFFSET + 4,
+    Rtc = PIC_2_OFFSET,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 #[allow(dead_code)]
@@ -214,39 +364,101 @@ impl InterruptIndex {
     fn as_usize(self) -> usize {
         usize::from(self as u8)
     }
+
+    /// Line of the interrupt on the ISA bus
+    fn isa_irq(self) -> u8 {
+        self as u8 - PIC_1_OFFSET
+    }
 }
 
-extern "C" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // print!(".");
+/// Acknowledges the interrupt at the APIC, or at the PIC until the APIC is set up
+pub fn end_of_interrupt(index: InterruptIndex) {
+    if apic::is_enabled() {
+        apic::end_of_interrupt();
+    } else {
+        unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) };
+    }
+}
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+/// Unmasks an interrupt that `apic::init` or the PIC setup left masked
+pub fn enable_isa_interrupt(index: InterruptIndex) -> Result<(), ApicError> {
+    if apic::is_enabled() {
+        return apic::route_isa_irq(index.isa_irq(), index.as_u8());
     }
+    interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let [mut mask_1, mut mask_2] = unsafe { pics.read_masks() };
+        match index.isa_irq() {
+            // the second PIC is chained to line 2 of the first
+            irq @ 8.. => {
+                mask_1 &= !(1 << 2);
+                mask_2 &= !(1 << (irq - 8));
+            }
+            irq => mask_1 &= !(1 << irq),
+        }
+        unsafe { pics.write_masks(mask_1, mask_2) };
+    });
+    Ok(())
 }
 
-extern "C" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                ScancodeSet1::new(),
-                layouts::Us104Key,
-                HandleControl::Ignore
-            ));
+/// Advances the clock and the scheduler, for whichever device drives the tick
+fn system_tick(index: InterruptIndex) {
+    crate::time::tick();
+    crate::task::timer::wake_expired();
+    end_of_interrupt(index);
+
+    // has to come after the EOI, we might not return here for a while
+    crate::thread::scheduler::tick();
+}
+
+extern "C" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    match crate::time::tick_source() {
+        TickSource::Pit => system_tick(InterruptIndex::Timer),
+        // the PIT might fire once more after it was stopped
+        TickSource::Rtc => end_of_interrupt(InterruptIndex::Timer),
     }
+}
 
-    let mut keyboard = KEYBOARD.lock();
+extern "C" fn rtc_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    rtc::acknowledge_interrupt();
+    match crate::time::tick_source() {
+        TickSource::Rtc => system_tick(InterruptIndex::Rtc),
+        TickSource::Pit => end_of_interrupt(InterruptIndex::Rtc),
+    }
+}
+
+extern "C" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // decoding happens in `task::keyboard`, the layout can change there
     let mut port: Port<u8> = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
     crate::task::keyboard::add_scancode(scancode);
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
+}
+
+extern "C" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // packets are put together in `task::mouse`
+    let mut port: Port<u8> = Port::new(0x60);
+
+    let byte: u8 = unsafe { port.read() };
+    crate::task::mouse::add_byte(byte);
+
+    end_of_interrupt(InterruptIndex::Mouse);
+}
+
+extern "C" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::task::serial::handle_interrupt();
+    end_of_interrupt(InterruptIndex::Serial1);
+}
+
+extern "C" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // only there to get the cpu out of `hlt`
+    apic::end_of_interrupt();
+}
+
+extern "C" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // not a real interrupt, so there is nothing to acknowledge
 }
 
 // test cases
@@ -0,0 +1,269 @@
+use super::{InterruptIndex, PICS};
+use crate::{acpi, memory::mmio};
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::model_specific::Msr,
+    structures::paging::{mapper::MapToError, Size4KiB},
+    VirtAddr,
+};
+
+/// Vector the local APIC delivers spurious interrupts to, they must not be acknowledged
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+/// Vector of the IPI that wakes up a halted cpu, e.g. when its executor has work
+pub const WAKEUP_VECTOR: u8 = 0xf0;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// local APIC registers
+const REG_ID: u64 = 0x20;
+const REG_TASK_PRIORITY: u64 = 0x80;
+const REG_EOI: u64 = 0xb0;
+const REG_SPURIOUS: u64 = 0xf0;
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+const REG_ICR_LOW: u64 = 0x300;
+const REG_ICR_HIGH: u64 = 0x310;
+
+// interrupt command register bits
+const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const ICR_SEND_PENDING: u32 = 1 << 12;
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+const ICR_ALL_EXCLUDING_SELF: u32 = 0b11 << 18;
+
+// I/O APIC registers, accessed through the select and window registers
+const IOAPIC_SELECT: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_REG_VERSION: u32 = 0x01;
+const IOAPIC_REG_REDIRECTION: u32 = 0x10;
+
+// redirection entry bits
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 15;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+#[derive(Debug)]
+pub enum ApicError {
+    /// ACPI wasn't initialized or there is no MADT
+    NoMadt,
+    NoIoApic,
+    /// no I/O APIC handles the global system interrupt
+    NoRoute(u32),
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for ApicError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        ApicError::Map(err)
+    }
+}
+
+struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    unsafe fn read(&mut self, reg: u32) -> u32 {
+        (self.base + IOAPIC_SELECT)
+            .as_mut_ptr::<u32>()
+            .write_volatile(reg);
+        (self.base + IOAPIC_WINDOW).as_ptr::<u32>().read_volatile()
+    }
+
+    unsafe fn write(&mut self, reg: u32, value: u32) {
+        (self.base + IOAPIC_SELECT)
+            .as_mut_ptr::<u32>()
+            .write_volatile(reg);
+        (self.base + IOAPIC_WINDOW)
+            .as_mut_ptr::<u32>()
+            .write_volatile(value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn set_redirection(&mut self, gsi: u32, low: u32, destination: u8) {
+        let reg = IOAPIC_REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        unsafe {
+            // mask while the entry is inconsistent
+            self.write(reg, REDIRECTION_MASKED);
+            self.write(reg + 1, (destination as u32) << 24);
+            self.write(reg, low);
+        }
+    }
+
+    fn mask_all(&mut self) {
+        for gsi in self.gsi_base..self.gsi_base + self.entries {
+            self.set_redirection(gsi, REDIRECTION_MASKED, 0);
+        }
+    }
+}
+
+/// Virtual address of the local APIC registers, 0 until `init`
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+static IO_APICS: Mutex<Vec<IoApic>> = Mutex::new(Vec::new());
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+unsafe fn local_read(reg: u64) -> u32 {
+    VirtAddr::new(LOCAL_APIC.load(Ordering::Relaxed) + reg)
+        .as_ptr::<u32>()
+        .read_volatile()
+}
+
+unsafe fn local_write(reg: u64, value: u32) {
+    VirtAddr::new(LOCAL_APIC.load(Ordering::Relaxed) + reg)
+        .as_mut_ptr::<u32>()
+        .write_volatile(value)
+}
+
+/// Switches from the 8259 PIC to the local APIC and I/O APICs described by the MADT
+///
+/// The timer and keyboard keep their vectors. `acpi::init` and `memory::init_global`
+/// have to be called before.
+pub fn init() -> Result<(), ApicError> {
+    let madt = acpi::get()
+        .and_then(|acpi| acpi.madt())
+        .ok_or(ApicError::NoMadt)?;
+    if madt.io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let local_apic = mmio::map(madt.local_apic_address, 4096)?;
+    let mut io_apics = Vec::new();
+    for entry in &madt.io_apics {
+        let mut io_apic = IoApic {
+            base: mmio::map(entry.address, 0x20)?,
+            gsi_base: entry.gsi_base,
+            entries: 0,
+        };
+        io_apic.entries = ((unsafe { io_apic.read(IOAPIC_REG_VERSION) } >> 16) & 0xff) + 1;
+        io_apics.push(io_apic);
+    }
+
+    interrupts::without_interrupts(|| {
+        LOCAL_APIC.store(local_apic.as_u64(), Ordering::Relaxed);
+        init_local();
+
+        io_apics.iter_mut().for_each(IoApic::mask_all);
+        *IO_APICS.lock() = io_apics;
+
+        // the 8259 keeps delivering the interrupts until all of them are routed
+        let routed = [InterruptIndex::Timer, InterruptIndex::Keyboard]
+            .into_iter()
+            .try_for_each(|index| route_isa_irq(index.isa_irq(), index.as_u8()));
+        if let Err(err) = routed {
+            IO_APICS.lock().iter_mut().for_each(IoApic::mask_all);
+            return Err(err);
+        }
+        unsafe { PICS.lock().disable() };
+
+        ENABLED.store(true, Ordering::SeqCst);
+        Ok(())
+    })
+}
+
+/// Enables the local APIC of the current cpu
+///
+/// The registers are at the same physical address on every cpu.
+pub fn init_local() {
+    unsafe {
+        let mut base = Msr::new(IA32_APIC_BASE);
+        base.write(base.read() | APIC_BASE_ENABLE);
+
+        local_write(REG_TASK_PRIORITY, 0);
+        local_write(REG_SPURIOUS, SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+}
+
+/// Delivers the ISA interrupt `irq` to `vector` on the current cpu
+///
+/// Applies the interrupt source overrides of the MADT, e.g. the PIT is usually
+/// connected to global system interrupt 2.
+pub fn route_isa_irq(irq: u8, vector: u8) -> Result<(), ApicError> {
+    let madt = acpi::get()
+        .and_then(|acpi| acpi.madt())
+        .ok_or(ApicError::NoMadt)?;
+
+    // ISA interrupts are active high and edge triggered unless overridden
+    let mut low = vector as u32;
+    let gsi = match madt.interrupt_override(irq) {
+        Some(o) => {
+            if o.active_low() {
+                low |= REDIRECTION_ACTIVE_LOW;
+            }
+            if o.level_triggered() {
+                low |= REDIRECTION_LEVEL_TRIGGERED;
+            }
+            o.gsi
+        }
+        None => irq as u32,
+    };
+
+    let destination = id();
+    interrupts::without_interrupts(|| {
+        let mut io_apics = IO_APICS.lock();
+        let io_apic = io_apics
+            .iter_mut()
+            .find(|io_apic| io_apic.handles(gsi))
+            .ok_or(ApicError::NoRoute(gsi))?;
+        io_apic.set_redirection(gsi, low, destination);
+        Ok(())
+    })
+}
+
+/// Whether interrupts are delivered through the APIC instead of the 8259 PIC
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the APIC id of the current cpu
+pub fn id() -> u8 {
+    (unsafe { local_read(REG_ID) } >> 24) as u8
+}
+
+/// Acknowledges the interrupt that is currently handled
+pub fn end_of_interrupt() {
+    unsafe { local_write(REG_EOI, 0) };
+}
+
+/// Writes the interrupt command register and waits until the IPI was sent
+fn send_command(destination: u8, command: u32) {
+    // an interrupt handler sending an IPI in between would overwrite the destination
+    interrupts::without_interrupts(|| unsafe {
+        local_write(REG_ICR_HIGH, (destination as u32) << 24);
+        local_write(REG_ICR_LOW, command);
+        while local_read(REG_ICR_LOW) & ICR_SEND_PENDING != 0 {
+            spin_loop();
+        }
+    });
+}
+
+/// Resets the cpu with `apic_id`, it waits for a startup IPI afterwards
+pub fn send_init(apic_id: u8) {
+    send_command(apic_id, ICR_DELIVERY_INIT | ICR_LEVEL_ASSERT);
+}
+
+/// Starts the cpu with `apic_id` in real mode at physical address `page * 4096`
+pub fn send_startup(apic_id: u8, page: u8) {
+    send_command(
+        apic_id,
+        ICR_DELIVERY_STARTUP | ICR_LEVEL_ASSERT | page as u32,
+    );
+}
+
+/// Sends an interrupt with `vector` to the cpu with `apic_id`
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    send_command(apic_id, ICR_LEVEL_ASSERT | vector as u32);
+}
+
+/// Sends an interrupt with `vector` to every cpu except the current one
+pub fn broadcast_ipi(vector: u8) {
+    send_command(0, ICR_ALL_EXCLUDING_SELF | ICR_LEVEL_ASSERT | vector as u32);
+}
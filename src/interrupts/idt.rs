@@ -94,7 +94,6 @@ impl EntryOptions {
         self
     }
 
-    #[allow(dead_code)]
     pub fn set_privilege_level(&mut self, dpl: u16) -> &mut Self {
         self.0 = set_bits(self.0, 13, 15, dpl);
         self
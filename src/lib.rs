@@ -14,20 +14,39 @@ use core::panic::PanicInfo;
 
 extern crate alloc;
 
+pub mod acpi;
 pub mod allocator;
+pub mod backtrace;
+pub mod cpu;
+pub mod elf;
+pub mod emergency;
+pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
+pub mod log;
 pub mod memory;
+pub mod pci;
+pub mod power;
+pub mod ps2;
 pub mod serial;
+pub mod shell;
+pub mod smp;
+pub mod syscall;
 pub mod task;
+pub mod thread;
+pub mod time;
+pub mod usermode;
 pub mod vga_buffer;
 
 pub fn init() {
     gdt::init();
+    cpu::init();
+    syscall::init();
     interrupts::init_idt();
     unsafe {
         interrupts::PICS.lock().initialize();
     };
+    time::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -93,9 +112,23 @@ use bootloader::{entry_point, BootInfo};
 entry_point!(test_kernel_main);
 
 /// Entry point for `cargo test`
+///
+/// Sets up the heap, the unit tests use collections.
 #[cfg(test)]
 #[no_mangle]
-fn test_kernel_main(_boot_info: &'static BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BitmapFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
     test_main();
     loop {}
 }
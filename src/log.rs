@@ -0,0 +1,450 @@
+use crate::serial;
+use crate::time::{Duration, Instant};
+use crate::vga_buffer::{self, KERNEL_CONSOLE};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Most `target=level` rules a filter can have
+const MAX_RULES: usize = 16;
+/// Most sinks records can go to
+const MAX_SINKS: usize = 8;
+/// Bytes of log the ring buffer keeps, older lines are overwritten
+pub const RING_SIZE: usize = 64 * 1024;
+
+/// Logs a message with the module it was written in as target
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Trace, $($arg)+));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError {
+    /// the filter names a level that doesn't exist
+    UnknownLevel,
+    TooManyRules,
+    TooManySinks,
+}
+
+/// How important a message is, `Error` is the most important
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|level| level.name() == name)
+    }
+
+    /// SGR parameter the console shows the level in
+    fn color(&self) -> u8 {
+        match self {
+            Level::Error => 31,
+            Level::Warn => 33,
+            Level::Info => 0,
+            Level::Debug | Level::Trace => 90,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.pad(label)
+    }
+}
+
+/// A message on its way to the sinks
+pub struct Record<'a> {
+    pub level: Level,
+    /// module path of the code that logged it
+    pub target: &'static str,
+    /// time since boot
+    pub time: Duration,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:5}.{:06}] {:<5} {}: {}",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.level,
+            self.target,
+            self.args
+        )
+    }
+}
+
+/// Where records go, called with interrupts enabled or from interrupt handlers
+///
+/// Must not allocate or log.
+pub trait Sink: Sync {
+    fn write(&self, record: &Record);
+}
+
+/// The kernel console, with the level in color
+pub struct ConsoleSink;
+
+impl Sink for ConsoleSink {
+    fn write(&self, record: &Record) {
+        let color = record.level.color();
+        vga_buffer::print_to(
+            KERNEL_CONSOLE,
+            format_args!("\x1b[{}m{}\x1b[0m\n", color, record),
+        );
+    }
+}
+
+/// COM1
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn write(&self, record: &Record) {
+        serial::_print(format_args!("{}\n", record));
+    }
+}
+
+/// The ring buffer `dump` reads
+pub struct RingSink;
+
+impl Sink for RingSink {
+    fn write(&self, record: &Record) {
+        // the record is lost if the buffer is locked, the cpu might have
+        // faulted while it held the lock
+        interrupts::without_interrupts(|| {
+            if let Some(mut ring) = RING.try_lock() {
+                writeln!(ring, "{}", record).unwrap();
+            }
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rule {
+    /// module path prefix
+    target: &'static str,
+    /// `None` turns logging off
+    level: Option<Level>,
+}
+
+/// Decides up to which level the records of a target are logged
+struct Filter {
+    default: Option<Level>,
+    rules: [Option<Rule>; MAX_RULES],
+}
+
+impl Filter {
+    const fn new() -> Self {
+        Filter {
+            default: Some(Level::Info),
+            rules: [None; MAX_RULES],
+        }
+    }
+
+    /// Parses comma separated `level` and `target=level` items, like `warn,rustkernel::time=debug`
+    fn parse(spec: &'static str) -> Result<Filter, LogError> {
+        let mut filter = Filter::new();
+        let mut count = 0;
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match item.split_once('=') {
+                Some((target, level)) => {
+                    let rule = filter.rules.get_mut(count).ok_or(LogError::TooManyRules)?;
+                    *rule = Some(Rule {
+                        target: target.trim(),
+                        level: parse_level(level.trim())?,
+                    });
+                    count += 1;
+                }
+                None => filter.default = parse_level(item)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The rule with the longest matching target wins
+    fn level(&self, target: &str) -> Option<Level> {
+        self.rules
+            .iter()
+            .flatten()
+            .filter(|rule| matches_target(rule.target, target))
+            .max_by_key(|rule| rule.target.len())
+            .map_or(self.default, |rule| rule.level)
+    }
+
+    /// Highest level any target is logged at
+    fn max_level(&self) -> Option<Level> {
+        let rules = self.rules.iter().flatten().map(|rule| rule.level);
+        rules.fold(self.default, Ord::max)
+    }
+}
+
+/// `off` or the name of a level
+fn parse_level(name: &str) -> Result<Option<Level>, LogError> {
+    match name {
+        "off" => Ok(None),
+        name => Level::from_name(name)
+            .map(Some)
+            .ok_or(LogError::UnknownLevel),
+    }
+}
+
+/// Whether `target` is the module `prefix` or lies in it
+fn matches_target(prefix: &str, target: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Keeps the last `N` bytes written to it
+pub struct Ring<const N: usize> {
+    bytes: [u8; N],
+    /// index of the oldest byte
+    start: usize,
+    len: usize,
+    /// whether bytes were overwritten, the oldest line might be cut off
+    wrapped: bool,
+}
+
+impl<const N: usize> Ring<N> {
+    pub const fn new() -> Self {
+        Ring {
+            bytes: [0; N],
+            start: 0,
+            len: 0,
+            wrapped: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < N {
+            self.bytes[(self.start + self.len) % N] = byte;
+            self.len += 1;
+        } else {
+            self.bytes[self.start] = byte;
+            self.start = (self.start + 1) % N;
+            self.wrapped = true;
+        }
+    }
+
+    /// The whole lines in the buffer, oldest first
+    pub fn contents(&mut self) -> &str {
+        // moves the oldest byte to the front, so the text is in one piece
+        self.bytes.rotate_left(self.start);
+        self.start = 0;
+        let mut text = &self.bytes[..self.len];
+        if self.wrapped {
+            let cut = text
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(text.len(), |i| i + 1);
+            text = &text[cut..];
+        }
+        core::str::from_utf8(text).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Default for Ring<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for Ring<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.push(byte));
+        Ok(())
+    }
+}
+
+/// A sink and the highest level it gets
+type SinkEntry = Option<(&'static dyn Sink, Level)>;
+
+static FILTER: Mutex<Filter> = Mutex::new(Filter::new());
+/// `max_level` of the filter, 0 if everything is off
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static SINKS: Mutex<[SinkEntry; MAX_SINKS]> = Mutex::new([
+    Some((&ConsoleSink, Level::Info)),
+    Some((&SerialSink, Level::Trace)),
+    Some((&RingSink, Level::Trace)),
+    None,
+    None,
+    None,
+    None,
+    None,
+]);
+static RING: Mutex<Ring<RING_SIZE>> = Mutex::new(Ring::new());
+
+/// Replaces the filter, see `Filter::parse` for the format
+///
+/// Until it is called everything from `info` up is logged.
+pub fn set_filter(spec: &'static str) -> Result<(), LogError> {
+    let filter = Filter::parse(spec)?;
+    let max_level = filter.max_level().map_or(0, |level| level as u8);
+    interrupts::without_interrupts(|| {
+        *FILTER.lock() = filter;
+        MAX_LEVEL.store(max_level, Ordering::Relaxed);
+    });
+    Ok(())
+}
+
+/// Sends the records up to `level` to `sink` as well
+pub fn add_sink(sink: &'static dyn Sink, level: Level) -> Result<(), LogError> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = SINKS.lock();
+        let free = sinks.iter_mut().find(|entry| entry.is_none());
+        *free.ok_or(LogError::TooManySinks)? = Some((sink, level));
+        Ok(())
+    })
+}
+
+/// Writes the lines in the ring buffer to `out`, oldest first
+///
+/// Writes nothing if the buffer is locked, so it is safe to call from the panic handler.
+pub fn dump(out: &mut impl fmt::Write) -> fmt::Result {
+    interrupts::without_interrupts(|| match RING.try_lock() {
+        Some(mut ring) => out.write_str(ring.contents()),
+        None => writeln!(out, "(kernel log is locked)"),
+    })
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &'static str, args: fmt::Arguments) {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    let enabled = interrupts::without_interrupts(|| FILTER.lock().level(target) >= Some(level));
+    if !enabled {
+        return;
+    }
+
+    let record = Record {
+        level,
+        target,
+        time: Instant::now().since_boot(),
+        args,
+    };
+    // a copy, so sinks can be added while records are written
+    let sinks = interrupts::without_interrupts(|| *SINKS.lock());
+    for (sink, max) in sinks.iter().flatten() {
+        if level <= *max {
+            sink.write(&record);
+        }
+    }
+}
+
+#[test_case]
+fn test_filter() {
+    let filter = Filter::parse("warn, rustkernel::time=trace,rustkernel::time::rtc=off").unwrap();
+    assert_eq!(filter.level("rustkernel"), Some(Level::Warn));
+    assert_eq!(filter.level("rustkernel::timer"), Some(Level::Warn));
+    assert_eq!(filter.level("rustkernel::time::hpet"), Some(Level::Trace));
+    assert_eq!(filter.level("rustkernel::time::rtc"), None);
+    assert_eq!(filter.max_level(), Some(Level::Trace));
+    assert_eq!(Filter::parse("loud").err(), Some(LogError::UnknownLevel));
+}
+
+#[test_case]
+fn test_ring_keeps_whole_lines() {
+    let mut ring = Ring::<16>::new();
+    write!(ring, "first\nsecond\n").unwrap();
+    assert_eq!(ring.contents(), "first\nsecond\n");
+    write!(ring, "third\n").unwrap();
+    assert_eq!(ring.contents(), "second\nthird\n");
+    write!(ring, "fourth\n").unwrap();
+    assert_eq!(ring.contents(), "third\nfourth\n");
+}
+
+#[test_case]
+fn test_added_sink_respects_its_level() {
+    use core::sync::atomic::AtomicUsize;
+
+    /// Counts the records it gets and keeps the level of the last one
+    struct CountingSink {
+        count: AtomicUsize,
+        last: AtomicU8,
+    }
+
+    impl Sink for CountingSink {
+        fn write(&self, record: &Record) {
+            self.count.fetch_add(1, Ordering::Relaxed);
+            self.last.store(record.level as u8, Ordering::Relaxed);
+        }
+    }
+
+    static SINK: CountingSink = CountingSink {
+        count: AtomicUsize::new(0),
+        last: AtomicU8::new(0),
+    };
+    add_sink(&SINK, Level::Warn).unwrap();
+    crate::error!("test record for the added sink");
+    crate::warn!("test record for the added sink");
+    crate::info!("test record only for the other sinks");
+    assert_eq!(SINK.count.load(Ordering::Relaxed), 2);
+    assert_eq!(SINK.last.load(Ordering::Relaxed), Level::Warn as u8);
+}
@@ -9,35 +9,71 @@ extern crate alloc;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use rustkernel::{
-    allocator, hlt_loop, init, memory, println,
-    task::{executor::Executor, keyboard, simple_executor::SimpleExecutor, Task},
+    acpi, allocator, elf, emergency, framebuffer, hlt_loop, info, init, interrupts, log, memory,
+    println, ps2, shell, smp,
+    task::{executor::Executor, serial, simple_executor::SimpleExecutor, Task},
+    thread, time, warn,
 };
 use x86_64::VirtAddr;
 
 entry_point!(kernel_main);
 
+/// Levels logged per module, see `log::set_filter`
+const LOG_FILTER: &str = "info";
+
 use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 
 //entry point to the programm
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
-    println!("main called!");
+    if let Err(err) = log::set_filter(LOG_FILTER) {
+        warn!("invalid log filter {:?}: {:?}", LOG_FILTER, err);
+    }
+    info!("main called!");
 
     init();
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
 
     let mut mapper = unsafe { rustkernel::memory::init(phys_mem_offset) };
-    let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator = unsafe { memory::BitmapFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    match framebuffer::console::init(1024, 768, 32) {
+        Ok((columns, rows)) => info!("framebuffer console with {}x{} characters", columns, rows),
+        Err(err) => warn!("staying in text mode: {:?}", err),
+    }
+    if let Err(err) = acpi::init() {
+        warn!("no ACPI tables: {:?}", err);
+    }
+    if let Err(err) = interrupts::apic::init() {
+        warn!("APIC not available, staying with the 8259 PIC: {:?}", err);
+    }
+    info!("TSC calibrated against {:?}", time::calibrate());
+    info!("it is {} UTC", time::now().date_time());
+    match ps2::init() {
+        Ok(devices) => info!("PS/2 devices: {:?}", devices),
+        Err(err) => warn!("PS/2 controller not available: {:?}", err),
+    }
+    thread::init();
+    match smp::init() {
+        Ok(cpus) => info!("{} cpus running", cpus),
+        Err(err) => warn!("application processors not started: {:?}", err),
+    }
+    if let Err(err) = serial::init() {
+        warn!("serial input not available: {:?}", err);
+    }
 
     #[cfg(test)]
     test_main();
 
+    thread::detach(elf::loader::spawn(elf::loader::HELLO, &["hello"], &[]));
+
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.spawn(Task::new(shell::run()));
+    executor.spawn(Task::new(framebuffer::panel::run()));
+    executor.spawn(Task::new(framebuffer::compositor::run()));
     executor.run();
 }
 
@@ -54,7 +90,7 @@ async fn example_task() {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    emergency::report_panic(info);
     hlt_loop();
 }
 
@@ -1,6 +1,19 @@
+pub mod address_space;
+pub mod frame_allocator;
+pub mod mmio;
+pub mod stack;
+pub mod vma;
+
+pub use frame_allocator::BitmapFrameAllocator;
+
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use x86_64::{
+    instructions::interrupts,
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+        Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
@@ -10,10 +23,69 @@ use x86_64::{
 /// Complete physical memory must be mapped at offset
 /// Function can only be called once
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    let (level_four_table_frame, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_PAGE_TABLE.store(
+        level_four_table_frame.start_address().as_u64(),
+        Ordering::Relaxed,
+    );
     let level_four_table = active_level_four_table(physical_memory_offset);
     OffsetPageTable::new(level_four_table, physical_memory_offset)
 }
 
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the level 4 table set up by the bootloader, used by kernel threads
+pub fn kernel_page_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PAGE_TABLE.load(Ordering::Relaxed)))
+}
+
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// Returns the address the physical address is mapped to by the bootloader
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// Whether `addr` is mapped in the active page table
+///
+/// Reads the tables without taking `MAPPER`, so it works in the panic handler
+/// and in exception handlers.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    if PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+    let (mut frame, _) = x86_64::registers::control::Cr3::read();
+    let indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    for (level, index) in indexes.into_iter().enumerate() {
+        let table: &PageTable = unsafe { &*phys_to_virt(frame.start_address()).as_ptr() };
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        // level 3 and 2 entries can map 1 GiB and 2 MiB pages
+        if matches!(level, 1 | 2) && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        frame = PhysFrame::containing_address(entry.addr());
+    }
+    true
+}
+
+/// Fills the frame with zeros through the physical memory mapping
+pub fn zero_frame(frame: PhysFrame) {
+    let virt = phys_to_virt(frame.start_address());
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096) };
+}
+
 /// all physical memory need to be mapped
 /// only call once
 unsafe fn active_level_four_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
@@ -52,40 +124,61 @@ pub fn create_example_mapping(
 //     }
 // }
 
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+static MAPPER: OnceCell<Mutex<OffsetPageTable<'static>>> = OnceCell::uninit();
+static FRAME_ALLOCATOR: OnceCell<Mutex<BitmapFrameAllocator>> = OnceCell::uninit();
 
-/// returns usable frames from the bootloader's memory map
-#[allow(dead_code)]
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+/// Makes the page table and frame allocator available to the rest of the kernel
+///
+/// Only call once, after the heap is initialized
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    MAPPER
+        .try_init_once(|| Mutex::new(mapper))
+        .expect("memory::init_global should only be called once");
+    FRAME_ALLOCATOR
+        .try_init_once(|| Mutex::new(frame_allocator))
+        .expect("memory::init_global should only be called once");
 }
 
-// use bootloader::bootinfo::MemoryRegion;
-
-#[allow(dead_code)]
-impl BootInfoFrameAllocator {
-    /// memory map has to be valid. `USABLE` frames must be unused
-    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator {
-            memory_map,
-            next: 0,
-        }
-    }
+/// Runs `f` with the global page table and frame allocator
+///
+/// Interrupts are disabled while `f` runs. `f` must not allocate on the heap.
+pub fn with_mapper<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut OffsetPageTable<'static>, &mut BitmapFrameAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.try_get().expect("mapper not initialized").lock();
+        let mut frame_allocator = FRAME_ALLOCATOR
+            .try_get()
+            .expect("frame allocator not initialized")
+            .lock();
+        f(&mut mapper, &mut frame_allocator)
+    })
+}
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+/// Like `with_mapper`, but returns `None` if `init_global` wasn't called yet
+pub fn try_with_mapper<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&mut OffsetPageTable<'static>, &mut BitmapFrameAllocator) -> R,
+{
+    if MAPPER.try_get().is_err() {
+        return None;
     }
+    Some(with_mapper(f))
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
-    }
+/// Runs `f` with the global frame allocator
+///
+/// Interrupts are disabled while `f` runs. `f` must not allocate on the heap.
+pub fn with_frame_allocator<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut BitmapFrameAllocator) -> R,
+{
+    interrupts::without_interrupts(|| {
+        let mut frame_allocator = FRAME_ALLOCATOR
+            .try_get()
+            .expect("frame allocator not initialized")
+            .lock();
+        f(&mut frame_allocator)
+    })
 }
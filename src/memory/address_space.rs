@@ -0,0 +1,315 @@
+use super::vma::{Access, FaultError, Vma, VmaError, VmaList};
+use super::{
+    kernel_page_table, phys_to_virt, physical_memory_offset, with_frame_allocator, zero_frame,
+};
+use crate::usermode::{self, USER_SPACE_END, USER_SPACE_START};
+use core::ops::Range;
+use x86_64::{
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, TranslateResult, UnmapError},
+        page_table::PageTableEntry,
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+/// Level 4 entries reserved for user space, everything else belongs to the kernel
+const USER_ENTRIES: Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+/// Virtual memory of a process
+///
+/// The kernel entries of the level 4 table are copied from the kernel's table,
+/// so they point to the same lower level tables and kernel mappings are shared.
+/// All frames mapped in user space are owned by the address space and freed on drop.
+/// Reserved areas are only backed by frames once they are accessed.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    vmas: VmaList,
+    /// next free address in the mmap region
+    mmap_next: u64,
+}
+
+unsafe fn table_mut(frame: PhysFrame) -> &'static mut PageTable {
+    &mut *phys_to_virt(frame.start_address()).as_mut_ptr()
+}
+
+fn assert_user_range(start: VirtAddr, len: u64) {
+    assert!(
+        usermode::is_user_range(start.as_u64(), len),
+        "{:?} + {:#x} is not in user space",
+        start,
+        len
+    );
+}
+
+fn pages(start: VirtAddr, len: u64) -> impl Iterator<Item = Page> {
+    Page::range(
+        Page::containing_address(start),
+        Page::containing_address(start + len + 4095u64),
+    )
+}
+
+impl AddressSpace {
+    /// Creates an address space with no user mappings
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let level_4_frame = with_frame_allocator(|frames| frames.allocate_frame())
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        zero_frame(level_4_frame);
+
+        let (kernel_table, table) =
+            unsafe { (table_mut(kernel_page_table()), table_mut(level_4_frame)) };
+        for (index, entry) in kernel_table.iter().enumerate() {
+            if !USER_ENTRIES.contains(&index) {
+                table[index] = entry.clone();
+            }
+        }
+
+        Ok(AddressSpace {
+            level_4_frame,
+            vmas: VmaList::new(),
+            mmap_next: usermode::USER_MMAP_START,
+        })
+    }
+
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Loads the address space into CR3
+    pub fn activate(&self) {
+        unsafe { Cr3::write(self.level_4_frame, Cr3Flags::empty()) };
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        unsafe { OffsetPageTable::new(table_mut(self.level_4_frame), physical_memory_offset()) }
+    }
+
+    /// Maps zeroed frames at `[start, start + len)`
+    pub fn map(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        for page in pages(start, len) {
+            let frame = with_frame_allocator(|frames| frames.allocate_frame())
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            zero_frame(frame);
+            if let Err(err) = self.map_to(page, frame, flags) {
+                with_frame_allocator(|frames| unsafe { frames.deallocate_frame(frame) });
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps `frame` at `page`, the address space takes ownership of the frame
+    pub fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert_user_range(page.start_address(), 4096);
+        let active = self.is_active();
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper = self.mapper();
+        let flush =
+            with_frame_allocator(|frames| unsafe { mapper.map_to(page, frame, flags, frames) })?;
+        if active {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+        Ok(())
+    }
+
+    /// Reserves `[start, start + len)`, frames are mapped on the first access
+    pub fn reserve(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), VmaError> {
+        assert_user_range(start, len);
+        let start = start.align_down(4096u64);
+        let end = (start + len).align_up(4096u64);
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        self.vmas.insert(Vma { start, end, flags })
+    }
+
+    /// Returns the reserved areas
+    pub fn vmas(&self) -> &VmaList {
+        &self.vmas
+    }
+
+    /// Maps a zeroed frame for `addr` if it lies in a reserved area that allows `access`
+    ///
+    /// Called by the page fault handler, the faulting access can be retried on success.
+    pub fn handle_fault(&mut self, addr: VirtAddr, access: Access) -> Result<(), FaultError> {
+        let vma = *self.vmas.find(addr).ok_or(FaultError::NotReserved)?;
+        if !vma.allows(access) {
+            return Err(FaultError::AccessViolation(vma));
+        }
+        let page = Page::containing_address(addr);
+        match self.translate(page) {
+            // another thread faulted on the same page first
+            Some((_, flags)) if Vma { flags, ..vma }.allows(access) => Ok(()),
+            Some(_) => Err(FaultError::AccessViolation(vma)),
+            None => self
+                .map(page.start_address(), 4096, vma.flags)
+                .map_err(|_| FaultError::OutOfMemory),
+        }
+    }
+
+    /// Checks that `[addr, addr + len)` is mapped or reserved and accessible from ring 3
+    pub fn is_accessible(&mut self, addr: VirtAddr, len: u64, write: bool) -> bool {
+        if len == 0 {
+            return true;
+        }
+        if !usermode::is_user_range(addr.as_u64(), len) {
+            return false;
+        }
+
+        let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if write {
+            required |= PageTableFlags::WRITABLE;
+        }
+        let access = if write { Access::Write } else { Access::Read };
+        let last = Page::containing_address(addr + (len - 1));
+        Page::range_inclusive(Page::containing_address(addr), last).all(|page| {
+            match self.translate(page) {
+                Some((_, flags)) => flags.contains(required),
+                None => self
+                    .vmas
+                    .find(page.start_address())
+                    .map_or(false, |vma| vma.allows(access)),
+            }
+        })
+    }
+
+    /// Removes the mappings and reservations in `[start, start + len)` and frees their frames
+    ///
+    /// Pages that are not mapped are skipped.
+    pub fn unmap(&mut self, start: VirtAddr, len: u64) -> Result<(), UnmapError> {
+        assert_user_range(start, len);
+        self.vmas
+            .remove(start.align_down(4096u64), (start + len).align_up(4096u64));
+        let active = self.is_active();
+        for page in pages(start, len) {
+            match self.mapper().unmap(page) {
+                Ok((frame, flush)) => {
+                    if active {
+                        flush.flush();
+                    } else {
+                        flush.ignore();
+                    }
+                    with_frame_allocator(|frames| unsafe { frames.deallocate_frame(frame) });
+                }
+                Err(UnmapError::PageNotMapped) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the flags of all pages and reserved areas in `[start, start + len)`
+    ///
+    /// Pages that are reserved but not mapped yet are skipped.
+    pub fn protect(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), FlagUpdateError> {
+        assert_user_range(start, len);
+        let active = self.is_active();
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        self.vmas.protect(
+            start.align_down(4096u64),
+            (start + len).align_up(4096u64),
+            flags,
+        );
+        for page in pages(start, len) {
+            let flush = match unsafe { self.mapper().update_flags(page, flags) } {
+                Ok(flush) => flush,
+                Err(FlagUpdateError::PageNotMapped)
+                    if self.vmas.find(page.start_address()).is_some() =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            if active {
+                flush.flush();
+            } else {
+                flush.ignore();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the frame and flags `page` is mapped to
+    pub fn translate(&mut self, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+        match self.mapper().translate(page.start_address()) {
+            TranslateResult::Mapped { frame, flags, .. } => {
+                Some((PhysFrame::containing_address(frame.start_address()), flags))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reserves `len` bytes in the mmap region
+    pub fn reserve_mmap(&mut self, len: u64) -> Option<VirtAddr> {
+        let len = len.checked_add(4095)? & !4095;
+        let start = self.mmap_next;
+        let end = start.checked_add(len)?;
+        if end > usermode::USER_MMAP_END {
+            return None;
+        }
+        self.mmap_next = end;
+        Some(VirtAddr::new(start))
+    }
+}
+
+/// Frees the table `entry` points to, everything mapped below it and the entry
+/// itself. At level 0 the entry maps a user frame, which is freed as well.
+unsafe fn free_entry(
+    entry: &mut PageTableEntry,
+    level: u8,
+    frames: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        // unused, or a huge page we never create in user space
+        Err(_) => return,
+    };
+    if level > 0 {
+        for next in table_mut(frame).iter_mut() {
+            free_entry(next, level - 1, frames);
+        }
+    }
+    frames.deallocate_frame(frame);
+    entry.set_unused();
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+
+        let table = unsafe { table_mut(self.level_4_frame) };
+        with_frame_allocator(|frames| unsafe {
+            for index in USER_ENTRIES {
+                free_entry(&mut table[index], 3, frames);
+            }
+            frames.deallocate_frame(self.level_4_frame);
+        });
+    }
+}
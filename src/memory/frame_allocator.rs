@@ -0,0 +1,219 @@
+use super::phys_to_virt;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{
+        frame::PhysFrameRange, FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB,
+    },
+    PhysAddr,
+};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS: usize = 64;
+
+/// Memory below 1 MiB is only handed out by `allocate_below` if there is other
+/// memory left, it's needed for things like the AP trampoline
+const LOW_MEMORY_END: u64 = 0x10_0000;
+const LOW_WORDS: usize = (LOW_MEMORY_END / FRAME_SIZE) as usize / BITS;
+
+/// Physical frame allocator with one bit per frame
+///
+/// A set bit means the frame is in use. Everything that is not `Usable` in the
+/// bootloader's memory map starts out (and stays) used. The bitmap itself lives
+/// in usable memory and is accessed through the physical memory mapping.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// word to start searching at, everything between it and the end of low
+    /// memory was full at some point
+    next: usize,
+    usable: usize,
+    free: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Builds the allocator from the bootloader's memory map
+    ///
+    /// # Safety
+    ///
+    /// `USABLE` frames must be unused and `memory::init` must have been called,
+    /// the bitmap is written through the physical memory mapping.
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        let end = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frames = (end / FRAME_SIZE) as usize;
+        let words = frames.div_ceil(BITS);
+        let bitmap_bytes = (words * 8) as u64;
+
+        // the bitmap is stored at the start of the first region that is big enough
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_bytes)
+            .map(|r| r.range.start_addr())
+            .expect("no usable region is big enough for the frame bitmap");
+        let bitmap: &'static mut [u64] = core::slice::from_raw_parts_mut(
+            phys_to_virt(PhysAddr::new(bitmap_start)).as_mut_ptr(),
+            words,
+        );
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            next: LOW_WORDS,
+            usable: 0,
+            free: 0,
+        };
+        for region in usable_regions() {
+            let start = (region.range.start_addr() / FRAME_SIZE) as usize;
+            let end = (region.range.end_addr() / FRAME_SIZE) as usize;
+            for index in start..end {
+                allocator.set_free(index);
+            }
+            allocator.usable += end - start;
+        }
+
+        let bitmap_frames = bitmap_bytes.div_ceil(FRAME_SIZE) as usize;
+        let bitmap_first = (bitmap_start / FRAME_SIZE) as usize;
+        for index in bitmap_first..bitmap_first + bitmap_frames {
+            allocator.set_used(index);
+        }
+
+        allocator
+    }
+
+    /// Number of frames that can currently be allocated
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    /// Number of usable frames that are allocated, including the bitmap
+    pub fn used_frames(&self) -> usize {
+        self.usable - self.free
+    }
+
+    /// Number of frames the bootloader reported as usable
+    pub fn usable_frames(&self) -> usize {
+        self.usable
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / BITS] & (1 << (index % BITS)) != 0
+    }
+
+    fn set_used(&mut self, index: usize) {
+        debug_assert!(!self.is_used(index));
+        self.bitmap[index / BITS] |= 1 << (index % BITS);
+        self.free -= 1;
+    }
+
+    fn set_free(&mut self, index: usize) {
+        assert!(self.is_used(index), "frame {:#x} freed twice", index);
+        self.bitmap[index / BITS] &= !(1 << (index % BITS));
+        self.free += 1;
+        if (LOW_WORDS..self.next).contains(&(index / BITS)) {
+            self.next = index / BITS;
+        }
+    }
+
+    fn frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+
+    fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    /// Allocates `count` physically contiguous frames, e.g. for DMA buffers
+    ///
+    /// The first frame is aligned to `align` frames, which has to be a power of two.
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrameRange> {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let frames = self.bitmap.len() * BITS;
+        let mut start = 0;
+        while start + count <= frames {
+            match (start..start + count)
+                .rev()
+                .find(|&index| self.is_used(index))
+            {
+                // skip past the used frame and retry at the next aligned index
+                Some(used) => start = (used + align) & !(align - 1),
+                None => {
+                    for index in start..start + count {
+                        self.set_used(index);
+                    }
+                    return Some(PhysFrame::range(
+                        Self::frame(start),
+                        Self::frame(start + count),
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    /// Frees frames returned by `allocate_contiguous`
+    ///
+    /// # Safety
+    ///
+    /// The frames must not be used anymore.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for frame in range {
+            self.deallocate_frame(frame);
+        }
+    }
+
+    /// Allocates a frame that lies completely below `limit`
+    pub fn allocate_below(&mut self, limit: PhysAddr) -> Option<PhysFrame> {
+        let frames = ((limit.as_u64() / FRAME_SIZE) as usize).min(self.bitmap.len() * BITS);
+        let index = (0..frames).find(|&index| !self.is_used(index))?;
+        self.set_used(index);
+        Some(Self::frame(index))
+    }
+
+    /// Returns the index of a free frame in `words`
+    fn find_free(&self, words: core::ops::Range<usize>) -> Option<usize> {
+        let start = words.start;
+        let word = self.bitmap[words]
+            .iter()
+            .position(|&word| word != u64::MAX)?
+            + start;
+        Some(word * BITS + (!self.bitmap[word]).trailing_zeros() as usize)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let words = self.bitmap.len();
+        let index = match self.find_free(self.next.min(words)..words) {
+            Some(index) => {
+                self.next = index / BITS;
+                index
+            }
+            // only low memory is left
+            None => self.find_free(0..LOW_WORDS.min(words))?,
+        };
+        self.set_used(index);
+        Some(Self::frame(index))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = Self::index(frame);
+        assert!(
+            index < self.bitmap.len() * BITS,
+            "{:?} is not managed by the frame allocator",
+            frame
+        );
+        self.set_free(index);
+    }
+}
@@ -0,0 +1,41 @@
+use super::with_mapper;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::{
+    structures::paging::{mapper::MapToError, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// Virtual region device memory is mapped in, right above the kernel stacks
+pub const MMIO_REGION_START: u64 = 0x0000_4440_0000_0000;
+pub const MMIO_REGION_END: u64 = 0x0000_4444_0000_0000;
+
+static NEXT: AtomicU64 = AtomicU64::new(MMIO_REGION_START);
+
+/// Maps `len` bytes of device memory at `phys` uncached and returns the virtual address
+///
+/// Mappings are never removed, devices are only set up once.
+pub fn map(phys: PhysAddr, len: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + len.max(1) - 1u64);
+    let size = last.start_address() - first.start_address() + 4096;
+
+    let start = NEXT.fetch_add(size, Ordering::Relaxed);
+    if start + size > MMIO_REGION_END {
+        return Err(MapToError::FrameAllocationFailed);
+    }
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    with_mapper(|mapper, frames| {
+        for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+            let page = Page::containing_address(VirtAddr::new(start + i as u64 * 4096));
+            unsafe { mapper.map_to(page, frame, flags, frames)?.flush() };
+        }
+        Ok::<(), MapToError<Size4KiB>>(())
+    })?;
+
+    Ok(VirtAddr::new(start) + (phys - first.start_address()))
+}
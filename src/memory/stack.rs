@@ -0,0 +1,139 @@
+use super::with_mapper;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+        Size4KiB,
+    },
+    VirtAddr,
+};
+
+/// Virtual region kernel stacks are mapped in
+///
+/// Shares the level 4 entry with the heap, so it's part of every address space.
+pub const STACK_REGION_START: u64 = 0x0000_4400_0000_0000;
+pub const STACK_REGION_END: u64 = 0x0000_4440_0000_0000;
+
+/// Every stack gets a slot of this size, starting with the guard page
+pub const STACK_SLOT_SIZE: u64 = 64 * 1024;
+
+const GUARD_SIZE: u64 = 4096;
+
+/// Slots that are free again, and the first slot that was never used
+struct Slots {
+    free: Vec<u64>,
+    next: u64,
+}
+
+static SLOTS: Mutex<Slots> = Mutex::new(Slots {
+    free: Vec::new(),
+    next: 0,
+});
+
+/// A kernel stack in the stack region with an unmapped guard page below it
+///
+/// An overflow hits the guard page instead of silently corrupting memory,
+/// see `is_guard_page`. The frames are freed on drop.
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: u64,
+    size: u64,
+}
+
+fn slot_start(slot: u64) -> VirtAddr {
+    VirtAddr::new(STACK_REGION_START + slot * STACK_SLOT_SIZE)
+}
+
+impl KernelStack {
+    /// Maps a stack of `size` bytes, rounded up to whole pages
+    pub fn new(size: u64) -> Result<Self, MapToError<Size4KiB>> {
+        let size = (size + 4095) & !4095;
+        assert!(
+            size > 0 && size <= STACK_SLOT_SIZE - GUARD_SIZE,
+            "kernel stacks can be at most {:#x} bytes",
+            STACK_SLOT_SIZE - GUARD_SIZE
+        );
+
+        let slot = interrupts::without_interrupts(|| {
+            let mut slots = SLOTS.lock();
+            match slots.free.pop() {
+                Some(slot) => Some(slot),
+                None if slot_start(slots.next) < VirtAddr::new(STACK_REGION_END) => {
+                    slots.next += 1;
+                    Some(slots.next - 1)
+                }
+                None => None,
+            }
+        })
+        .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let stack = KernelStack { slot, size };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        with_mapper(|mapper, frames| {
+            for page in stack.pages() {
+                let frame = frames
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?;
+                match unsafe { mapper.map_to(page, frame, flags, frames) } {
+                    Ok(flush) => flush.flush(),
+                    Err(err) => {
+                        unsafe { frames.deallocate_frame(frame) };
+                        return Err(err);
+                    }
+                }
+            }
+            Ok(())
+        })
+        // dropping `stack` unmaps the pages mapped so far
+        .map(|()| stack)
+    }
+
+    fn bottom(&self) -> VirtAddr {
+        slot_start(self.slot) + GUARD_SIZE
+    }
+
+    /// First address above the stack, 16 byte aligned
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.size
+    }
+
+    /// Whether `addr` lies in the guard page below this stack
+    pub fn guard_contains(&self, addr: VirtAddr) -> bool {
+        let guard = slot_start(self.slot);
+        guard <= addr && addr < guard + GUARD_SIZE
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.bottom()),
+            Page::containing_address(self.top()),
+        )
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        with_mapper(|mapper, frames| {
+            for page in self.pages() {
+                match mapper.unmap(page) {
+                    Ok((frame, flush)) => {
+                        flush.flush();
+                        unsafe { frames.deallocate_frame(frame) };
+                    }
+                    // a partially mapped stack whose allocation failed
+                    Err(_) => break,
+                }
+            }
+        });
+        interrupts::without_interrupts(|| SLOTS.lock().free.push(self.slot));
+    }
+}
+
+/// Whether `addr` lies in the guard page of a kernel stack
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    (STACK_REGION_START..STACK_REGION_END).contains(&addr)
+        && (addr - STACK_REGION_START) % STACK_SLOT_SIZE < GUARD_SIZE
+}
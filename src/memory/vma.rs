@@ -0,0 +1,166 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+/// A reserved region of virtual memory
+///
+/// Frames are only allocated and mapped when a page of the area is first accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: VirtAddr,
+    /// first address after the area
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+}
+
+/// Kind of memory access that caused a page fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// the area overlaps an already reserved one
+    Overlap,
+}
+
+/// Why a page fault could not be resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// the address is not part of any reserved area
+    NotReserved,
+    /// the area or the page mapped in it doesn't allow the access
+    AccessViolation(Vma),
+    OutOfMemory,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Whether the permissions of the area allow `access`
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => true,
+            Access::Write => self.flags.contains(PageTableFlags::WRITABLE),
+            Access::Execute => !self.flags.contains(PageTableFlags::NO_EXECUTE),
+        }
+    }
+}
+
+/// Non-overlapping areas, sorted by start address
+#[derive(Debug, Default)]
+pub struct VmaList {
+    areas: BTreeMap<VirtAddr, Vma>,
+}
+
+impl VmaList {
+    pub const fn new() -> Self {
+        VmaList {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the area, it must not overlap any existing one
+    pub fn insert(&mut self, vma: Vma) -> Result<(), VmaError> {
+        let overlaps_previous = self.find(vma.start).is_some();
+        let overlaps_next = self
+            .areas
+            .range(vma.start..)
+            .next()
+            .map_or(false, |(&start, _)| start < vma.end);
+        if overlaps_previous || overlaps_next {
+            return Err(VmaError::Overlap);
+        }
+        self.areas.insert(vma.start, vma);
+        Ok(())
+    }
+
+    /// Returns the area containing `addr`
+    ///
+    /// Doesn't allocate, so it can be used in the page fault handler.
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    /// Removes `[start, end)` from all areas, splitting them where necessary
+    pub fn remove(&mut self, start: VirtAddr, end: VirtAddr) {
+        for key in self.split(start, end) {
+            self.areas.remove(&key);
+        }
+    }
+
+    /// Changes the permissions of `[start, end)`, splitting areas where necessary
+    pub fn protect(&mut self, start: VirtAddr, end: VirtAddr, flags: PageTableFlags) {
+        for key in self.split(start, end) {
+            if let Some(vma) = self.areas.get_mut(&key) {
+                vma.flags = flags;
+            }
+        }
+    }
+
+    /// Splits the areas at `start` and `end` and returns the start addresses
+    /// of the areas inside `[start, end)`
+    fn split(&mut self, start: VirtAddr, end: VirtAddr) -> Vec<VirtAddr> {
+        self.split_at(start);
+        self.split_at(end);
+        self.areas.range(start..end).map(|(&key, _)| key).collect()
+    }
+
+    fn split_at(&mut self, addr: VirtAddr) {
+        if let Some(&vma) = self.find(addr) {
+            if vma.start != addr {
+                self.areas.get_mut(&vma.start).unwrap().end = addr;
+                self.areas.insert(addr, Vma { start: addr, ..vma });
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_vma_split() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut list = VmaList::new();
+    list.insert(Vma {
+        start: VirtAddr::new(0x1000),
+        end: VirtAddr::new(0x5000),
+        flags,
+    })
+    .unwrap();
+    assert_eq!(
+        list.insert(Vma {
+            start: VirtAddr::new(0x4000),
+            end: VirtAddr::new(0x6000),
+            flags,
+        }),
+        Err(VmaError::Overlap)
+    );
+
+    list.protect(
+        VirtAddr::new(0x2000),
+        VirtAddr::new(0x3000),
+        PageTableFlags::PRESENT,
+    );
+    list.remove(VirtAddr::new(0x4000), VirtAddr::new(0x5000));
+    assert_eq!(list.iter().count(), 3);
+    assert!(list
+        .find(VirtAddr::new(0x1fff))
+        .unwrap()
+        .allows(Access::Write));
+    assert!(!list
+        .find(VirtAddr::new(0x2000))
+        .unwrap()
+        .allows(Access::Write));
+    assert!(list.find(VirtAddr::new(0x4000)).is_none());
+}
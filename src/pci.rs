@@ -0,0 +1,97 @@
+use spin::Mutex;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+use x86_64::PhysAddr;
+
+const CONFIG_ADDRESS_PORT: u16 = 0xcf8;
+const CONFIG_DATA_PORT: u16 = 0xcfc;
+
+const ENABLE: u32 = 1 << 31;
+/// Vendor id read from a slot without a device
+const NO_DEVICE: u16 = 0xffff;
+/// Bit of the header type, set for devices with more than one function
+const MULTI_FUNCTION: u32 = 0x80 << 16;
+
+// configuration space offsets
+const VENDOR_DEVICE_ID: u8 = 0x00;
+const HEADER_TYPE: u8 = 0x0c;
+const FIRST_BAR: u8 = 0x10;
+
+/// Address and data port, an access needs both
+static PORTS: Mutex<(Port<u32>, Port<u32>)> =
+    Mutex::new((Port::new(CONFIG_ADDRESS_PORT), Port::new(CONFIG_DATA_PORT)));
+
+/// A function of a device on the PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciDevice {
+    /// Reads the register at `offset` of the configuration space
+    pub fn read(&self, offset: u8) -> u32 {
+        let address = ENABLE
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xfc);
+        without_interrupts(|| {
+            let mut ports = PORTS.lock();
+            unsafe {
+                ports.0.write(address);
+                ports.1.read()
+            }
+        })
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(VENDOR_DEVICE_ID) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(VENDOR_DEVICE_ID) >> 16) as u16
+    }
+
+    /// Returns the address of memory BAR `index`, `None` if it is an I/O BAR
+    pub fn memory_bar(&self, index: u8) -> Option<PhysAddr> {
+        let offset = FIRST_BAR + 4 * index;
+        let bar = self.read(offset);
+        if bar & 1 != 0 {
+            return None;
+        }
+        let mut addr = u64::from(bar & !0xf);
+        // a 64 bit BAR continues in the next one
+        if bar & 0b110 == 0b100 {
+            addr |= u64::from(self.read(offset + 4)) << 32;
+        }
+        Some(PhysAddr::new(addr))
+    }
+}
+
+/// Returns all functions of all devices
+pub fn devices() -> impl Iterator<Item = PciDevice> {
+    (0..=255)
+        .flat_map(|bus| {
+            (0..32).map(move |device| PciDevice {
+                bus,
+                device,
+                function: 0,
+            })
+        })
+        .filter(|device| device.vendor_id() != NO_DEVICE)
+        .flat_map(|device| {
+            let functions = if device.read(HEADER_TYPE) & MULTI_FUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+            (0..functions).map(move |function| PciDevice { function, ..device })
+        })
+        .filter(|device| device.vendor_id() != NO_DEVICE)
+}
+
+/// Returns the first device with the given ids
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    devices().find(|device| device.vendor_id() == vendor_id && device.device_id() == device_id)
+}
@@ -0,0 +1,38 @@
+use core::arch::asm;
+use x86_64::instructions::{interrupts, port::Port, tables::lidt};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+/// Command and status port of the 8042 keyboard controller
+const KEYBOARD_CONTROLLER: u16 = 0x64;
+/// Status bit that is set while the controller hasn't taken the last command
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+/// Command that pulses the reset line of the cpu
+const PULSE_RESET: u8 = 0xfe;
+
+/// Restarts the machine
+///
+/// Asks the keyboard controller to reset the cpu, if there is none a triple
+/// fault does it. `acpi::shutdown` turns the machine off instead.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    unsafe {
+        let mut controller = Port::<u8>::new(KEYBOARD_CONTROLLER);
+        for _ in 0..0x10000 {
+            if controller.read() & INPUT_BUFFER_FULL == 0 {
+                break;
+            }
+        }
+        controller.write(PULSE_RESET);
+    }
+
+    // the next exception can't be delivered with an empty IDT
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    unsafe {
+        lidt(&empty);
+        asm!("int3", options(noreturn));
+    }
+}
@@ -0,0 +1,282 @@
+use crate::interrupts::{self, apic::ApicError, InterruptIndex};
+use crate::task::mouse;
+use crate::time::{Duration, Instant};
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+// status register bits
+const OUTPUT_BUFFER_FULL: u8 = 1 << 0;
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+
+// controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_SECOND_PORT: u8 = 0xa7;
+const ENABLE_SECOND_PORT: u8 = 0xa8;
+const TEST_SECOND_PORT: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_FIRST_PORT: u8 = 0xab;
+const DISABLE_FIRST_PORT: u8 = 0xad;
+const ENABLE_FIRST_PORT: u8 = 0xae;
+const WRITE_SECOND_PORT: u8 = 0xd4;
+
+// configuration byte bits
+const CONFIG_FIRST_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_IRQ: u8 = 1 << 1;
+const CONFIG_SECOND_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// device commands and responses
+const RESET: u8 = 0xff;
+const SET_DEFAULTS: u8 = 0xf6;
+const ENABLE_SCANNING: u8 = 0xf4;
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_ID: u8 = 0xf2;
+const SET_SCANCODE_SET: u8 = 0xf0;
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const DEVICE_TEST_PASSED: u8 = 0xaa;
+
+/// Device id of a mouse that reports the scroll wheel
+const INTELLIMOUSE_ID: u8 = 3;
+
+/// How long the controller and the devices get to answer, a reset takes the longest
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum Ps2Error {
+    /// the controller didn't answer, there probably is none
+    Timeout,
+    SelfTestFailed(u8),
+    /// the keyboard port test returned this error code
+    KeyboardPortFailed(u8),
+    /// a device answered a command with something else than an acknowledgement
+    NoAck(u8),
+    Apic(ApicError),
+}
+
+impl From<ApicError> for Ps2Error {
+    fn from(err: ApicError) -> Self {
+        Ps2Error::Apic(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseType {
+    Standard,
+    /// IntelliMouse, its packets have a fourth byte with the wheel movement
+    Wheel,
+}
+
+/// What `init` found
+#[derive(Debug, Clone, Copy)]
+pub struct Devices {
+    pub keyboard: bool,
+    pub mouse: Option<MouseType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Keyboard,
+    Mouse,
+}
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(STATUS_PORT).read() }
+}
+
+fn wait_for(condition: impl Fn(u8) -> bool) -> Result<(), Ps2Error> {
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition(status()) {
+        if Instant::now() >= deadline {
+            return Err(Ps2Error::Timeout);
+        }
+        core::hint::spin_loop();
+    }
+    Ok(())
+}
+
+fn read_data() -> Result<u8, Ps2Error> {
+    wait_for(|status| status & OUTPUT_BUFFER_FULL != 0)?;
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & INPUT_BUFFER_FULL == 0)?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+fn send_command(command: u8) -> Result<(), Ps2Error> {
+    wait_for(|status| status & INPUT_BUFFER_FULL == 0)?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+/// Drops whatever the devices sent before
+fn flush() {
+    for _ in 0..16 {
+        if status() & OUTPUT_BUFFER_FULL == 0 {
+            break;
+        }
+        unsafe { Port::<u8>::new(DATA_PORT).read() };
+    }
+}
+
+fn write_device(device: Device, byte: u8) -> Result<(), Ps2Error> {
+    if device == Device::Mouse {
+        send_command(WRITE_SECOND_PORT)?;
+    }
+    write_data(byte)
+}
+
+/// Sends `byte` to `device` and waits for the acknowledgement, resending if asked to
+fn device_command(device: Device, byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..3 {
+        write_device(device, byte)?;
+        match read_data()? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            other => return Err(Ps2Error::NoAck(other)),
+        }
+    }
+    Err(Ps2Error::NoAck(RESEND))
+}
+
+fn reset_device(device: Device) -> Result<(), Ps2Error> {
+    device_command(device, RESET)?;
+    match read_data()? {
+        DEVICE_TEST_PASSED => {}
+        other => return Err(Ps2Error::NoAck(other)),
+    }
+    // a mouse sends its id after the test result
+    if device == Device::Mouse {
+        read_data()?;
+    }
+    Ok(())
+}
+
+fn init_keyboard() -> Result<(), Ps2Error> {
+    reset_device(Device::Keyboard)?;
+    // the controller translates set 2 into the set 1 `task::keyboard` decodes
+    device_command(Device::Keyboard, SET_SCANCODE_SET)?;
+    device_command(Device::Keyboard, 2)?;
+    device_command(Device::Keyboard, ENABLE_SCANNING)
+}
+
+fn init_mouse() -> Result<MouseType, Ps2Error> {
+    reset_device(Device::Mouse)?;
+    device_command(Device::Mouse, SET_DEFAULTS)?;
+
+    // this sequence of sample rates unlocks the wheel of an IntelliMouse
+    for rate in [200, 100, 80] {
+        device_command(Device::Mouse, SET_SAMPLE_RATE)?;
+        device_command(Device::Mouse, rate)?;
+    }
+    device_command(Device::Mouse, GET_ID)?;
+    let mouse_type = match read_data()? {
+        INTELLIMOUSE_ID => MouseType::Wheel,
+        _ => MouseType::Standard,
+    };
+
+    device_command(Device::Mouse, ENABLE_SCANNING)?;
+    Ok(mouse_type)
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    send_command(READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    send_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sets up the 8042 controller, the keyboard and the mouse if there is one
+///
+/// Keyboard and mouse interrupts are routed afterwards. Has to be called after
+/// `interrupts::apic::init` and `time::calibrate`, the timeouts use the clock.
+pub fn init() -> Result<Devices, Ps2Error> {
+    // no interrupt may take the answers to our commands
+    let (devices, config) = without_interrupts(|| -> Result<_, Ps2Error> {
+        send_command(DISABLE_FIRST_PORT)?;
+        send_command(DISABLE_SECOND_PORT)?;
+        flush();
+
+        let mut config = read_config()?;
+        config &= !(CONFIG_FIRST_IRQ | CONFIG_SECOND_IRQ);
+        config |= CONFIG_TRANSLATION;
+        write_config(config)?;
+
+        send_command(SELF_TEST)?;
+        match read_data()? {
+            SELF_TEST_PASSED => {}
+            other => return Err(Ps2Error::SelfTestFailed(other)),
+        }
+        // some controllers reset themselves during the test
+        write_config(config)?;
+
+        // the clock of the second port only runs while it is enabled, if there is one
+        send_command(ENABLE_SECOND_PORT)?;
+        let dual_channel = read_config()? & CONFIG_SECOND_CLOCK_DISABLED == 0;
+        send_command(DISABLE_SECOND_PORT)?;
+
+        send_command(TEST_FIRST_PORT)?;
+        match read_data()? {
+            PORT_TEST_PASSED => {}
+            other => return Err(Ps2Error::KeyboardPortFailed(other)),
+        }
+        let mut mouse_port = false;
+        if dual_channel {
+            send_command(TEST_SECOND_PORT)?;
+            mouse_port = read_data()? == PORT_TEST_PASSED;
+        }
+
+        send_command(ENABLE_FIRST_PORT)?;
+        let keyboard = init_keyboard().is_ok();
+        let mut mouse = None;
+        if mouse_port {
+            send_command(ENABLE_SECOND_PORT)?;
+            mouse = init_mouse().ok();
+        }
+        flush();
+
+        if keyboard {
+            config |= CONFIG_FIRST_IRQ;
+        }
+        if mouse.is_some() {
+            config |= CONFIG_SECOND_IRQ;
+        }
+        write_config(config)?;
+        Ok((Devices { keyboard, mouse }, config))
+    })?;
+
+    if let Some(mouse_type) = devices.mouse {
+        mouse::set_packet_size(match mouse_type {
+            MouseType::Standard => 3,
+            MouseType::Wheel => 4,
+        });
+        interrupts::enable_isa_interrupt(InterruptIndex::Mouse)?;
+    }
+    if config & CONFIG_FIRST_IRQ != 0 {
+        interrupts::enable_isa_interrupt(InterruptIndex::Keyboard)?;
+    }
+    Ok(devices)
+}
+
+/// Sends a byte to the keyboard without waiting for the acknowledgement
+///
+/// The keyboard answers through the keyboard interrupt, so the answer ends up
+/// in the scancode queue.
+pub fn write_keyboard(byte: u8) {
+    without_interrupts(|| {
+        let _ = write_device(Device::Keyboard, byte);
+    });
+}
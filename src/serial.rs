@@ -1,3 +1,4 @@
+use crate::emergency::{self, SerialWriter};
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
@@ -15,10 +16,15 @@ lazy_static! {
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
     interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
+        let _guard = match emergency::enter() {
+            Some(guard) => guard,
+            None => {
+                let _ = SerialWriter.write_fmt(args);
+                return;
+            }
+        };
+        // writing to the port can't fail, only formatting can
+        let _ = SERIAL1.lock().write_fmt(args);
     });
 }
 
@@ -0,0 +1,248 @@
+pub mod commands;
+pub mod editor;
+
+use crate::task::keyboard::{DecodedKey, KeyCode, KeyEvent, KeyEventStream, KeyState, Modifiers};
+use crate::task::serial::{SerialStream, SerialWriter};
+use crate::vga_buffer::{self, CONSOLE_COUNT, KERNEL_CONSOLE};
+use alloc::{string::String, vec::Vec};
+use commands::CommandError;
+use editor::{Key, LineEditor};
+use futures_util::stream::{self, StreamExt};
+
+const PROMPT: &str = "> ";
+/// Lines Shift+PageUp and Shift+PageDown scroll
+const SCROLL_LINES: isize = 10;
+
+/// Input of either device
+enum Input {
+    Key(KeyEvent),
+    Serial(u8),
+}
+
+/// Turns the bytes a serial terminal sends into keys
+///
+/// Understands the ANSI escape sequences of the cursor keys, home, end and delete.
+#[derive(Default)]
+struct SerialDecoder {
+    state: EscapeState,
+    /// last byte was a carriage return, a line feed after it is no new line
+    carriage_return: bool,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    #[default]
+    None,
+    /// got the escape character
+    Escape,
+    /// got `ESC [`, and the digits of a parameter so far
+    Csi(u8),
+}
+
+impl SerialDecoder {
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        let carriage_return = core::mem::replace(&mut self.carriage_return, byte == b'\r');
+        match (self.state, byte) {
+            (EscapeState::None, 0x1b) => {
+                self.state = EscapeState::Escape;
+                None
+            }
+            (EscapeState::None, b'\r') => Some(Key::Enter),
+            (EscapeState::None, b'\n') if carriage_return => None,
+            (EscapeState::None, b'\n') => Some(Key::Enter),
+            // terminals usually send delete for the backspace key
+            (EscapeState::None, 0x08 | 0x7f) => Some(Key::Backspace),
+            (EscapeState::None, byte @ 0x20..=0x7e) => Some(Key::Char(byte as char)),
+            (EscapeState::None, _) => None,
+            (EscapeState::Escape, b'[' | b'O') => {
+                self.state = EscapeState::Csi(0);
+                None
+            }
+            (EscapeState::Csi(param), digit @ b'0'..=b'9') => {
+                self.state =
+                    EscapeState::Csi(param.saturating_mul(10).saturating_add(digit - b'0'));
+                None
+            }
+            (EscapeState::Csi(param), byte) => {
+                self.state = EscapeState::None;
+                match (byte, param) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'C', _) => Some(Key::Right),
+                    (b'D', _) => Some(Key::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+                    (b'~', 3) => Some(Key::Delete),
+                    _ => None,
+                }
+            }
+            (EscapeState::Escape, _) => {
+                self.state = EscapeState::None;
+                None
+            }
+        }
+    }
+}
+
+fn decode_key(key: DecodedKey) -> Option<Key> {
+    match key {
+        DecodedKey::Unicode('\n') => Some(Key::Enter),
+        DecodedKey::Unicode('\u{8}') => Some(Key::Backspace),
+        DecodedKey::Unicode('\u{7f}') => Some(Key::Delete),
+        DecodedKey::Unicode(c) if !c.is_control() => Some(Key::Char(c)),
+        DecodedKey::RawKey(KeyCode::ArrowUp) => Some(Key::Up),
+        DecodedKey::RawKey(KeyCode::ArrowDown) => Some(Key::Down),
+        DecodedKey::RawKey(KeyCode::ArrowLeft) => Some(Key::Left),
+        DecodedKey::RawKey(KeyCode::ArrowRight) => Some(Key::Right),
+        DecodedKey::RawKey(KeyCode::Home) => Some(Key::Home),
+        DecodedKey::RawKey(KeyCode::End) => Some(Key::End),
+        _ => None,
+    }
+}
+
+/// Switches consoles on Alt+F1..F4 and scrolls on Shift+PageUp/PageDown
+///
+/// Returns whether `event` was one of those keys.
+fn console_key(event: &KeyEvent) -> bool {
+    if event.state != KeyState::Pressed {
+        return false;
+    }
+    let console = match event.code {
+        KeyCode::F1 => 0,
+        KeyCode::F2 => 1,
+        KeyCode::F3 => 2,
+        KeyCode::F4 => 3,
+        KeyCode::PageUp if event.modifiers.shift() => {
+            vga_buffer::scroll(SCROLL_LINES);
+            return true;
+        }
+        KeyCode::PageDown if event.modifiers.shift() => {
+            vga_buffer::scroll(-SCROLL_LINES);
+            return true;
+        }
+        _ => return false,
+    };
+    if !event.modifiers.contains(Modifiers::ALT) {
+        return false;
+    }
+    vga_buffer::switch_console(console);
+    true
+}
+
+/// Shows output on a virtual console, the kernel console also goes to the serial terminal
+struct Console {
+    serial: SerialWriter,
+}
+
+impl Console {
+    async fn write(&mut self, console: usize, s: &str) {
+        vga_buffer::print_to(console, format_args!("{}", s));
+        if console != KERNEL_CONSOLE {
+            return;
+        }
+        // serial terminals need a carriage return before every line feed
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.serial.write(b"\r\n").await;
+            }
+            self.serial.write_str(line).await;
+        }
+    }
+}
+
+/// Runs `line` and returns its output
+fn execute(line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let mut out = String::new();
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (*name, args),
+        None => return out,
+    };
+
+    match commands::find(name) {
+        Some(command) => match (command.run)(args, &mut out) {
+            Ok(()) => {}
+            Err(CommandError::Usage) => out = alloc::format!("usage: {}\n", command.usage),
+            Err(err) => out.push_str(&alloc::format!("{}: {:?}\n", name, err)),
+        },
+        None => out = alloc::format!("unknown command {}, try help\n", name),
+    }
+    out
+}
+
+/// Reads lines from the keyboard and the serial port and runs them as commands
+///
+/// Every virtual console has its own line, the keyboard types into the active
+/// one and the serial terminal into the kernel console. Takes over both input
+/// streams, so it can only be spawned once.
+pub async fn run() {
+    let keys = KeyEventStream::new().map(Input::Key);
+    let serial_input = SerialStream::new().map(Input::Serial);
+    let mut input = stream::select(keys, serial_input);
+
+    let mut serial = SerialDecoder::default();
+    let mut editors: [LineEditor; CONSOLE_COUNT] = core::array::from_fn(|_| LineEditor::new());
+    let mut console = Console {
+        serial: SerialWriter::new(),
+    };
+
+    for index in 0..CONSOLE_COUNT {
+        console
+            .write(index, "kernel shell, type help for the commands\n")
+            .await;
+        console.write(index, PROMPT).await;
+    }
+    while let Some(input) = input.next().await {
+        let (index, key) = match input {
+            Input::Key(event) if console_key(&event) => continue,
+            Input::Key(event) => (vga_buffer::active_console(), event.key.and_then(decode_key)),
+            Input::Serial(byte) => (KERNEL_CONSOLE, serial.decode(byte)),
+        };
+        let key = match key {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let editor = &mut editors[index];
+        match editor.handle(key) {
+            Some(line) => {
+                console.write(index, "\n").await;
+                let output = execute(&line);
+                console.write(index, &output).await;
+                console.write(index, PROMPT).await;
+            }
+            None => {
+                let redraw = editor.render(PROMPT);
+                console.write(index, &redraw).await;
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_serial_escape_sequences() {
+    let mut decoder = SerialDecoder::default();
+    let keys: Vec<Option<Key>> = b"a\x1b[D\x1b[3~\r\n\x7f"
+        .iter()
+        .map(|&byte| decoder.decode(byte))
+        .filter(Option::is_some)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            Some(Key::Char('a')),
+            Some(Key::Left),
+            Some(Key::Delete),
+            Some(Key::Enter),
+            Some(Key::Backspace)
+        ]
+    );
+}
+
+#[test_case]
+fn test_execute_reports_errors() {
+    assert_eq!(execute("echo  a   b"), "a b\n");
+    assert_eq!(execute("peek"), "usage: peek <paddr> [len]\n");
+    assert!(execute("frobnicate").starts_with("unknown command"));
+    assert_eq!(execute("   "), "");
+}
@@ -0,0 +1,350 @@
+use crate::acpi::{self, AcpiError};
+use crate::allocator::{self, HEAP_MAX_SIZE};
+use crate::task::keyboard::{self, Layout};
+use crate::{cpu, log, memory, power, task, thread, time};
+use alloc::string::String;
+use core::fmt::Write;
+use core::num::ParseIntError;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Most bytes `peek` shows at once
+const MAX_PEEK: u64 = 256;
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// the arguments don't match the usage of the command
+    Usage,
+    InvalidNumber(ParseIntError),
+    /// the physical memory mapping doesn't cover the address
+    NotMapped(u64),
+    Acpi(AcpiError),
+}
+
+impl From<ParseIntError> for CommandError {
+    fn from(err: ParseIntError) -> Self {
+        CommandError::InvalidNumber(err)
+    }
+}
+
+impl From<AcpiError> for CommandError {
+    fn from(err: AcpiError) -> Self {
+        CommandError::Acpi(err)
+    }
+}
+
+type CommandResult = Result<(), CommandError>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: fn(&[&str], &mut String) -> CommandResult,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "help",
+        help: "lists the commands",
+        run: help,
+    },
+    Command {
+        name: "mem",
+        usage: "mem",
+        help: "shows heap and frame usage",
+        run: mem,
+    },
+    Command {
+        name: "tasks",
+        usage: "tasks",
+        help: "lists threads and counts async tasks",
+        run: tasks,
+    },
+    Command {
+        name: "uptime",
+        usage: "uptime",
+        help: "shows the time since boot and the date",
+        run: uptime,
+    },
+    Command {
+        name: "dmesg",
+        usage: "dmesg",
+        help: "shows the kernel log",
+        run: dmesg,
+    },
+    Command {
+        name: "echo",
+        usage: "echo [text...]",
+        help: "prints its arguments",
+        run: echo,
+    },
+    Command {
+        name: "peek",
+        usage: "peek <paddr> [len]",
+        help: "dumps physical memory",
+        run: peek,
+    },
+    Command {
+        name: "poke",
+        usage: "poke <paddr> <byte>...",
+        help: "writes bytes to physical memory",
+        run: poke,
+    },
+    Command {
+        name: "pagewalk",
+        usage: "pagewalk <vaddr>",
+        help: "shows the page table entries that map an address",
+        run: pagewalk,
+    },
+    Command {
+        name: "layout",
+        usage: "layout [us|uk|de|dvorak|azerty]",
+        help: "shows or switches the keyboard layout",
+        run: layout,
+    },
+    Command {
+        name: "reboot",
+        usage: "reboot",
+        help: "restarts the machine",
+        run: |_, _| reboot(),
+    },
+    Command {
+        name: "poweroff",
+        usage: "poweroff",
+        help: "turns the machine off through ACPI",
+        run: |_, _| poweroff(),
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Parses a decimal number, or a hexadecimal one starting with `0x`
+fn parse_number(s: &str) -> Result<u64, ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Returns the address of `addr` in the physical memory mapping, if it is mapped
+fn phys_to_mapped(addr: u64) -> Result<VirtAddr, CommandError> {
+    // `memory::phys_to_virt` panics if the sum isn't canonical
+    let virt = PhysAddr::try_new(addr)
+        .ok()
+        .and_then(|_| memory::physical_memory_offset().as_u64().checked_add(addr))
+        .and_then(|virt| VirtAddr::try_new(virt).ok())
+        .ok_or(CommandError::NotMapped(addr))?;
+    memory::with_mapper(|mapper, _| mapper.translate_addr(virt))
+        .map(|_| virt)
+        .ok_or(CommandError::NotMapped(addr))
+}
+
+fn help(_args: &[&str], out: &mut String) -> CommandResult {
+    for command in COMMANDS {
+        writeln!(out, "{:<24} {}", command.usage, command.help).unwrap();
+    }
+    Ok(())
+}
+
+fn mem(_args: &[&str], out: &mut String) -> CommandResult {
+    let heap = allocator::stats();
+    writeln!(
+        out,
+        "heap: {} KiB of {} KiB used, grows up to {} KiB",
+        heap.used / 1024,
+        heap.size / 1024,
+        HEAP_MAX_SIZE / 1024
+    )
+    .unwrap();
+
+    let (used, usable) =
+        memory::with_frame_allocator(|frames| (frames.used_frames(), frames.usable_frames()));
+    writeln!(
+        out,
+        "frames: {} of {} used, {} MiB free",
+        used,
+        usable,
+        (usable - used) * 4096 / (1024 * 1024)
+    )
+    .unwrap();
+    Ok(())
+}
+
+fn tasks(_args: &[&str], out: &mut String) -> CommandResult {
+    writeln!(out, "thread  state    kind").unwrap();
+    for info in thread::list() {
+        let kind = if info.user { "user" } else { "kernel" };
+        let state = alloc::format!("{:?}", info.state);
+        writeln!(out, "{:<7} {:<8} {}", info.id.as_u64(), state, kind).unwrap();
+    }
+    writeln!(
+        out,
+        "{} async tasks on {} cpus",
+        task::count(),
+        cpu::count()
+    )
+    .unwrap();
+    Ok(())
+}
+
+fn uptime(_args: &[&str], out: &mut String) -> CommandResult {
+    let up = time::Instant::now().since_boot();
+    writeln!(
+        out,
+        "up {}.{:03} s, {} ticks, it is {} UTC",
+        up.as_secs(),
+        up.subsec_millis(),
+        time::ticks(),
+        time::now().date_time()
+    )
+    .unwrap();
+    Ok(())
+}
+
+fn dmesg(_args: &[&str], out: &mut String) -> CommandResult {
+    log::dump(out).unwrap();
+    Ok(())
+}
+
+fn echo(args: &[&str], out: &mut String) -> CommandResult {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        out.push_str(arg);
+    }
+    out.push('\n');
+    Ok(())
+}
+
+fn peek(args: &[&str], out: &mut String) -> CommandResult {
+    let (start, len) = match args {
+        [addr] => (parse_number(addr)?, 16),
+        [addr, len] => (parse_number(addr)?, parse_number(len)?.min(MAX_PEEK)),
+        _ => return Err(CommandError::Usage),
+    };
+
+    let end = start.saturating_add(len);
+    for line in (start..end).step_by(16) {
+        write!(out, "{:#012x}:", line).unwrap();
+        for addr in line..line.saturating_add(16).min(end) {
+            let virt = phys_to_mapped(addr)?;
+            let byte = unsafe { virt.as_ptr::<u8>().read_volatile() };
+            write!(out, " {:02x}", byte).unwrap();
+        }
+        out.push('\n');
+    }
+    Ok(())
+}
+
+fn poke(args: &[&str], out: &mut String) -> CommandResult {
+    let (addr, bytes) = match args {
+        [addr, bytes @ ..] if !bytes.is_empty() => (parse_number(addr)?, bytes),
+        _ => return Err(CommandError::Usage),
+    };
+
+    // everything is checked before the first write
+    let mut values = alloc::vec::Vec::new();
+    for (i, byte) in bytes.iter().enumerate() {
+        let value = u8::try_from(parse_number(byte)?).map_err(|_| CommandError::Usage)?;
+        let virt = phys_to_mapped(addr.saturating_add(i as u64))?;
+        values.push((virt, value));
+    }
+    for &(virt, value) in &values {
+        unsafe { virt.as_mut_ptr::<u8>().write_volatile(value) };
+    }
+    writeln!(out, "wrote {} bytes at {:#x}", values.len(), addr).unwrap();
+    Ok(())
+}
+
+fn pagewalk(args: &[&str], out: &mut String) -> CommandResult {
+    let addr = match args {
+        [addr] => VirtAddr::try_new(parse_number(addr)?).map_err(|_| CommandError::Usage)?,
+        _ => return Err(CommandError::Usage),
+    };
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+
+    let (mut table_frame, _) = Cr3::read();
+    for (level, index) in (1..=4).rev().zip(indices) {
+        let table = memory::phys_to_virt(table_frame.start_address());
+        let table = unsafe { &*table.as_ptr::<PageTable>() };
+        let entry = &table[index];
+        writeln!(
+            out,
+            "P{}[{:>3}] {:#012x} {:?}",
+            level,
+            u16::from(index),
+            entry.addr().as_u64(),
+            entry.flags()
+        )
+        .unwrap();
+
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            writeln!(out, "not mapped").unwrap();
+            return Ok(());
+        }
+        if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+            // the offset covers all the levels below
+            let offset = addr.as_u64() & ((1 << (12 + 9 * (level - 1))) - 1);
+            writeln!(out, "-> {:#x}", entry.addr().as_u64() + offset).unwrap();
+            return Ok(());
+        }
+        table_frame = entry.frame().expect("present entry without frame");
+    }
+    Ok(())
+}
+
+fn layout(args: &[&str], out: &mut String) -> CommandResult {
+    match args {
+        [] => writeln!(out, "keyboard layout: {}", keyboard::layout().name()).unwrap(),
+        [name] => {
+            let layout = Layout::from_name(name).ok_or(CommandError::Usage)?;
+            keyboard::set_layout(layout);
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn reboot() -> CommandResult {
+    power::reboot();
+}
+
+fn poweroff() -> CommandResult {
+    acpi::shutdown()?;
+    Ok(())
+}
+
+#[test_case]
+fn test_parse_number() {
+    assert_eq!(parse_number("42"), Ok(42));
+    assert_eq!(parse_number("0xb8000"), Ok(0xb8000));
+    assert!(parse_number("0xg").is_err());
+}
+
+#[test_case]
+fn test_echo_joins_arguments() {
+    let mut out = String::new();
+    echo(&["hello", "world"], &mut out).unwrap();
+    assert_eq!(out, "hello world\n");
+}
+
+#[test_case]
+fn test_peek_at_the_end_of_the_address_space() {
+    let mut out = String::new();
+    let result = peek(&["0xfffffffffffffff0"], &mut out);
+    assert!(matches!(result, Err(CommandError::NotMapped(_))));
+    // a valid physical address, but the mapping of it wouldn't be canonical
+    let result = peek(&["0x8000000000000"], &mut out);
+    assert!(matches!(result, Err(CommandError::NotMapped(_))));
+}
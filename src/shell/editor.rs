@@ -0,0 +1,221 @@
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+
+/// Longest line that still fits on the screen after the prompt
+const MAX_LINE: usize = 72;
+/// Number of lines the history keeps
+const HISTORY_SIZE: usize = 32;
+
+/// Input the line editor understands, decoded from the keyboard or serial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+}
+
+/// Edits one line at a time and remembers the previous ones
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    /// entry of the history that is shown, `None` while editing a new line
+    browsing: Option<usize>,
+    /// new line that was being edited before browsing the history
+    draft: String,
+    /// characters on the screen after the prompt, they have to be overwritten
+    drawn: usize,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            line: Vec::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            browsing: None,
+            draft: String::new(),
+            drawn: 0,
+        }
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Applies `key`, returns the finished line on enter
+    pub fn handle(&mut self, key: Key) -> Option<String> {
+        match key {
+            Key::Char(c) => {
+                if self.line.len() < MAX_LINE {
+                    self.line.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+            }
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+            }
+            Key::Delete => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                }
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.line.len(),
+            Key::Up => self.browse_back(),
+            Key::Down => self.browse_forward(),
+            Key::Enter => return Some(self.finish()),
+        }
+        None
+    }
+
+    fn browse_back(&mut self) {
+        let index = match self.browsing {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.line();
+                self.history.len() - 1
+            }
+        };
+        self.browsing = Some(index);
+        let entry = self.history[index].clone();
+        self.set_line(&entry);
+    }
+
+    fn browse_forward(&mut self) {
+        match self.browsing {
+            Some(index) if index + 1 < self.history.len() => {
+                self.browsing = Some(index + 1);
+                let entry = self.history[index + 1].clone();
+                self.set_line(&entry);
+            }
+            Some(_) => {
+                self.browsing = None;
+                let draft = core::mem::take(&mut self.draft);
+                self.set_line(&draft);
+            }
+            None => {}
+        }
+    }
+
+    fn set_line(&mut self, s: &str) {
+        self.line = s.chars().collect();
+        self.cursor = self.line.len();
+    }
+
+    /// Ends the line, it is added to the history unless it repeats the last one
+    fn finish(&mut self) -> String {
+        let line = self.line();
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && self.history.back().map(String::as_str) != Some(trimmed) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(String::from(trimmed));
+        }
+
+        self.line.clear();
+        self.cursor = 0;
+        self.browsing = None;
+        self.draft.clear();
+        self.drawn = 0;
+        line
+    }
+
+    /// Returns the text that redraws the line behind `prompt`
+    ///
+    /// Only needs carriage returns, so it works on the screen and on a serial
+    /// terminal. Leaves the cursor at the editing position.
+    pub fn render(&mut self, prompt: &str) -> String {
+        let mut out = String::from("\r");
+        out.push_str(prompt);
+        out.extend(self.line.iter());
+        // blank out what is left of a longer line
+        for _ in self.line.len()..self.drawn {
+            out.push(' ');
+        }
+        out.push('\r');
+        out.push_str(prompt);
+        out.extend(self.line[..self.cursor].iter());
+        self.drawn = self.line.len();
+        out
+    }
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+fn type_str(editor: &mut LineEditor, s: &str) {
+    for c in s.chars() {
+        editor.handle(Key::Char(c));
+    }
+}
+
+#[test_case]
+fn test_editing_in_the_middle() {
+    let mut editor = LineEditor::new();
+    type_str(&mut editor, "helo");
+    editor.handle(Key::Left);
+    type_str(&mut editor, "l");
+    assert_eq!(editor.line(), "hello");
+    assert_eq!(editor.cursor(), 4);
+
+    editor.handle(Key::Home);
+    editor.handle(Key::Delete);
+    editor.handle(Key::End);
+    editor.handle(Key::Backspace);
+    assert_eq!(editor.handle(Key::Enter).as_deref(), Some("ell"));
+    assert_eq!(editor.line(), "");
+}
+
+#[test_case]
+fn test_history() {
+    let mut editor = LineEditor::new();
+    for line in ["mem", "uptime", "uptime", ""] {
+        type_str(&mut editor, line);
+        editor.handle(Key::Enter);
+    }
+    type_str(&mut editor, "he");
+
+    editor.handle(Key::Up);
+    assert_eq!(editor.line(), "uptime");
+    editor.handle(Key::Up);
+    assert_eq!(editor.line(), "mem");
+    // the oldest entry stays
+    editor.handle(Key::Up);
+    assert_eq!(editor.line(), "mem");
+    editor.handle(Key::Down);
+    editor.handle(Key::Down);
+    assert_eq!(editor.line(), "he");
+}
+
+#[test_case]
+fn test_render_blanks_removed_characters() {
+    let mut editor = LineEditor::new();
+    type_str(&mut editor, "abc");
+    assert_eq!(editor.render("> "), "\r> abc\r> abc");
+    editor.handle(Key::Backspace);
+    editor.handle(Key::Left);
+    assert_eq!(editor.render("> "), "\r> ab \r> a");
+}
@@ -0,0 +1,189 @@
+mod trampoline;
+
+use crate::{
+    acpi, cpu, gdt,
+    interrupts::{self, apic},
+    memory::{self, stack::KernelStack},
+    task::executor::Executor,
+    thread,
+    time::{Duration, Instant},
+    warn,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use trampoline::Trampoline;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Size of the stack the executor of an application processor runs on
+const STACK_SIZE: u64 = 4096 * 8;
+/// Size of the double fault and page fault stacks of an application processor
+const IST_STACK_SIZE: u64 = 4096 * 2;
+/// Startup IPIs can only point below 1 MiB
+const TRAMPOLINE_LIMIT: u64 = 0x10_0000;
+/// How long a cpu gets to come up after every startup IPI
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// interrupts still go through the 8259 PIC
+    NoApic,
+    NoMadt,
+    /// no free frame below 1 MiB for the trampoline
+    NoLowMemory,
+    Map(MapToError<Size4KiB>),
+    /// the cpu didn't come up after the startup IPIs
+    Timeout,
+}
+
+impl From<MapToError<Size4KiB>> for SmpError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        SmpError::Map(err)
+    }
+}
+
+/// What an application processor needs to set itself up, passed to `ap_entry`
+struct ApBoot {
+    index: usize,
+    double_fault_stack: VirtAddr,
+    page_fault_stack: VirtAddr,
+    /// set by the cpu once it doesn't need the trampoline and this anymore
+    started: AtomicBool,
+}
+
+/// Stacks of the running application processors, they are never freed
+static STACKS: Mutex<Vec<KernelStack>> = Mutex::new(Vec::new());
+
+/// Starts all application processors listed in the MADT
+///
+/// Every processor gets its own GDT, TSS and per-cpu block and runs an executor
+/// that takes tasks spawned with `task::spawn`. Returns the number of running
+/// cpus. Needs `apic::init` and `thread::init`, the start up sleeps.
+pub fn init() -> Result<usize, SmpError> {
+    if !apic::is_enabled() {
+        return Err(SmpError::NoApic);
+    }
+    let madt = acpi::get()
+        .and_then(|acpi| acpi.madt())
+        .ok_or(SmpError::NoMadt)?;
+
+    let frame = memory::with_frame_allocator(|frames| {
+        frames.allocate_below(PhysAddr::new(TRAMPOLINE_LIMIT))
+    })
+    .ok_or(SmpError::NoLowMemory)?;
+    // the trampoline runs at its physical address when it enables paging
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+    let mapped = memory::with_mapper(|mapper, frames| {
+        match unsafe { mapper.identity_map(frame, PageTableFlags::PRESENT, frames) } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(true)
+            }
+            // the bootloader might identity map low memory already
+            Err(MapToError::PageAlreadyMapped(existing)) if existing == frame => Ok(false),
+            Err(err) => Err(err),
+        }
+    })?;
+    let mut trampoline = Trampoline::new(frame);
+
+    let bsp = cpu::current().apic_id();
+    let mut index = 1;
+    let mut all_started = true;
+    for processor in madt
+        .processors
+        .iter()
+        .filter(|p| p.enabled && p.apic_id != bsp)
+    {
+        match start_ap(&mut trampoline, index, processor.apic_id) {
+            Ok(()) => index += 1,
+            Err(err) => {
+                warn!(
+                    "cpu with APIC id {} did not start: {:?}",
+                    processor.apic_id, err
+                );
+                all_started = false;
+            }
+        }
+    }
+
+    // a cpu that timed out might still run the trampoline later
+    if all_started {
+        memory::with_mapper(|mapper, frames| {
+            if mapped {
+                let (_, flush) = mapper.unmap(page).expect("trampoline is not mapped");
+                flush.flush();
+            }
+            unsafe { frames.deallocate_frame(trampoline.frame()) };
+        });
+    }
+
+    Ok(cpu::count())
+}
+
+/// Sends INIT and startup IPIs to the cpu with `apic_id` and waits until it runs
+fn start_ap(trampoline: &mut Trampoline, index: usize, apic_id: u8) -> Result<(), SmpError> {
+    let stack = KernelStack::new(STACK_SIZE)?;
+    let double_fault_stack = KernelStack::new(IST_STACK_SIZE)?;
+    let page_fault_stack = KernelStack::new(IST_STACK_SIZE)?;
+    let boot = Box::new(ApBoot {
+        index,
+        double_fault_stack: double_fault_stack.top(),
+        page_fault_stack: page_fault_stack.top(),
+        started: AtomicBool::new(false),
+    });
+    trampoline.set_entry(stack.top(), ap_entry, &*boot as *const ApBoot as u64);
+
+    apic::send_init(apic_id);
+    thread::sleep(Duration::from_millis(10));
+    // the second startup IPI is only needed by some older cpus
+    for _ in 0..2 {
+        apic::send_startup(apic_id, trampoline.page());
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if boot.started.load(Ordering::Acquire) {
+                STACKS
+                    .lock()
+                    .extend([stack, double_fault_stack, page_fault_stack]);
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // the cpu might still come up and use them
+    core::mem::forget((stack, double_fault_stack, page_fault_stack));
+    Box::leak(boot);
+    Err(SmpError::Timeout)
+}
+
+/// Called by the trampoline on the new stack with interrupts disabled
+extern "C" fn ap_entry(boot: u64) -> ! {
+    let boot = unsafe { &*(boot as *const ApBoot) };
+    gdt::init_ap(boot.double_fault_stack, boot.page_fault_stack);
+    interrupts::init_idt();
+    cpu::init_ap(boot.index);
+    apic::init_local();
+    boot.started.store(true, Ordering::Release);
+
+    x86_64::instructions::interrupts::enable();
+    Executor::new().run();
+}
+
+/// Gets the cpu with `apic_id` out of `hlt` unless it is the current one
+pub(crate) fn wake(apic_id: u8) {
+    if apic::is_enabled() && cpu::count() > 1 && apic_id != cpu::current().apic_id() {
+        apic::send_ipi(apic_id, apic::WAKEUP_VECTOR);
+    }
+}
+
+/// Gets all other cpus out of `hlt`
+pub(crate) fn wake_all() {
+    if apic::is_enabled() && cpu::count() > 1 {
+        apic::broadcast_ipi(apic::WAKEUP_VECTOR);
+    }
+}
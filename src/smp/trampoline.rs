@@ -0,0 +1,186 @@
+use crate::memory::phys_to_virt;
+use core::arch::global_asm;
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr3, Cr4},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+// Started by the startup IPI in real mode with cs = page * 256 and ip = 0, so
+// everything is addressed relative to the start of the page. It switches from
+// real mode directly to long mode with the page table and control registers of
+// the bootstrap processor and calls the entry function on the given stack. The
+// fields after the code are patched by `Trampoline` before every start.
+global_asm!(
+    r#"
+    .pushsection .rodata.ap_trampoline, "a"
+    .code16
+    .global ap_trampoline_start
+ap_trampoline_start:
+    cli
+    cld
+    mov %cs, %ax
+    mov %ax, %ds
+
+    lgdtl (ap_trampoline_gdtr - ap_trampoline_start)
+    movl (ap_trampoline_cr4 - ap_trampoline_start), %eax
+    mov %eax, %cr4
+    movl (ap_trampoline_cr3 - ap_trampoline_start), %eax
+    mov %eax, %cr3
+    movl (ap_trampoline_efer - ap_trampoline_start), %eax
+    xor %edx, %edx
+    mov $0xc0000080, %ecx
+    wrmsr
+    # enabling protection and paging at once activates long mode
+    movl (ap_trampoline_cr0 - ap_trampoline_start), %eax
+    mov %eax, %cr0
+    ljmpl *(ap_trampoline_long_mode_jump - ap_trampoline_start)
+
+    .code64
+    .global ap_trampoline_long_mode
+ap_trampoline_long_mode:
+    mov $0x10, %ax
+    mov %ax, %ds
+    mov %ax, %es
+    mov %ax, %ss
+    xor %eax, %eax
+    mov %ax, %fs
+    mov %ax, %gs
+    mov ap_trampoline_stack(%rip), %rsp
+    mov ap_trampoline_argument(%rip), %rdi
+    mov ap_trampoline_entry(%rip), %rax
+    call *%rax
+    ud2
+
+    .align 16
+    .global ap_trampoline_gdt
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00af9a000000ffff  # 64 bit code
+    .quad 0x00cf92000000ffff  # data
+    .global ap_trampoline_gdtr
+ap_trampoline_gdtr:
+    .word ap_trampoline_gdtr - ap_trampoline_gdt - 1
+    .long 0                   # physical address of the gdt
+    .global ap_trampoline_long_mode_jump
+ap_trampoline_long_mode_jump:
+    .long 0                   # physical address of ap_trampoline_long_mode
+    .word 0x08
+    .align 4
+    .global ap_trampoline_cr0
+ap_trampoline_cr0:
+    .long 0
+    .global ap_trampoline_cr3
+ap_trampoline_cr3:
+    .long 0
+    .global ap_trampoline_cr4
+ap_trampoline_cr4:
+    .long 0
+    .global ap_trampoline_efer
+ap_trampoline_efer:
+    .long 0
+    .align 8
+    .global ap_trampoline_stack
+ap_trampoline_stack:
+    .quad 0
+    .global ap_trampoline_argument
+ap_trampoline_argument:
+    .quad 0
+    .global ap_trampoline_entry
+ap_trampoline_entry:
+    .quad 0
+    .global ap_trampoline_end
+ap_trampoline_end:
+    .popsection
+"#,
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_gdtr: u8;
+    static ap_trampoline_long_mode_jump: u8;
+    static ap_trampoline_cr0: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_cr4: u8;
+    static ap_trampoline_efer: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_argument: u8;
+    static ap_trampoline_entry: u8;
+}
+
+/// Function an application processor calls once it reached long mode
+pub type ApEntry = extern "C" fn(u64) -> !;
+
+/// Copy of the trampoline in a page below 1 MiB
+pub struct Trampoline {
+    frame: PhysFrame,
+}
+
+/// Offset of a trampoline symbol from the start of the trampoline
+fn offset(symbol: *const u8) -> u64 {
+    symbol as u64 - (&raw const ap_trampoline_start) as u64
+}
+
+impl Trampoline {
+    /// Copies the trampoline to `frame`
+    ///
+    /// The frame has to be below 1 MiB and identity mapped, the startup IPI can
+    /// only start cpus there and the trampoline runs at that address when it
+    /// enables paging.
+    pub fn new(frame: PhysFrame) -> Self {
+        let start = &raw const ap_trampoline_start;
+        let len = offset(&raw const ap_trampoline_end) as usize;
+        assert!(len <= 4096, "AP trampoline does not fit into a page");
+        assert!(frame.start_address().as_u64() < 0x10_0000);
+        unsafe {
+            let dest = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+            core::ptr::copy_nonoverlapping(start, dest, len);
+        }
+
+        // the real mode part can only load 32 bit values
+        let (level_4_table, _) = Cr3::read();
+        let cr3 = level_4_table.start_address().as_u64();
+        assert!(cr3 < 1 << 32, "kernel page table is above 4 GiB");
+        let efer = Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits();
+
+        let trampoline = Trampoline { frame };
+        let base = frame.start_address().as_u64();
+        let gdt = base + offset(&raw const ap_trampoline_gdt);
+        let long_mode = base + offset(&raw const ap_trampoline_long_mode);
+        trampoline.write(&raw const ap_trampoline_gdtr, 2, gdt as u32);
+        trampoline.write(&raw const ap_trampoline_long_mode_jump, 0, long_mode as u32);
+        trampoline.write(&raw const ap_trampoline_cr0, 0, Cr0::read_raw() as u32);
+        trampoline.write(&raw const ap_trampoline_cr3, 0, cr3 as u32);
+        trampoline.write(&raw const ap_trampoline_cr4, 0, Cr4::read_raw() as u32);
+        trampoline.write(&raw const ap_trampoline_efer, 0, efer as u32);
+        trampoline
+    }
+
+    /// Sets the stack and the function the next cpu started through the trampoline runs
+    pub fn set_entry(&mut self, stack_top: VirtAddr, entry: ApEntry, argument: u64) {
+        self.write(&raw const ap_trampoline_stack, 0, stack_top.as_u64());
+        self.write(&raw const ap_trampoline_argument, 0, argument);
+        self.write(&raw const ap_trampoline_entry, 0, entry as usize as u64);
+    }
+
+    /// Page number passed in the startup IPI
+    pub fn page(&self) -> u8 {
+        (self.frame.start_address().as_u64() / 4096) as u8
+    }
+
+    pub fn frame(&self) -> PhysFrame {
+        self.frame
+    }
+
+    fn write<T>(&self, symbol: *const u8, field_offset: u64, value: T) {
+        let addr = self.frame.start_address() + offset(symbol) + field_offset;
+        unsafe { phys_to_virt(addr).as_mut_ptr::<T>().write_unaligned(value) };
+    }
+}
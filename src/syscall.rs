@@ -0,0 +1,211 @@
+use crate::{
+    gdt, print, serial_print,
+    thread::{self, ExitStatus},
+    time::Duration,
+};
+use core::arch::asm;
+use x86_64::{
+    instructions::interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::paging::PageTableFlags,
+    VirtAddr,
+};
+
+/// Syscall numbers, passed in rax
+///
+/// Arguments go in rdi, rsi, rdx, r10 and r8, the result is returned in rax.
+/// Negative results are errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Syscall {
+    /// write(fd, buf, len) -> written bytes
+    Write = 0,
+    /// exit(code) -> !
+    Exit = 1,
+    /// yield() -> 0
+    Yield = 2,
+    /// sleep(milliseconds) -> 0
+    Sleep = 3,
+    /// mmap(addr, len, prot) -> mapped address
+    Mmap = 4,
+}
+
+pub const EBADF: i64 = -9;
+pub const ENOMEM: i64 = -12;
+pub const EFAULT: i64 = -14;
+pub const EINVAL: i64 = -22;
+pub const ENOSYS: i64 = -38;
+
+/// `prot` bits of `mmap`
+pub const PROT_WRITE: u64 = 1 << 1;
+pub const PROT_EXEC: u64 = 1 << 2;
+
+type SyscallHandler = fn(u64, u64, u64, u64, u64) -> i64;
+
+/// Indexed by the syscall number
+static SYSCALL_TABLE: [SyscallHandler; 5] = [sys_write, sys_exit, sys_yield, sys_sleep, sys_mmap];
+
+/// Top of the stack `syscall_entry` switches to, see `set_kernel_stack`
+static mut KERNEL_STACK_TOP: u64 = 0;
+/// Scratch space for the user stack pointer during the stack switch
+static mut USER_STACK_POINTER: u64 = 0;
+
+/// Enables the SYSCALL instruction
+///
+/// Requires the GDT from `gdt::init`
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .expect("GDT layout does not fit SYSCALL/SYSRET");
+    LStar::write(VirtAddr::new(syscall_entry as usize as u64));
+    // entered with interrupts disabled, until we are on the kernel stack
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+
+    set_kernel_stack(gdt::kernel_stack());
+}
+
+/// Sets the stack syscalls run on, called on every context switch
+pub(crate) fn set_kernel_stack(stack_top: VirtAddr) {
+    unsafe { KERNEL_STACK_TOP = stack_top.as_u64() };
+}
+
+/// Target of the SYSCALL instruction
+///
+/// rcx holds the user rip and r11 the user rflags, both are needed for SYSRET.
+/// GS points to the per-cpu block while in the kernel, see `cpu`.
+#[naked]
+unsafe extern "C" fn syscall_entry() -> ! {
+    asm!(
+        "
+        swapgs
+        mov [rip + {user_rsp}], rsp
+        mov rsp, [rip + {kernel_rsp}]
+        push qword ptr [rip + {user_rsp}]
+        push rcx
+        push r11
+        sub rsp, 8 // align stack pointer (3*8 pushed)
+
+        // syscall abi -> C abi
+        mov r9, r8
+        mov r8, r10
+        mov rcx, rdx
+        mov rdx, rsi
+        mov rsi, rdi
+        mov rdi, rax
+        call {dispatch}
+
+        // don't leak kernel values in the scratch registers, rcx and r11 are restored
+        // below and the callee-saved ones hold the user values again
+        xor edx, edx
+        xor esi, esi
+        xor edi, edi
+        xor r8d, r8d
+        xor r9d, r9d
+        xor r10d, r10d
+
+        // don't get interrupted on the user stack
+        cli
+        add rsp, 8 // undo alignment
+        pop r11
+        pop rcx
+        pop rsp
+        swapgs
+        sysretq",
+        user_rsp = sym USER_STACK_POINTER,
+        kernel_rsp = sym KERNEL_STACK_TOP,
+        dispatch = sym dispatch,
+        options(noreturn)
+    );
+}
+
+extern "C" fn dispatch(number: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> i64 {
+    // we are on the thread's kernel stack, so it is fine to be preempted
+    interrupts::enable();
+
+    match SYSCALL_TABLE.get(number as usize) {
+        Some(handler) => handler(arg0, arg1, arg2, arg3, arg4),
+        None => ENOSYS,
+    }
+}
+
+fn sys_write(fd: u64, buf: u64, len: u64, _: u64, _: u64) -> i64 {
+    // the lock has to be released before touching the buffer, which can page fault
+    let accessible = thread::address_space().map_or(false, |address_space| {
+        let mut address_space = address_space.lock();
+        address_space.is_accessible(VirtAddr::new_truncate(buf), len, false)
+    });
+    if !accessible {
+        return EFAULT;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, len as usize) };
+    let s = match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(_) => return EINVAL,
+    };
+
+    match fd {
+        1 => {
+            print!("{}", s);
+        }
+        2 => {
+            serial_print!("{}", s);
+        }
+        _ => return EBADF,
+    }
+    len as i64
+}
+
+fn sys_exit(code: u64, _: u64, _: u64, _: u64, _: u64) -> i64 {
+    thread::exit_with(ExitStatus::Exited(code));
+}
+
+fn sys_yield(_: u64, _: u64, _: u64, _: u64, _: u64) -> i64 {
+    thread::yield_now();
+    0
+}
+
+fn sys_sleep(milliseconds: u64, _: u64, _: u64, _: u64, _: u64) -> i64 {
+    thread::sleep(Duration::from_millis(milliseconds));
+    0
+}
+
+/// Reserves zeroed memory in the address space of the calling thread
+///
+/// Frames are mapped lazily on the first access. `addr` is only a hint and
+/// currently ignored.
+fn sys_mmap(_addr: u64, len: u64, prot: u64, _: u64, _: u64) -> i64 {
+    if len == 0 {
+        return EINVAL;
+    }
+    let address_space = match thread::address_space() {
+        Some(address_space) => address_space,
+        None => return EINVAL,
+    };
+    let mut address_space = address_space.lock();
+
+    let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let start = match address_space.reserve_mmap(len) {
+        Some(start) => start,
+        None => return ENOMEM,
+    };
+    match address_space.reserve(start, len, flags) {
+        Ok(()) => start.as_u64() as i64,
+        Err(_) => ENOMEM,
+    }
+}
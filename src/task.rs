@@ -1,22 +1,31 @@
 pub mod executor;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial;
 pub mod simple_executor;
+pub mod timer;
 
 use alloc::boxed::Box;
 use core::task::{Context, Poll};
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
 };
+use crossbeam_queue::SegQueue;
+use lazy_static::lazy_static;
 
 pub struct Task {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
+/// Tasks that were created and not dropped yet, on all executors
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        LIVE_TASKS.fetch_add(1, Ordering::Relaxed);
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
@@ -28,6 +37,17 @@ impl Task {
     }
 }
 
+impl Drop for Task {
+    fn drop(&mut self) {
+        LIVE_TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of tasks that haven't finished yet
+pub fn count() -> usize {
+    LIVE_TASKS.load(Ordering::Relaxed)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
@@ -37,3 +57,28 @@ impl TaskId {
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
+
+/// A task whose future is `Send`, so it can move to the executor of another cpu
+struct SharedTask(Task);
+
+// only created by `spawn`, which requires a `Send` future
+unsafe impl Send for SharedTask {}
+
+lazy_static! {
+    /// Tasks spawned with `spawn`, each runs on the executor that takes it first
+    static ref SHARED_TASKS: SegQueue<SharedTask> = SegQueue::new();
+}
+
+/// Spawns a task that the executor of any cpu can run
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    SHARED_TASKS.push(SharedTask(Task::new(future)));
+    crate::smp::wake_all();
+}
+
+fn take_shared() -> Option<Task> {
+    SHARED_TASKS.pop().map(|shared| shared.0)
+}
+
+fn has_shared() -> bool {
+    !SHARED_TASKS.is_empty()
+}
@@ -1,4 +1,5 @@
 use super::{Task, TaskId};
+use crate::{cpu, smp};
 use alloc::task::Wake;
 use alloc::{collections::BTreeMap, sync::Arc};
 use core::task::{Context, Poll, Waker};
@@ -29,6 +30,7 @@ impl Executor {
 
     pub fn run(&mut self) -> ! {
         loop {
+            self.take_shared_task();
             self.run_ready_tasks();
 			self.sleep_if_idle();
         }
@@ -36,13 +38,22 @@ impl Executor {
 
     fn sleep_if_idle(&self) {
 		interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.task_queue.is_empty() && !super::has_shared() {
             enable_and_hlt();
         } else {
 			interrupts::enable();
 		}
     }
 
+    /// Moves a task spawned with `task::spawn` to this executor
+    ///
+    /// Only one at a time, so the executors of the other cpus get their share.
+    fn take_shared_task(&mut self) {
+        if let Some(task) = super::take_shared() {
+            self.spawn(task);
+        }
+    }
+
     fn run_ready_tasks(&mut self) {
         let Self {
             tasks,
@@ -74,6 +85,8 @@ impl Executor {
 struct TaskWaker {
     task_id: TaskId,
     task_queue: Arc<ArrayQueue<TaskId>>,
+    /// APIC id of the cpu the executor runs on, it might be halted
+    cpu: u8,
 }
 
 impl TaskWaker {
@@ -81,10 +94,12 @@ impl TaskWaker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
             task_queue,
+            cpu: cpu::current().apic_id(),
         }))
     }
     fn wake_task(&self) {
         self.task_queue.push(self.task_id).expect("task_queue full");
+        smp::wake(self.cpu);
     }
 }
 
@@ -1,8 +1,11 @@
-use crate::{print, println};
+use crate::time::{Duration, Instant};
+use crate::{print, ps2, warn};
+use bitflags::bitflags;
 use conquer_once::spin::OnceCell;
 use core::{
     iter::Scan,
     pin::Pin,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
     task::{Context, Poll},
 };
 use crossbeam_queue::ArrayQueue;
@@ -10,10 +13,14 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{layouts, layouts::AnyLayout, HandleControl, Keyboard, ScancodeSet1};
+
+pub use pc_keyboard::{DecodedKey, KeyCode};
 
 static WAKER: AtomicWaker = AtomicWaker::new();
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Scancodes the interrupt handler dropped, the stream reports them
+static DROPPED: AtomicU64 = AtomicU64::new(0);
 
 pub struct ScancodeStream {
     _private: (),
@@ -33,12 +40,16 @@ impl Stream for ScancodeStream {
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
         let queue = SCANCODE_QUEUE.try_get().expect("not initialized");
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("dropped {} scancodes before they were read", dropped);
+        }
 
         if let Some(scancode) = queue.pop() {
             return Poll::Ready(Some(scancode));
         }
 
-        WAKER.register(&cx.waker());
+        WAKER.register(cx.waker());
         match queue.pop() {
             Some(scancode) => {
                 WAKER.take();
@@ -52,33 +63,437 @@ impl Stream for ScancodeStream {
 /// Called by keyboard interrupt handler
 /// Not allowed to allocate or block
 pub(crate) fn add_scancode(scancode: u8) {
-    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
-        if let Err(_) = queue.push(scancode) {
-            println!("WARNING: scancode queue full; dropped keyboard input");
-        } else {
-            WAKER.wake();
+    match SCANCODE_QUEUE.try_get() {
+        Ok(queue) if queue.push(scancode).is_ok() => WAKER.wake(),
+        // logging here could print from the interrupt handler
+        _ => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
         }
-    } else {
-        println!("WARNING: scancode queue uninitialized");
     }
 }
 
-pub async fn print_keypresses() {
-    let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(
-        ScancodeSet1::new(),
-        layouts::Us104Key,
-        HandleControl::Ignore,
-    );
+// bytes the keyboard answers commands with, they arrive like scancodes
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const SET_LEDS: u8 = 0xed;
+/// How long the keyboard gets to answer a byte of the set LEDs command
+const LED_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Keyboard layouts `KeyEventStream` can decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us,
+    Uk,
+    German,
+    Dvorak,
+    Azerty,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 5] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::German,
+        Layout::Dvorak,
+        Layout::Azerty,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::German => "de",
+            Layout::Dvorak => "dvorak",
+            Layout::Azerty => "azerty",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL.into_iter().find(|layout| layout.name() == name)
+    }
+
+    fn keyboard(&self) -> Keyboard<AnyLayout, ScancodeSet1> {
+        let layout = match self {
+            Layout::Us => AnyLayout::Us104Key(layouts::Us104Key),
+            Layout::Uk => AnyLayout::Uk105Key(layouts::Uk105Key),
+            Layout::German => AnyLayout::De105Key(layouts::De105Key),
+            Layout::Dvorak => AnyLayout::Dvorak104Key(layouts::Dvorak104Key),
+            Layout::Azerty => AnyLayout::Azerty(layouts::Azerty),
+        };
+        Keyboard::new(ScancodeSet1::new(), layout, HandleControl::Ignore)
+    }
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+/// Switches the layout, key events decoded afterwards use it
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+pub fn layout() -> Layout {
+    let value = LAYOUT.load(Ordering::Relaxed);
+    Layout::ALL
+        .into_iter()
+        .find(|layout| *layout as u8 == value)
+        .expect("invalid layout")
+}
+
+bitflags! {
+    /// Modifier keys that are held and lock keys that are on
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Modifiers: u16 {
+        const LEFT_SHIFT = 1 << 0;
+        const RIGHT_SHIFT = 1 << 1;
+        const LEFT_CTRL = 1 << 2;
+        const RIGHT_CTRL = 1 << 3;
+        const ALT = 1 << 4;
+        const ALT_GR = 1 << 5;
+        const CAPS_LOCK = 1 << 6;
+        const NUM_LOCK = 1 << 7;
+        const SCROLL_LOCK = 1 << 8;
+    }
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.intersects(Modifiers::LEFT_SHIFT | Modifiers::RIGHT_SHIFT)
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.intersects(Modifiers::LEFT_CTRL | Modifiers::RIGHT_CTRL)
+    }
+
+    /// Bits of the set LEDs command
+    fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.contains(Modifiers::SCROLL_LOCK) {
+            leds |= 1 << 0;
+        }
+        if self.contains(Modifiers::NUM_LOCK) {
+            leds |= 1 << 1;
+        }
+        if self.contains(Modifiers::CAPS_LOCK) {
+            leds |= 1 << 2;
+        }
+        leds
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Pressed,
+    Released,
+}
+
+/// A key going down or up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// modifiers after the event was applied
+    pub modifiers: Modifiers,
+    /// what the key means in the current layout, only for presses
+    pub key: Option<DecodedKey>,
+}
+
+/// Progress of the set LEDs command, the keyboard acknowledges each of its two bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedCommand {
+    Idle,
+    /// sent `SET_LEDS`, the LED bits follow once it is acknowledged
+    Started,
+    /// sent these LED bits
+    Sent(u8),
+}
+
+/// Keeps the keyboard LEDs in sync, one command at a time
+struct Leds {
+    command: LedCommand,
+    /// when the last byte was sent, so a lost answer doesn't block later updates
+    sent_at: Instant,
+}
+
+impl Leds {
+    fn new() -> Self {
+        Leds {
+            command: LedCommand::Idle,
+            sent_at: Instant::now(),
+        }
+    }
+
+    /// Starts setting the LEDs, a command on its way sends the latest bits once acknowledged
+    fn update(&mut self) {
+        let lost = Instant::now() >= self.sent_at + LED_TIMEOUT;
+        if self.command == LedCommand::Idle || lost {
+            self.send(SET_LEDS, LedCommand::Started);
+        }
+    }
+
+    /// Continues the command with the answer of the keyboard
+    fn answer(&mut self, response: u8, leds: u8) {
+        match (self.command, response) {
+            (LedCommand::Started, ACK) => self.send(leds, LedCommand::Sent(leds)),
+            (LedCommand::Started, RESEND) => self.send(SET_LEDS, LedCommand::Started),
+            (LedCommand::Sent(sent), ACK) => {
+                self.command = LedCommand::Idle;
+                // a lock key was pressed in the meantime
+                if sent != leds {
+                    self.update();
+                }
+            }
+            (LedCommand::Sent(sent), RESEND) => self.send(sent, LedCommand::Sent(sent)),
+            _ => {}
+        }
+    }
+
+    fn send(&mut self, byte: u8, command: LedCommand) {
+        ps2::write_keyboard(byte);
+        self.command = command;
+        self.sent_at = Instant::now();
+    }
+}
+
+/// Turns scancodes into `KeyEvent`s and tracks the modifiers, without touching the keyboard
+struct Decoder {
+    keyboard: Keyboard<AnyLayout, ScancodeSet1>,
+    layout: Layout,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    fn new(layout: Layout) -> Self {
+        Decoder {
+            keyboard: layout.keyboard(),
+            layout,
+            // matches the state `pc_keyboard` starts with
+            modifiers: Modifiers::NUM_LOCK,
+        }
+    }
+
+    /// Starts decoding with a new layout, keeping caps and num lock
+    fn switch_layout(&mut self, layout: Layout) {
+        self.keyboard = layout.keyboard();
+        self.layout = layout;
+        // the new decoder starts with caps lock off and num lock on
+        let caps_lock = self.modifiers.contains(Modifiers::CAPS_LOCK);
+        let num_lock = self.modifiers.contains(Modifiers::NUM_LOCK);
+        let toggles = [
+            (KeyCode::CapsLock, caps_lock),
+            (KeyCode::NumpadLock, !num_lock),
+        ];
+        for (code, toggle) in toggles {
+            if toggle {
+                let event = pc_keyboard::KeyEvent::new(code, pc_keyboard::KeyState::Down);
+                self.keyboard.process_keyevent(event);
+            }
+        }
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, pressed: bool) {
+        let held = match code {
+            KeyCode::LShift => Modifiers::LEFT_SHIFT,
+            KeyCode::RShift => Modifiers::RIGHT_SHIFT,
+            KeyCode::LControl => Modifiers::LEFT_CTRL,
+            KeyCode::RControl => Modifiers::RIGHT_CTRL,
+            KeyCode::LAlt => Modifiers::ALT,
+            KeyCode::RAltGr => Modifiers::ALT_GR,
+            KeyCode::CapsLock | KeyCode::NumpadLock | KeyCode::ScrollLock => {
+                if pressed {
+                    let lock = match code {
+                        KeyCode::CapsLock => Modifiers::CAPS_LOCK,
+                        KeyCode::NumpadLock => Modifiers::NUM_LOCK,
+                        _ => Modifiers::SCROLL_LOCK,
+                    };
+                    self.modifiers.toggle(lock);
+                }
+                return;
+            }
+            _ => return,
+        };
+        self.modifiers.set(held, pressed);
+    }
+
+    /// Decodes the next scancode with `layout`, `None` until a key event is complete
+    fn decode(&mut self, scancode: u8, layout: Layout) -> Option<KeyEvent> {
+        if layout != self.layout {
+            self.switch_layout(layout);
+        }
+
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        let pressed = event.state != pc_keyboard::KeyState::Up;
+        let code = event.code;
+        self.update_modifiers(code, pressed);
+        let key = self.keyboard.process_keyevent(event);
+        Some(KeyEvent {
+            code,
+            state: if pressed {
+                KeyState::Pressed
+            } else {
+                KeyState::Released
+            },
+            modifiers: self.modifiers,
+            key: key.filter(|_| pressed),
+        })
+    }
+}
+
+/// Decodes the scancodes of `ScancodeStream` into `KeyEvent`s
+///
+/// Uses the layout set with `set_layout` and keeps the keyboard LEDs in sync
+/// with the lock keys.
+pub struct KeyEventStream {
+    scancodes: ScancodeStream,
+    decoder: Decoder,
+    leds: Leds,
+}
+
+impl KeyEventStream {
+    pub fn new() -> Self {
+        // the queue has to exist before the keyboard answers
+        let mut stream = KeyEventStream {
+            scancodes: ScancodeStream::new(),
+            decoder: Decoder::new(layout()),
+            leds: Leds::new(),
+        };
+        stream.leds.update();
+        stream
+    }
+
+    fn handle(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let leds = self.decoder.modifiers.leds();
+        if scancode == ACK || scancode == RESEND {
+            self.leds.answer(scancode, leds);
+            return None;
+        }
+        let event = self.decoder.decode(scancode, layout())?;
+        if event.modifiers.leds() != leds {
+            self.leds.update();
+        }
+        Some(event)
+    }
+}
+
+impl Default for KeyEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        let this = self.get_mut();
+        loop {
+            match Pin::new(&mut this.scancodes).poll_next(cx) {
+                Poll::Ready(Some(scancode)) => {
+                    if let Some(event) = this.handle(scancode) {
+                        return Poll::Ready(Some(event));
+                    }
                 }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
             }
         }
     }
 }
+
+pub async fn print_keypresses() {
+    let mut events = KeyEventStream::new();
+
+    while let Some(event) = events.next().await {
+        match event.key {
+            Some(DecodedKey::Unicode(character)) => print!("{}", character),
+            Some(DecodedKey::RawKey(key)) => print!("{:?}", key),
+            None => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_layout_names() {
+    for layout in Layout::ALL {
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    assert_eq!(Layout::from_name("klingon"), None);
+}
+
+#[test_case]
+fn test_lock_leds() {
+    assert_eq!(Modifiers::empty().leds(), 0);
+    assert_eq!((Modifiers::NUM_LOCK | Modifiers::LEFT_SHIFT).leds(), 0b010);
+    assert_eq!(
+        (Modifiers::CAPS_LOCK | Modifiers::SCROLL_LOCK).leds(),
+        0b101
+    );
+}
+
+/// Decodes `scancodes` and returns the characters they type
+#[cfg(test)]
+fn type_scancodes(
+    decoder: &mut Decoder,
+    scancodes: &[u8],
+    layout: Layout,
+) -> alloc::string::String {
+    scancodes
+        .iter()
+        .filter_map(|&scancode| decoder.decode(scancode, layout)?.key)
+        .filter_map(|key| match key {
+            DecodedKey::Unicode(character) => Some(character),
+            DecodedKey::RawKey(_) => None,
+        })
+        .collect()
+}
+
+#[test_case]
+fn test_decode_shift() {
+    let mut decoder = Decoder::new(Layout::Us);
+    // a, shift down, a, shift up, a
+    let scancodes = [0x1e, 0x9e, 0x2a, 0x1e, 0x9e, 0xaa, 0x1e, 0x9e];
+    assert_eq!(type_scancodes(&mut decoder, &scancodes, Layout::Us), "aAa");
+    assert!(!decoder.modifiers.shift());
+
+    let event = decoder
+        .decode(0x36, Layout::Us)
+        .expect("right shift pressed");
+    assert_eq!(event.state, KeyState::Pressed);
+    assert!(event.modifiers.contains(Modifiers::RIGHT_SHIFT));
+}
+
+#[test_case]
+fn test_decode_lock_keys() {
+    let mut decoder = Decoder::new(Layout::Us);
+    assert_eq!(decoder.modifiers, Modifiers::NUM_LOCK);
+
+    // caps lock toggles on the press only
+    type_scancodes(&mut decoder, &[0x3a, 0xba], Layout::Us);
+    assert!(decoder.modifiers.contains(Modifiers::CAPS_LOCK));
+    assert_eq!(type_scancodes(&mut decoder, &[0x1e, 0x9e], Layout::Us), "A");
+    type_scancodes(&mut decoder, &[0x3a, 0xba], Layout::Us);
+    assert!(!decoder.modifiers.contains(Modifiers::CAPS_LOCK));
+
+    // keypad 7 is a digit only with num lock on
+    assert_eq!(type_scancodes(&mut decoder, &[0x47, 0xc7], Layout::Us), "7");
+    type_scancodes(&mut decoder, &[0x45, 0xc5], Layout::Us);
+    assert!(!decoder.modifiers.contains(Modifiers::NUM_LOCK));
+    assert_eq!(type_scancodes(&mut decoder, &[0x47, 0xc7], Layout::Us), "");
+}
+
+#[test_case]
+fn test_decode_switches_layout_mid_stream() {
+    let mut decoder = Decoder::new(Layout::Us);
+    // the key right of t is y on us keyboards and z on german ones
+    let scancodes = [0x15, 0x95, 0x3a, 0xba];
+    assert_eq!(type_scancodes(&mut decoder, &scancodes, Layout::Us), "y");
+
+    // caps lock stays on across the switch
+    assert_eq!(
+        type_scancodes(&mut decoder, &[0x15, 0x95], Layout::German),
+        "Z"
+    );
+    assert!(decoder
+        .modifiers
+        .contains(Modifiers::CAPS_LOCK | Modifiers::NUM_LOCK));
+}
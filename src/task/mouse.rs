@@ -0,0 +1,175 @@
+use crate::warn;
+use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{stream::Stream, task::AtomicWaker};
+
+// bits of the first byte of a packet
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+/// Always set, used to find the start of a packet
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Bytes the interrupt handler dropped, the stream reports them
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+/// 3, or 4 if the mouse reports the wheel, set by `ps2::init`
+static PACKET_SIZE: AtomicUsize = AtomicUsize::new(3);
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MouseButtons: u8 {
+        const LEFT = LEFT;
+        const RIGHT = RIGHT;
+        const MIDDLE = MIDDLE;
+    }
+}
+
+/// Movement since the last event and the buttons that are held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// to the right
+    pub dx: i16,
+    /// down, like screen coordinates
+    pub dy: i16,
+    /// towards the user
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+impl MouseEvent {
+    /// Decodes a packet of 3 bytes, or 4 with the wheel movement
+    fn from_packet(packet: &[u8]) -> Self {
+        let flags = packet[0];
+        // 9 bit two's complement with the sign in the first byte
+        let axis = |value: u8, sign: u8, overflow: u8| {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 256
+            } else {
+                value as i16
+            }
+        };
+        MouseEvent {
+            dx: axis(packet[1], X_SIGN, X_OVERFLOW),
+            // the mouse counts upwards
+            dy: -axis(packet[2], Y_SIGN, Y_OVERFLOW),
+            wheel: packet.get(3).map_or(0, |&z| z as i8),
+            buttons: MouseButtons::from_bits_truncate(flags),
+        }
+    }
+}
+
+pub(crate) fn set_packet_size(size: usize) {
+    PACKET_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Called by the mouse interrupt handler
+/// Not allowed to allocate or block
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if queue.push(byte).is_err() {
+            // logging here could print from the interrupt handler
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        } else {
+            WAKER.wake();
+        }
+    }
+}
+
+/// Packets from the PS/2 mouse, decoded into `MouseEvent`s
+pub struct MouseStream {
+    packet: [u8; 4],
+    received: usize,
+}
+
+impl MouseStream {
+    pub fn new() -> Self {
+        BYTE_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("MouseStream::new should only be called once");
+        MouseStream {
+            packet: [0; 4],
+            received: 0,
+        }
+    }
+
+    /// Collects a byte, returns the event once the packet is complete
+    fn add(&mut self, byte: u8) -> Option<MouseEvent> {
+        // a lost byte shifts all packets, wait for a plausible first byte
+        if self.received == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.packet[self.received] = byte;
+        self.received += 1;
+
+        let size = PACKET_SIZE.load(Ordering::Relaxed);
+        if self.received < size {
+            return None;
+        }
+        self.received = 0;
+        Some(MouseEvent::from_packet(&self.packet[..size]))
+    }
+}
+
+impl Default for MouseStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let this = self.get_mut();
+        let queue = BYTE_QUEUE.try_get().expect("not initialized");
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("mouse queue full; dropped {} bytes", dropped);
+        }
+
+        loop {
+            let byte = match queue.pop() {
+                Some(byte) => byte,
+                None => {
+                    WAKER.register(cx.waker());
+                    match queue.pop() {
+                        Some(byte) => {
+                            WAKER.take();
+                            byte
+                        }
+                        None => return Poll::Pending,
+                    }
+                }
+            };
+            if let Some(event) = this.add(byte) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_packet_decoding() {
+    let event = MouseEvent::from_packet(&[ALWAYS_ONE | LEFT | X_SIGN, 0xfe, 0x05]);
+    assert_eq!(event.dx, -2);
+    assert_eq!(event.dy, -5);
+    assert_eq!(event.wheel, 0);
+    assert_eq!(event.buttons, MouseButtons::LEFT);
+
+    let event = MouseEvent::from_packet(&[ALWAYS_ONE | X_OVERFLOW | Y_SIGN, 0x10, 0xf0, 0xff]);
+    assert_eq!((event.dx, event.dy, event.wheel), (0, 16, -1));
+}
@@ -0,0 +1,290 @@
+use crate::interrupts::{self, apic::ApicError, InterruptIndex};
+use crate::warn;
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    future::poll_fn,
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+use spin::Mutex;
+use x86_64::instructions::{
+    interrupts::without_interrupts,
+    port::{Port, PortReadOnly},
+};
+
+const COM1: u16 = 0x3f8;
+/// Bytes the transmit FIFO of a 16550 holds
+const FIFO_SIZE: usize = 16;
+
+// interrupt enable register bits
+const IER_RECEIVED_DATA: u8 = 1 << 0;
+const IER_TRANSMIT_EMPTY: u8 = 1 << 1;
+
+// modem control register bits, `SerialPort::init` sets the first three
+const MCR_DEFAULT: u8 = 0x0b;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+// line status register bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+static RECEIVE_WAKER: AtomicWaker = AtomicWaker::new();
+static RECEIVE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+/// Received bytes that didn't fit into the queue, the stream reports them
+static RECEIVE_DROPPED: AtomicU64 = AtomicU64::new(0);
+static TRANSMIT_WAKER: AtomicWaker = AtomicWaker::new();
+static TRANSMIT_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+
+/// Registers of COM1, only locked with interrupts disabled
+///
+/// `serial::SERIAL1` writes the data register too, it waits for the FIFO to
+/// drain before every byte, so both can be used at the same time.
+static UART: Mutex<Uart> = Mutex::new(Uart::new(COM1));
+
+struct Uart {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: PortReadOnly<u8>,
+}
+
+impl Uart {
+    const fn new(base: u16) -> Self {
+        Uart {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            modem_control: Port::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+        }
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    fn set_interrupts(&mut self, enable: u8) {
+        unsafe { self.interrupt_enable.write(enable) };
+    }
+
+    fn receive(&mut self) {
+        while self.line_status() & LSR_DATA_READY != 0 {
+            let byte = unsafe { self.data.read() };
+            // nobody listens before the stream is created
+            if let Ok(queue) = RECEIVE_QUEUE.try_get() {
+                if queue.push(byte).is_err() {
+                    // logging here could print from the interrupt handler
+                    RECEIVE_DROPPED.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    RECEIVE_WAKER.wake();
+                }
+            }
+        }
+    }
+
+    /// Refills the FIFO from the transmit queue once it is empty
+    fn transmit(&mut self) {
+        let queue = match TRANSMIT_QUEUE.try_get() {
+            Ok(queue) => queue,
+            Err(_) => return,
+        };
+        if self.line_status() & LSR_TRANSMIT_EMPTY != 0 {
+            for _ in 0..FIFO_SIZE {
+                match queue.pop() {
+                    Some(byte) => unsafe { self.data.write(byte) },
+                    None => break,
+                }
+            }
+            TRANSMIT_WAKER.wake();
+        }
+
+        // the UART interrupts again once the FIFO drained
+        match queue.is_empty() {
+            true => self.set_interrupts(IER_RECEIVED_DATA),
+            false => self.set_interrupts(IER_RECEIVED_DATA | IER_TRANSMIT_EMPTY),
+        }
+    }
+}
+
+/// Routes IRQ 4 of COM1 to the current cpu
+///
+/// Has to be called after `interrupts::apic::init`, which masks all other
+/// interrupts. Input that arrived before is discarded.
+pub fn init() -> Result<(), ApicError> {
+    without_interrupts(|| {
+        let mut uart = UART.lock();
+        while uart.line_status() & LSR_DATA_READY != 0 {
+            unsafe { uart.data.read() };
+        }
+        uart.set_interrupts(IER_RECEIVED_DATA);
+    });
+    interrupts::enable_isa_interrupt(InterruptIndex::Serial1)
+}
+
+/// Connects the transmitter of COM1 to its receiver, for tests
+///
+/// Nothing reaches the host in loopback mode, not even `serial_print!`.
+pub fn set_loopback(enable: bool) {
+    let mcr = match enable {
+        true => MCR_DEFAULT | MCR_LOOPBACK,
+        false => MCR_DEFAULT,
+    };
+    without_interrupts(|| unsafe { UART.lock().modem_control.write(mcr) });
+}
+
+/// Called by the COM1 interrupt handler
+/// Not allowed to allocate or block
+pub(crate) fn handle_interrupt() {
+    let mut uart = UART.lock();
+    uart.receive();
+    uart.transmit();
+}
+
+/// Bytes received on COM1
+pub struct SerialStream {
+    _private: (),
+}
+
+impl SerialStream {
+    pub fn new() -> Self {
+        RECEIVE_QUEUE
+            .try_init_once(|| ArrayQueue::new(256))
+            .expect("SerialStream::new should only be called once");
+        SerialStream { _private: () }
+    }
+}
+
+impl Default for SerialStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = RECEIVE_QUEUE.try_get().expect("not initialized");
+        let dropped = RECEIVE_DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("serial receive queue full; dropped {} bytes", dropped);
+        }
+
+        if let Some(byte) = queue.pop() {
+            return Poll::Ready(Some(byte));
+        }
+
+        RECEIVE_WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(byte) => {
+                RECEIVE_WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Sends bytes on COM1 from the interrupt handler, without waiting for the UART
+pub struct SerialWriter {
+    _private: (),
+}
+
+impl SerialWriter {
+    pub fn new() -> Self {
+        TRANSMIT_QUEUE
+            .try_init_once(|| ArrayQueue::new(1024))
+            .expect("SerialWriter::new should only be called once");
+        SerialWriter { _private: () }
+    }
+
+    /// Completes once all of `bytes` are queued, not when they are sent
+    pub fn write<'a>(&'a mut self, bytes: &'a [u8]) -> Write<'a> {
+        Write { bytes }
+    }
+
+    pub async fn write_str(&mut self, s: &str) {
+        self.write(s.as_bytes()).await
+    }
+
+    /// Completes once the interrupt handler moved every queued byte into the FIFO
+    pub async fn flush(&mut self) {
+        let queue = TRANSMIT_QUEUE.try_get().expect("not initialized");
+        poll_fn(|cx| {
+            TRANSMIT_WAKER.register(cx.waker());
+            match queue.is_empty() {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Formats right away, so the future doesn't keep `args`
+    pub fn write_fmt(&mut self, args: fmt::Arguments) -> impl Future<Output = ()> + '_ {
+        let s = alloc::fmt::format(args);
+        async move { self.write_str(&s).await }
+    }
+}
+
+impl Default for SerialWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by `SerialWriter::write`
+pub struct Write<'a> {
+    bytes: &'a [u8],
+}
+
+impl Future for Write<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let queue = TRANSMIT_QUEUE.try_get().expect("not initialized");
+        loop {
+            while let Some((&byte, rest)) = this.bytes.split_first() {
+                if queue.push(byte).is_err() {
+                    break;
+                }
+                this.bytes = rest;
+            }
+            // the interrupt only comes if the FIFO is refilled once
+            without_interrupts(|| UART.lock().transmit());
+            if this.bytes.is_empty() {
+                return Poll::Ready(());
+            }
+
+            TRANSMIT_WAKER.register(cx.waker());
+            // the interrupt might have made room before the waker was registered
+            if queue.is_full() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+/// Sends every received byte back, so a terminal shows what is typed
+pub async fn echo() {
+    let mut input = SerialStream::new();
+    let mut output = SerialWriter::new();
+
+    while let Some(byte) = input.next().await {
+        match byte {
+            // terminals send a carriage return for enter
+            b'\r' => output.write(b"\r\n").await,
+            // backspace and delete
+            0x08 | 0x7f => output.write(b"\x08 \x08").await,
+            byte => output.write(&[byte]).await,
+        }
+    }
+}
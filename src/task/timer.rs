@@ -0,0 +1,169 @@
+use crate::time::{Duration, Instant};
+use alloc::collections::BinaryHeap;
+use core::{
+    cmp::Ordering,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{self, AtomicU64},
+    task::{Context, Poll, Waker},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// A waker that is due at `deadline`
+struct Timer {
+    deadline: Instant,
+    /// tells the timers of different `Sleep`s apart
+    id: u64,
+    waker: Waker,
+}
+
+// ordered so the earliest deadline is at the top of the max-heap
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.id).cmp(&(self.deadline, self.id))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.id) == (other.deadline, other.id)
+    }
+}
+
+impl Eq for Timer {}
+
+/// Pending timers, only locked with interrupts disabled
+static TIMERS: Mutex<BinaryHeap<Timer>> = Mutex::new(BinaryHeap::new());
+
+/// Called by the timer interrupt handler
+/// Not allowed to allocate or block
+pub(crate) fn wake_expired() {
+    let now = Instant::now();
+    loop {
+        let timer = {
+            let mut timers = TIMERS.lock();
+            match timers.peek() {
+                Some(timer) if timer.deadline <= now => timers.pop(),
+                _ => None,
+            }
+        };
+        // the executor keeps its own reference to the waker, so dropping it frees nothing
+        match timer {
+            Some(timer) => timer.waker.wake(),
+            None => break,
+        }
+    }
+}
+
+/// Future that completes at a deadline, see `sleep` and `sleep_until`
+pub struct Sleep {
+    deadline: Instant,
+    id: u64,
+    registered: bool,
+}
+
+impl Sleep {
+    fn new(deadline: Instant) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Sleep {
+            deadline,
+            id: NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed),
+            registered: false,
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    fn unregister(&mut self) {
+        if self.registered {
+            let id = self.id;
+            interrupts::without_interrupts(|| TIMERS.lock().retain(|timer| timer.id != id));
+            self.registered = false;
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        if Instant::now() >= this.deadline {
+            this.unregister();
+            return Poll::Ready(());
+        }
+
+        // the waker might have changed since the last poll
+        this.unregister();
+        let timer = Timer {
+            deadline: this.deadline,
+            id: this.id,
+            waker: cx.waker().clone(),
+        };
+        interrupts::without_interrupts(|| TIMERS.lock().push(timer));
+        this.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Completes after `duration`
+///
+/// The timer interrupt wakes the task, so the resolution is a tick.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep::new(Instant::now() + duration)
+}
+
+/// Completes once `deadline` passed
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep::new(deadline)
+}
+
+/// Error of a `Timeout` whose future didn't complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Future returned by `timeout`
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // `future` is never moved out of the pinned `Timeout`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Runs `future` for at most `duration`
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
@@ -0,0 +1,247 @@
+pub mod scheduler;
+mod switch;
+
+use crate::cpu;
+use crate::memory::{self, address_space::AddressSpace, stack::KernelStack};
+use crate::time::{self, Duration};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::{instructions::interrupts, structures::paging::PhysFrame, VirtAddr};
+
+/// Size of the stack every spawned kernel thread gets
+pub const STACK_SIZE: usize = 4096 * 4;
+/// Size of the stack page faults of the thread are handled on
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ThreadState {
+    Ready,
+    Running,
+    Blocked,
+    Exited,
+}
+
+/// How a thread ended, see `join`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// ended on its own, with the code passed to the `exit` syscall or 0 for kernel threads
+    Exited(u64),
+    /// terminated by the kernel after a fault, or its program couldn't be loaded
+    Killed,
+}
+
+/// Thread control block
+pub(crate) struct Thread {
+    id: ThreadId,
+    state: ThreadState,
+    /// stack pointer saved by `switch_context` while the thread is not running
+    saved_rsp: u64,
+    /// `None` for the boot thread, which runs on the bootloader's stack
+    stack: Option<KernelStack>,
+    /// loaded into the TSS, so interrupts and syscalls from ring 3 land on this stack
+    stack_top: VirtAddr,
+    /// `None` for the boot thread, which keeps the static stack from `gdt`
+    page_fault_stack: Option<KernelStack>,
+    /// loaded into the TSS as the page fault IST entry
+    page_fault_stack_top: VirtAddr,
+    /// `None` for kernel threads
+    address_space: Option<Arc<Mutex<AddressSpace>>>,
+    /// level 4 table loaded into CR3 when switching to the thread
+    page_table: PhysFrame,
+    /// threads blocked in `join` on this thread
+    joiners: Vec<ThreadId>,
+    /// whether the exit status is dropped instead of kept for `join`
+    detached: bool,
+}
+
+/// Snapshot of a thread, see `list`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ThreadInfo {
+    pub id: ThreadId,
+    pub state: ThreadState,
+    /// whether the thread has an address space for user code
+    pub user: bool,
+}
+
+type ThreadEntry = Box<dyn FnOnce() + Send + 'static>;
+
+impl Thread {
+    /// Creates the control block for the already running boot thread
+    fn bootstrap() -> Self {
+        Thread {
+            id: ThreadId::new(),
+            state: ThreadState::Running,
+            saved_rsp: 0,
+            stack: None,
+            stack_top: crate::gdt::kernel_stack(),
+            page_fault_stack: None,
+            page_fault_stack_top: crate::gdt::page_fault_stack(),
+            address_space: None,
+            page_table: memory::kernel_page_table(),
+            joiners: Vec::new(),
+            detached: false,
+        }
+    }
+
+    /// Creates a new thread with its own stack
+    ///
+    /// The stack is prepared so that the first `switch_context` into it
+    /// "returns" into `thread_trampoline` with the entry closure in r12.
+    fn new(entry: ThreadEntry) -> Self {
+        let stack = KernelStack::new(STACK_SIZE as u64).expect("allocating thread stack failed");
+        let page_fault_stack =
+            KernelStack::new(PAGE_FAULT_STACK_SIZE as u64).expect("allocating thread stack failed");
+        let stack_top = stack.top().as_u64();
+
+        // after the 6 callee-saved registers and the return address are popped,
+        // rsp has to be 16 byte aligned for the `call` in the trampoline
+        let frame = [
+            0,                                         // r15
+            0,                                         // r14
+            0,                                         // r13
+            Box::into_raw(Box::new(entry)) as u64,     // r12
+            0,                                         // rbx
+            0,                                         // rbp
+            switch::thread_trampoline as usize as u64, // return address
+        ];
+        let saved_rsp = stack_top - 16 - 8 * frame.len() as u64;
+        unsafe {
+            let ptr = saved_rsp as *mut u64;
+            for (i, value) in frame.iter().enumerate() {
+                ptr.add(i).write(*value);
+            }
+        }
+
+        Thread {
+            id: ThreadId::new(),
+            state: ThreadState::Ready,
+            saved_rsp,
+            stack: Some(stack),
+            stack_top: VirtAddr::new(stack_top),
+            page_fault_stack_top: page_fault_stack.top(),
+            page_fault_stack: Some(page_fault_stack),
+            address_space: None,
+            page_table: memory::kernel_page_table(),
+            joiners: Vec::new(),
+            detached: false,
+        }
+    }
+}
+
+/// Called on the new stack by `thread_trampoline`
+extern "C" fn thread_entry(entry: *mut ThreadEntry) -> ! {
+    scheduler::finish_switch();
+    interrupts::enable();
+
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit();
+}
+
+/// Spawns a new kernel thread running `f`
+///
+/// The thread is preempted by the timer interrupt, so it is allowed to loop forever.
+pub fn spawn_thread<F>(f: F) -> ThreadId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let thread = Box::new(Thread::new(Box::new(f)));
+    let id = thread.id;
+    scheduler::add(thread);
+    id
+}
+
+/// Gives up the rest of the time slice of the current thread
+pub fn yield_now() {
+    scheduler::reschedule(ThreadState::Ready);
+}
+
+/// Blocks the current thread for at least `ticks` timer interrupts
+pub fn sleep_ticks(ticks: u64) {
+    scheduler::sleep(ticks);
+}
+
+/// Blocks the current thread for at least `duration`
+pub fn sleep(duration: Duration) {
+    // the current tick is already partly over
+    sleep_ticks(time::ticks_for(duration) + 1);
+}
+
+/// Terminates the current thread and wakes up all threads joining it
+pub fn exit() -> ! {
+    exit_with(ExitStatus::Exited(0));
+}
+
+/// Terminates the current thread, `join` returns `status` for it
+pub fn exit_with(status: ExitStatus) -> ! {
+    scheduler::exit_current(status);
+    unreachable!("exited thread was scheduled again");
+}
+
+/// Blocks until the thread with the given id has exited and returns how it ended
+///
+/// The status is kept until the first `join`, later ones and unknown ids get `None`.
+pub fn join(id: ThreadId) -> Option<ExitStatus> {
+    while scheduler::wait_for(id) {}
+    scheduler::take_exit_status(id)
+}
+
+/// Drops the exit status of the thread once it ended, for threads nobody joins
+///
+/// `join` still waits for the thread, but returns `None`.
+pub fn detach(id: ThreadId) {
+    scheduler::detach(id);
+}
+
+/// Returns how many exit statuses are kept for a `join`
+pub fn unjoined_count() -> usize {
+    scheduler::unjoined_count()
+}
+
+/// Returns the thread whose kernel stack overflowed if `addr` is in its guard page
+pub fn stack_owner(addr: VirtAddr) -> Option<ThreadId> {
+    scheduler::stack_owner(addr)
+}
+
+/// Returns all threads that haven't exited, ordered by id
+pub(crate) fn list() -> Vec<ThreadInfo> {
+    scheduler::list()
+}
+
+/// Returns the id of the running thread
+pub fn current_id() -> ThreadId {
+    ThreadId(cpu::current().current_thread())
+}
+
+/// Moves the current thread into `address_space` and activates it
+pub fn set_address_space(address_space: Arc<Mutex<AddressSpace>>) {
+    scheduler::set_address_space(address_space);
+}
+
+/// Returns the address space of the current thread, `None` for kernel threads
+pub fn address_space() -> Option<Arc<Mutex<AddressSpace>>> {
+    scheduler::address_space()
+}
+
+/// Sets up the scheduler, turning the caller into the boot thread
+///
+/// The heap has to be initialized before. Threads only run on the bootstrap
+/// processor, application processors only run executors.
+pub fn init() {
+    scheduler::init();
+}
@@ -0,0 +1,293 @@
+use super::{switch::switch_context, ExitStatus, Thread, ThreadId, ThreadInfo, ThreadState};
+use crate::{cpu, gdt, hlt_loop, memory::address_space::AddressSpace, time};
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque, sync::Arc, vec::Vec};
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts,
+    registers::control::{Cr3, Cr3Flags},
+    VirtAddr,
+};
+
+static SCHEDULER: OnceCell<Mutex<Scheduler>> = OnceCell::uninit();
+
+/// Round robin scheduler
+///
+/// The lock is only ever taken with interrupts disabled, otherwise the timer
+/// interrupt could try to reschedule while it is held.
+struct Scheduler {
+    threads: BTreeMap<ThreadId, Box<Thread>>,
+    ready: VecDeque<ThreadId>,
+    current: ThreadId,
+    /// runs only if no other thread is ready
+    idle: ThreadId,
+    /// exited thread whose stack can be freed once we switched away from it
+    dead: Option<Box<Thread>>,
+    /// blocked threads with the tick they want to be woken up at
+    sleeping: Vec<(u64, ThreadId)>,
+    /// how exited threads ended, until they are joined
+    exit_statuses: BTreeMap<ThreadId, ExitStatus>,
+}
+
+impl Scheduler {
+    fn thread_mut(&mut self, id: ThreadId) -> &mut Thread {
+        self.threads.get_mut(&id).expect("unknown thread")
+    }
+
+    fn make_ready(&mut self, id: ThreadId) {
+        self.thread_mut(id).state = ThreadState::Ready;
+        if id != self.idle {
+            self.ready.push_back(id);
+        }
+    }
+
+    /// Puts the current thread into `state` and picks the next thread
+    ///
+    /// Returns the arguments for `switch_context`, or `None` if the current
+    /// thread just keeps running.
+    fn switch(&mut self, state: ThreadState) -> Option<(*mut u64, u64)> {
+        let current = self.current;
+
+        if state == ThreadState::Ready && self.ready.is_empty() && current != self.idle {
+            return None;
+        }
+
+        let next = self.ready.pop_front().unwrap_or(self.idle);
+        if next == current {
+            return None;
+        }
+
+        let old_rsp = match state {
+            ThreadState::Ready => {
+                self.make_ready(current);
+                &mut self.thread_mut(current).saved_rsp as *mut u64
+            }
+            ThreadState::Blocked => {
+                let thread = self.thread_mut(current);
+                thread.state = ThreadState::Blocked;
+                &mut thread.saved_rsp as *mut u64
+            }
+            ThreadState::Exited => {
+                let mut thread = self.threads.remove(&current).expect("unknown thread");
+                thread.state = ThreadState::Exited;
+                let old_rsp = &mut thread.saved_rsp as *mut u64;
+                // the box keeps `saved_rsp` at the same address after the move
+                self.dead = Some(thread);
+                old_rsp
+            }
+            ThreadState::Running => unreachable!("cannot switch to running state"),
+        };
+
+        let next_thread = self.thread_mut(next);
+        next_thread.state = ThreadState::Running;
+        let new_rsp = next_thread.saved_rsp;
+        gdt::set_kernel_stack(next_thread.stack_top);
+        gdt::set_page_fault_stack(next_thread.page_fault_stack_top);
+        if Cr3::read().0 != next_thread.page_table {
+            unsafe { Cr3::write(next_thread.page_table, Cr3Flags::empty()) };
+        }
+        self.current = next;
+        cpu::current().set_current_thread(next.as_u64());
+
+        Some((old_rsp, new_rsp))
+    }
+
+    fn wake_sleepers(&mut self) {
+        let now = time::ticks();
+        let mut i = 0;
+        while i < self.sleeping.len() {
+            if self.sleeping[i].0 <= now {
+                let (_, id) = self.sleeping.swap_remove(i);
+                self.make_ready(id);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+pub(super) fn init() {
+    let boot = Box::new(Thread::bootstrap());
+    let idle = Box::new(Thread::new(Box::new(|| hlt_loop())));
+
+    let mut threads = BTreeMap::new();
+    let (boot_id, idle_id) = (boot.id, idle.id);
+    threads.insert(boot_id, boot);
+    threads.insert(idle_id, idle);
+    cpu::current().set_current_thread(boot_id.as_u64());
+
+    SCHEDULER
+        .try_init_once(|| {
+            Mutex::new(Scheduler {
+                threads,
+                ready: VecDeque::new(),
+                current: boot_id,
+                idle: idle_id,
+                dead: None,
+                sleeping: Vec::new(),
+                exit_statuses: BTreeMap::new(),
+            })
+        })
+        .expect("thread::init should only be called once");
+}
+
+fn scheduler() -> &'static Mutex<Scheduler> {
+    SCHEDULER.try_get().expect("scheduler not initialized")
+}
+
+pub(super) fn add(thread: Box<Thread>) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler().lock();
+        let id = thread.id;
+        scheduler.threads.insert(id, thread);
+        scheduler.ready.push_back(id);
+    });
+}
+
+pub(super) fn set_address_space(address_space: Arc<Mutex<AddressSpace>>) {
+    let old = interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler().lock();
+        let current = scheduler.current;
+        let thread = scheduler.thread_mut(current);
+        thread.page_table = address_space.lock().level_4_frame();
+        unsafe { Cr3::write(thread.page_table, Cr3Flags::empty()) };
+        thread.address_space.replace(address_space)
+    });
+    // only dropped after it is no longer active
+    drop(old);
+}
+
+pub(super) fn address_space() -> Option<Arc<Mutex<AddressSpace>>> {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler().lock();
+        let current = scheduler.current;
+        scheduler.thread_mut(current).address_space.clone()
+    })
+}
+
+pub(super) fn list() -> Vec<ThreadInfo> {
+    interrupts::without_interrupts(|| {
+        let scheduler = scheduler().lock();
+        scheduler
+            .threads
+            .values()
+            .map(|thread| ThreadInfo {
+                id: thread.id,
+                state: thread.state,
+                user: thread.address_space.is_some(),
+            })
+            .collect()
+    })
+}
+
+/// Returns the thread whose stack has its guard page at `addr`
+pub(super) fn stack_owner(addr: VirtAddr) -> Option<ThreadId> {
+    interrupts::without_interrupts(|| {
+        // the overflow might have happened with the lock held
+        let scheduler = scheduler().try_lock()?;
+        scheduler
+            .threads
+            .values()
+            .find(|thread| {
+                [&thread.stack, &thread.page_fault_stack]
+                    .iter()
+                    .any(|stack| stack.as_ref().map_or(false, |s| s.guard_contains(addr)))
+            })
+            .map(|thread| thread.id)
+    })
+}
+
+/// Switches away from the current thread, leaving it in `state`
+///
+/// Returns once the thread is scheduled again.
+pub(super) fn reschedule(state: ThreadState) {
+    interrupts::without_interrupts(|| {
+        let switch = scheduler().lock().switch(state);
+        if let Some((old_rsp, new_rsp)) = switch {
+            unsafe { switch_context(old_rsp, new_rsp) };
+            finish_switch();
+        }
+    });
+}
+
+/// Has to be run by every thread right after it was switched to
+pub(super) fn finish_switch() {
+    let dead = interrupts::without_interrupts(|| scheduler().lock().dead.take());
+    drop(dead);
+}
+
+pub(super) fn exit_current(status: ExitStatus) {
+    interrupts::without_interrupts(|| {
+        {
+            let mut scheduler = scheduler().lock();
+            let current = scheduler.current;
+            if !scheduler.thread_mut(current).detached {
+                scheduler.exit_statuses.insert(current, status);
+            }
+            let joiners = core::mem::take(&mut scheduler.thread_mut(current).joiners);
+            for joiner in joiners {
+                scheduler.make_ready(joiner);
+            }
+        }
+        reschedule(ThreadState::Exited);
+    });
+}
+
+/// Blocks the current thread until `id` exits
+///
+/// Returns `false` if the thread already exited.
+pub(super) fn wait_for(id: ThreadId) -> bool {
+    interrupts::without_interrupts(|| {
+        {
+            let mut scheduler = scheduler().lock();
+            let current = scheduler.current;
+            match scheduler.threads.get_mut(&id) {
+                Some(thread) if thread.state != ThreadState::Exited => thread.joiners.push(current),
+                _ => return false,
+            }
+        }
+        reschedule(ThreadState::Blocked);
+        true
+    })
+}
+
+pub(super) fn detach(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler().lock();
+        match scheduler.threads.get_mut(&id) {
+            Some(thread) => thread.detached = true,
+            // it already exited
+            None => {
+                scheduler.exit_statuses.remove(&id);
+            }
+        }
+    });
+}
+
+pub(super) fn unjoined_count() -> usize {
+    interrupts::without_interrupts(|| scheduler().lock().exit_statuses.len())
+}
+
+pub(super) fn take_exit_status(id: ThreadId) -> Option<ExitStatus> {
+    interrupts::without_interrupts(|| scheduler().lock().exit_statuses.remove(&id))
+}
+
+pub(super) fn sleep(ticks: u64) {
+    interrupts::without_interrupts(|| {
+        {
+            let mut scheduler = scheduler().lock();
+            let wake_at = time::ticks() + ticks;
+            let current = scheduler.current;
+            scheduler.sleeping.push((wake_at, current));
+        }
+        reschedule(ThreadState::Blocked);
+    });
+}
+
+/// Called by the timer interrupt handler to preempt the running thread
+pub fn tick() {
+    if let Ok(scheduler) = SCHEDULER.try_get() {
+        scheduler.lock().wake_sleepers();
+        reschedule(ThreadState::Ready);
+    }
+}
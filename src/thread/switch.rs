@@ -0,0 +1,44 @@
+use core::arch::asm;
+
+/// Saves the callee-saved registers on the current stack, stores the stack
+/// pointer in `old_rsp` and continues on the stack at `new_rsp`
+///
+/// Caller-saved registers are already saved by the compiler (or the interrupt
+/// wrapper) at the call site, so they don't need to be touched here.
+#[naked]
+pub(super) unsafe extern "C" fn switch_context(old_rsp: *mut u64, new_rsp: u64) {
+    asm!(
+        "
+        push rbp
+        push rbx
+        push r12
+        push r13
+        push r14
+        push r15
+        mov [rdi], rsp
+        mov rsp, rsi
+        pop r15
+        pop r14
+        pop r13
+        pop r12
+        pop rbx
+        pop rbp
+        ret",
+        options(noreturn)
+    );
+}
+
+/// First code a new thread runs, entered through the `ret` of `switch_context`
+///
+/// The thread's entry closure is passed in r12, see `Thread::new`.
+#[naked]
+pub(super) unsafe extern "C" fn thread_trampoline() -> ! {
+    asm!(
+        "
+        mov rdi, r12
+        call {}
+        ud2",
+        sym super::thread_entry,
+        options(noreturn)
+    );
+}
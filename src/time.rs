@@ -0,0 +1,278 @@
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+
+pub use core::time::Duration;
+pub use rtc::DateTime;
+
+use crate::acpi;
+use crate::interrupts::{self as irq, apic::ApicError, InterruptIndex};
+use core::arch::x86_64::_rdtsc;
+use core::ops::{Add, AddAssign, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use hpet::Hpet;
+use x86_64::instructions::interrupts;
+
+/// Timer interrupts per second
+pub const TICK_HZ: u64 = 100;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+/// How long the TSC is measured for during calibration
+const CALIBRATION_TIME: Duration = Duration::from_millis(10);
+
+/// Timer interrupts since `init`
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Length of a tick, the PIT can't hit `TICK_HZ` exactly
+static TICK_NANOS: AtomicU64 = AtomicU64::new(NANOS_PER_SEC / TICK_HZ);
+/// Sum of the lengths of all ticks, they change if the tick source changes
+static TICK_CLOCK_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Whether the periodic RTC interrupt drives the tick instead of the PIT
+static RTC_TICK: AtomicBool = AtomicBool::new(false);
+
+/// TSC increments per second, 0 until `calibrate`
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+/// TSC value at calibration
+static TSC_BASE: AtomicU64 = AtomicU64::new(0);
+/// Time since boot at calibration, so the clock doesn't jump back
+static NANOS_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time at boot in nanoseconds, 0 until the RTC was read
+static BOOT_UNIX_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// Clock the TSC was calibrated against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    Hpet,
+    Pit,
+}
+
+/// Device raising the timer interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickSource {
+    Pit,
+    Rtc,
+}
+
+/// Programs the PIT to fire `TICK_HZ` times a second
+///
+/// Called by `crate::init` before interrupts are enabled.
+pub fn init() {
+    let cycles = pit::set_frequency(TICK_HZ);
+    TICK_NANOS.store(cycles * NANOS_PER_SEC / pit::FREQUENCY, Ordering::Relaxed);
+}
+
+/// Called by the handler of the interrupt of the tick source
+pub(crate) fn tick() {
+    TICK_CLOCK_NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn tick_source() -> TickSource {
+    match RTC_TICK.load(Ordering::Relaxed) {
+        true => TickSource::Rtc,
+        false => TickSource::Pit,
+    }
+}
+
+/// Lets the periodic RTC interrupt drive the tick instead of the PIT
+///
+/// The RTC only runs at powers of two, so the tick becomes a bit shorter than
+/// `1 / TICK_HZ`. Sleeps in progress keep their number of ticks.
+pub fn use_rtc_tick() -> Result<(), ApicError> {
+    irq::enable_isa_interrupt(InterruptIndex::Rtc)?;
+    interrupts::without_interrupts(|| {
+        let hz = rtc::enable_periodic_interrupt(TICK_HZ);
+        TICK_NANOS.store(NANOS_PER_SEC / hz, Ordering::Relaxed);
+        RTC_TICK.store(true, Ordering::Relaxed);
+        pit::stop();
+    });
+    Ok(())
+}
+
+/// Timer interrupts since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Length of one timer tick
+pub fn tick_duration() -> Duration {
+    Duration::from_nanos(TICK_NANOS.load(Ordering::Relaxed))
+}
+
+/// Number of ticks that cover `duration`, rounded up
+pub fn ticks_for(duration: Duration) -> u64 {
+    let tick = TICK_NANOS.load(Ordering::Relaxed) as u128;
+    duration.as_nanos().div_ceil(tick) as u64
+}
+
+fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Measures the TSC frequency, against the HPET if ACPI describes one
+///
+/// Afterwards `Instant` has the resolution of the TSC instead of a tick.
+/// `acpi::init` and `memory::init_global` have to be called before.
+pub fn calibrate() -> ClockSource {
+    let hpet = acpi::get()
+        .and_then(|acpi| acpi.hpet())
+        .and_then(|table| Hpet::new(table).ok());
+
+    // nothing may delay the measurement
+    let (source, hz) = interrupts::without_interrupts(|| match &hpet {
+        Some(hpet) => (ClockSource::Hpet, calibrate_with_hpet(hpet)),
+        None => (ClockSource::Pit, calibrate_with_pit()),
+    });
+
+    // published last, `now` only reads the bases once it sees the frequency
+    let now = nanos_since_boot();
+    TSC_BASE.store(rdtsc(), Ordering::Relaxed);
+    NANOS_BASE.store(now, Ordering::Relaxed);
+    TSC_HZ.store(hz, Ordering::Release);
+    source
+}
+
+fn calibrate_with_hpet(hpet: &Hpet) -> u64 {
+    let cycles = hpet.frequency() * CALIBRATION_TIME.as_millis() as u64 / 1000;
+    let start = hpet.counter();
+    let tsc_start = rdtsc();
+    let mut elapsed = 0;
+    while elapsed < cycles {
+        elapsed = hpet.elapsed(start, hpet.counter());
+    }
+    let tsc_elapsed = rdtsc() - tsc_start;
+    (tsc_elapsed as u128 * hpet.frequency() as u128 / elapsed as u128) as u64
+}
+
+fn calibrate_with_pit() -> u64 {
+    let cycles = pit::FREQUENCY * CALIBRATION_TIME.as_millis() as u64 / 1000;
+    pit::start_one_shot(cycles as u16);
+    let tsc_start = rdtsc();
+    while !pit::one_shot_done() {}
+    let tsc_elapsed = rdtsc() - tsc_start;
+    (tsc_elapsed as u128 * pit::FREQUENCY as u128 / cycles as u128) as u64
+}
+
+/// TSC increments per second, `None` until `calibrate`
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_HZ.load(Ordering::Acquire) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+fn nanos_since_boot() -> u64 {
+    match tsc_frequency() {
+        Some(hz) => {
+            let elapsed = rdtsc().saturating_sub(TSC_BASE.load(Ordering::Relaxed));
+            let nanos = elapsed as u128 * NANOS_PER_SEC as u128 / hz as u128;
+            NANOS_BASE.load(Ordering::Relaxed) + nanos as u64
+        }
+        None => TICK_CLOCK_NANOS.load(Ordering::Relaxed),
+    }
+}
+
+/// A point on the monotonic clock, which starts at boot
+///
+/// Has the resolution of a tick until the TSC is calibrated. Can be used in
+/// interrupt handlers, it takes no locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(nanos_since_boot())
+    }
+
+    /// Time since boot
+    pub fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// Time from `earlier` to `self`, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        u64::try_from(duration.as_nanos())
+            .ok()
+            .and_then(|nanos| self.0.checked_add(nanos))
+            .map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Wall clock time, nanoseconds since the Unix epoch
+///
+/// Unlike `Instant` it is not monotonic, it follows the RTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+impl SystemTime {
+    pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+    /// The RTC is read on the first call, afterwards the monotonic clock counts on
+    pub fn now() -> Self {
+        let mut boot = BOOT_UNIX_NANOS.load(Ordering::Relaxed);
+        if boot == 0 {
+            let unix = rtc::read_date_time().to_unix_timestamp() * NANOS_PER_SEC;
+            boot = unix.saturating_sub(nanos_since_boot());
+            BOOT_UNIX_NANOS.store(boot, Ordering::Relaxed);
+        }
+        SystemTime(boot + nanos_since_boot())
+    }
+
+    pub fn duration_since_epoch(&self) -> Duration {
+        Duration::from_nanos(self.0)
+    }
+
+    /// Whole seconds since the Unix epoch
+    pub fn unix_timestamp(&self) -> u64 {
+        self.0 / NANOS_PER_SEC
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix_timestamp(self.unix_timestamp())
+    }
+}
+
+/// Current wall clock time, see `SystemTime`
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+#[test_case]
+fn test_ticks_for_rounds_up() {
+    let tick = tick_duration();
+    assert_eq!(ticks_for(Duration::ZERO), 0);
+    assert_eq!(ticks_for(tick), 1);
+    assert_eq!(ticks_for(tick + Duration::from_nanos(1)), 2);
+    assert_eq!(ticks_for(tick * 3), 3);
+}
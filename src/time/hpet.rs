@@ -0,0 +1,69 @@
+use crate::{acpi, memory::mmio};
+use x86_64::{
+    structures::paging::{mapper::MapToError, Size4KiB},
+    VirtAddr,
+};
+
+// registers
+const REG_CAPABILITIES: u64 = 0x00;
+const REG_CONFIG: u64 = 0x10;
+const REG_COUNTER: u64 = 0xf0;
+
+const CAPABILITY_64_BIT_COUNTER: u64 = 1 << 13;
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// Main counter of the high precision event timer
+///
+/// The comparators are not used, only the free running counter.
+pub struct Hpet {
+    base: VirtAddr,
+    /// length of a counter increment in femtoseconds
+    period: u64,
+    /// mask of the bits the counter has, it might only be 32 bit wide
+    counter_mask: u64,
+}
+
+impl Hpet {
+    /// Maps the registers described by the ACPI table and starts the counter
+    pub fn new(table: &acpi::hpet::Hpet) -> Result<Self, MapToError<Size4KiB>> {
+        let base = mmio::map(table.address, 0x400)?;
+        let mut hpet = Hpet {
+            base,
+            period: 0,
+            counter_mask: u32::MAX as u64,
+        };
+
+        let capabilities = hpet.read(REG_CAPABILITIES);
+        hpet.period = capabilities >> 32;
+        if capabilities & CAPABILITY_64_BIT_COUNTER != 0 {
+            hpet.counter_mask = u64::MAX;
+        }
+        let config = hpet.read(REG_CONFIG);
+        hpet.write(REG_CONFIG, config | CONFIG_ENABLE);
+        Ok(hpet)
+    }
+
+    /// Counter increments per second
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period.max(1)
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.read(REG_COUNTER) & self.counter_mask
+    }
+
+    /// Increments between two counter values, a 32 bit counter can wrap in between
+    pub fn elapsed(&self, start: u64, end: u64) -> u64 {
+        end.wrapping_sub(start) & self.counter_mask
+    }
+
+    fn read(&self, reg: u64) -> u64 {
+        unsafe { (self.base + reg).as_ptr::<u64>().read_volatile() }
+    }
+
+    fn write(&mut self, reg: u64, value: u64) {
+        unsafe { (self.base + reg).as_mut_ptr::<u64>().write_volatile(value) }
+    }
+}
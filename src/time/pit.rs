@@ -0,0 +1,82 @@
+use x86_64::instructions::port::Port;
+
+/// Input clock of the programmable interval timer in Hz
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Keyboard controller port B, controls the gate of channel 2 and shows its output
+const PORT_B: u16 = 0x61;
+
+// command bits
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+const MODE_ONE_SHOT: u8 = 0 << 1;
+const MODE_RATE_GENERATOR: u8 = 2 << 1;
+
+// port B bits
+const GATE_2: u8 = 1 << 0;
+const SPEAKER: u8 = 1 << 1;
+const OUTPUT_2: u8 = 1 << 5;
+
+/// Returns the reload value closest to `hz`, 0 stands for 65536
+fn divisor(hz: u64) -> u16 {
+    let divisor = (FREQUENCY + hz / 2) / hz.max(1);
+    divisor.clamp(1, 0x10000) as u16
+}
+
+/// Programs channel 0, which is connected to IRQ 0, to fire about `hz` times a second
+///
+/// Returns the length of a period in PIT cycles, it is rarely exactly `FREQUENCY / hz`.
+pub fn set_frequency(hz: u64) -> u64 {
+    let divisor = divisor(hz);
+    unsafe {
+        Port::<u8>::new(COMMAND).write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_RATE_GENERATOR);
+        let mut data = Port::<u8>::new(CHANNEL_0);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+    match divisor {
+        0 => 0x10000,
+        divisor => divisor as u64,
+    }
+}
+
+/// Stops the periodic interrupt of channel 0
+///
+/// The channel counts down once more and stays quiet afterwards.
+pub fn stop() {
+    unsafe {
+        Port::<u8>::new(COMMAND).write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_ONE_SHOT);
+        let mut data = Port::<u8>::new(CHANNEL_0);
+        data.write(0xff);
+        data.write(0xff);
+    }
+}
+
+/// Starts channel 2 counting down `cycles` PIT cycles, see `one_shot_done`
+///
+/// Channel 2 is not connected to an interrupt, so this works with interrupts
+/// disabled. The speaker stays off.
+pub fn start_one_shot(cycles: u16) {
+    unsafe {
+        let mut port_b = Port::<u8>::new(PORT_B);
+        let value = port_b.read() & !(SPEAKER | GATE_2);
+        port_b.write(value);
+
+        Port::<u8>::new(COMMAND).write(SELECT_CHANNEL_2 | ACCESS_LOW_HIGH | MODE_ONE_SHOT);
+        let mut data = Port::<u8>::new(CHANNEL_2);
+        data.write(cycles as u8);
+        data.write((cycles >> 8) as u8);
+
+        // counting starts on the rising edge of the gate
+        port_b.write(value | GATE_2);
+    }
+}
+
+/// Whether the count started by `start_one_shot` reached zero
+pub fn one_shot_done() -> bool {
+    unsafe { Port::<u8>::new(PORT_B).read() & OUTPUT_2 != 0 }
+}
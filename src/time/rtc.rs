@@ -0,0 +1,269 @@
+use crate::acpi;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::{interrupts, port::Port};
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+/// Set in the index write of every access, so no NMI arrives between index and
+/// data access, cleared again afterwards
+const NMI_DISABLE: u8 = 1 << 7;
+
+// registers
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE: u8 = 0x0f;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Set in the hours register for PM in 12 hour mode
+const HOURS_PM: u8 = 1 << 7;
+
+/// The periodic interrupt divides this frequency by powers of two
+const BASE_FREQUENCY: u64 = 32768;
+
+/// Serializes the index/data register pairs, only locked with interrupts disabled
+static CMOS: Mutex<()> = Mutex::new(());
+
+/// Reads a CMOS register, the lock has to be held
+unsafe fn read(reg: u8) -> u8 {
+    Port::<u8>::new(INDEX).write(NMI_DISABLE | reg);
+    let value = Port::<u8>::new(DATA).read();
+    Port::<u8>::new(INDEX).write(reg);
+    value
+}
+
+/// Writes a CMOS register, the lock has to be held
+unsafe fn write(reg: u8, value: u8) {
+    Port::<u8>::new(INDEX).write(NMI_DISABLE | reg);
+    Port::<u8>::new(DATA).write(value);
+    Port::<u8>::new(INDEX).write(reg);
+}
+
+/// A date and time in UTC, as kept by the RTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC
+    pub fn to_unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let seconds = self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64;
+        (days * 86400 + seconds).max(0) as u64
+    }
+
+    pub fn from_unix_timestamp(timestamp: u64) -> Self {
+        let (days, seconds) = ((timestamp / 86400) as i64, timestamp % 86400);
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// conversions between dates and days since the epoch, from
+// http://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    // counted from March, so the leap day is at the end
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Register values of one read, before decoding
+#[derive(PartialEq, Eq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// Reads all registers once an update is not in progress
+unsafe fn read_raw(century_register: u8) -> Raw {
+    while read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    Raw {
+        second: read(REG_SECONDS),
+        minute: read(REG_MINUTES),
+        hour: read(REG_HOURS),
+        day: read(REG_DAY),
+        month: read(REG_MONTH),
+        year: read(REG_YEAR),
+        century: match century_register {
+            0 => 0,
+            reg => read(reg),
+        },
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Reads the current date and time
+///
+/// Takes up to a few milliseconds if the RTC is just updating.
+pub fn read_date_time() -> DateTime {
+    // ACPI tells where the century is kept, if anywhere
+    let century_register = acpi::get()
+        .and_then(|acpi| acpi.fadt())
+        .map_or(0, |fadt| fadt.century);
+
+    let (raw, status_b) = interrupts::without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            // an update might still start in between, so read until two reads agree
+            let mut raw = read_raw(century_register);
+            loop {
+                let again = read_raw(century_register);
+                if again == raw {
+                    break;
+                }
+                raw = again;
+            }
+            (raw, read(REG_STATUS_B))
+        }
+    });
+
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+    let mut hour = decode(raw.hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 am is midnight and 12 pm is noon
+        hour %= 12;
+        if raw.hour & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+    let century = match raw.century {
+        0 => 20,
+        century => decode(century) as u16,
+    };
+
+    DateTime {
+        year: century * 100 + decode(raw.year) as u16,
+        month: decode(raw.month),
+        day: decode(raw.day),
+        hour,
+        minute: decode(raw.minute),
+        second: decode(raw.second),
+    }
+}
+
+/// Enables the periodic interrupt at the lowest rate that is at least `hz`
+///
+/// The rate is a power of two between 2 and 8192 Hz, the actual rate is
+/// returned. The interrupt arrives on ISA IRQ 8 and has to be acknowledged
+/// with `acknowledge_interrupt`.
+pub fn enable_periodic_interrupt(hz: u64) -> u64 {
+    // rate 3 is the fastest setting that works reliably, 15 the slowest
+    let rate = (3..=15u8)
+        .rev()
+        .find(|&rate| BASE_FREQUENCY >> (rate - 1) >= hz)
+        .unwrap_or(3);
+
+    interrupts::without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_a = read(REG_STATUS_A);
+            write(REG_STATUS_A, (status_a & !STATUS_A_RATE) | rate);
+            let status_b = read(REG_STATUS_B);
+            write(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+            // the RTC only raises the next interrupt once this was read
+            read(REG_STATUS_C);
+        }
+    });
+    BASE_FREQUENCY >> (rate - 1)
+}
+
+/// Has to be called on every RTC interrupt, otherwise no further interrupts arrive
+pub fn acknowledge_interrupt() {
+    let _cmos = CMOS.lock();
+    unsafe { read(REG_STATUS_C) };
+}
+
+#[test_case]
+fn test_unix_timestamp_conversion() {
+    let epoch = DateTime::from_unix_timestamp(0);
+    assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+    let leap_day = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 12,
+        minute: 34,
+        second: 56,
+    };
+    assert_eq!(leap_day.to_unix_timestamp(), 1709210096);
+    assert_eq!(DateTime::from_unix_timestamp(1709210096), leap_day);
+    assert_eq!(DateTime::from_unix_timestamp(946684800).year, 2000);
+}
+
+#[test_case]
+fn test_bcd() {
+    assert_eq!(from_bcd(0x59), 59);
+    assert_eq!(from_bcd(0x12), 12);
+}
@@ -0,0 +1,73 @@
+use crate::gdt;
+use core::arch::asm;
+use x86_64::VirtAddr;
+
+/// Lowest address user programs are allowed to use
+///
+/// User space covers whole level 4 entries, so it never shares page tables with
+/// the kernel (which the bootloader maps in the first entry).
+pub const USER_SPACE_START: u64 = 0x0000_0080_0000_0000;
+/// First address above user space
+pub const USER_SPACE_END: u64 = 0x0000_4000_0000_0000;
+
+/// Region handed out by the `mmap` syscall
+pub const USER_MMAP_START: u64 = 0x0000_1000_0000_0000;
+pub const USER_MMAP_END: u64 = 0x0000_2000_0000_0000;
+
+/// Checks that `[addr, addr + len)` lies completely in user space
+pub fn is_user_range(addr: u64, len: u64) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => addr >= USER_SPACE_START && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// Drops to ring 3 and continues at `entry` with the given stack
+///
+/// The current kernel stack is abandoned, the thread comes back into the
+/// kernel only through interrupts and syscalls.
+///
+/// # Safety
+///
+/// `entry` and `stack_pointer` have to be mapped user accessible.
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack_pointer: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    let code_selector = selectors.user_code_selector.0 as u64;
+    let data_selector = selectors.user_data_selector.0 as u64;
+
+    // build an interrupt stack frame and "return" into ring 3,
+    // clearing all registers so no kernel data leaks. The kernel GS base is
+    // swapped out with interrupts disabled, a handler running in between would
+    // see the user GS. iretq enables interrupts again.
+    asm!(
+        "
+        push rax
+        push rsi
+        push 0x202
+        push rdx
+        push rdi
+        xor eax, eax
+        xor ebx, ebx
+        xor ecx, ecx
+        xor edx, edx
+        xor esi, esi
+        xor edi, edi
+        xor ebp, ebp
+        xor r8, r8
+        xor r9, r9
+        xor r10, r10
+        xor r11, r11
+        xor r12, r12
+        xor r13, r13
+        xor r14, r14
+        xor r15, r15
+        cli
+        swapgs
+        iretq",
+        in("rax") data_selector,
+        in("rsi") stack_pointer.as_u64(),
+        in("rdx") code_selector,
+        in("rdi") entry.as_u64(),
+        options(noreturn)
+    );
+}
@@ -1,8 +1,15 @@
+pub(crate) mod ansi;
+pub(crate) mod cp437;
+
+use crate::emergency::{self, ScreenWriter};
+use alloc::collections::VecDeque;
+use ansi::{Action, Csi, Parser};
 use core::fmt::{self};
+use core::ops::Range;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use volatile::Volatile;
-use x86_64::instructions::interrupts;
+use x86_64::instructions::{interrupts, port::Port};
 
 #[macro_export]
 macro_rules! print {
@@ -20,9 +27,62 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    print_to(KERNEL_CONSOLE, args);
+}
+
+/// Writes to virtual console `console`, it only shows while it is active
+///
+/// In graphics mode the framebuffer console keeps and draws the text instead.
+pub fn print_to(console: usize, args: fmt::Arguments) {
     use core::fmt::Write;
+    // an interrupt between taking the guard and disabling interrupts would
+    // find the cpu printing and bypass the locks
+    interrupts::without_interrupts(|| {
+        let _guard = match emergency::enter() {
+            Some(guard) => guard,
+            None => {
+                let _ = ScreenWriter.write_fmt(args);
+                return;
+            }
+        };
+        if !crate::framebuffer::console::print_to(console, args) {
+            CONSOLES.lock().writers[console].write_fmt(args).unwrap();
+        }
+    });
+}
+
+/// Shows virtual console `console` on the screen
+pub fn switch_console(console: usize) {
+    if console >= CONSOLE_COUNT {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let _guard = match emergency::enter() {
+            Some(guard) => guard,
+            None => return,
+        };
+        CONSOLES.lock().switch(console);
+        crate::framebuffer::console::switch(console);
+    });
+}
+
+pub fn active_console() -> usize {
+    interrupts::without_interrupts(|| CONSOLES.lock().active)
+}
+
+/// Scrolls the active console `lines` back into the history, negative values scroll forward
+pub fn scroll(lines: isize) {
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let _guard = match emergency::enter() {
+            Some(guard) => guard,
+            None => return,
+        };
+        if crate::framebuffer::console::scroll(lines) {
+            return;
+        }
+        let mut consoles = CONSOLES.lock();
+        let active = consoles.active;
+        consoles.writers[active].scroll(lines);
     });
 }
 
@@ -48,6 +108,41 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    /// Colors in the order of the escape sequences, the bright ones follow
+    const ANSI: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Brown,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::Yellow,
+        Color::LightBlue,
+        Color::Pink,
+        Color::LightCyan,
+        Color::White,
+    ];
+
+    /// Color `index` of the 8 basic ones
+    fn ansi(index: u16, bright: bool) -> Color {
+        Color::ANSI[usize::from(index) + if bright { 8 } else { 0 }]
+    }
+
+    /// The bright variant, like a terminal shows bold text
+    fn bright(self) -> Color {
+        match Color::ANSI.iter().position(|&color| color == self) {
+            Some(i) => Color::ANSI[i | 8],
+            None => self,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct ColorCode(u8);
@@ -67,73 +162,371 @@ struct ScreenChar {
 
 const BUFFER_WIDTH: usize = 80;
 const BUFFER_HEIGHT: usize = 20;
+const TAB_WIDTH: usize = 8;
+
+pub const CONSOLE_COUNT: usize = 4;
+/// Console `print!` writes to
+pub const KERNEL_CONSOLE: usize = 0;
+/// Lines each console keeps after they scrolled off the screen
+pub(crate) const SCROLLBACK_LINES: usize = 1000;
+
+const DEFAULT_FOREGROUND: Color = Color::LightGray;
+const DEFAULT_BACKGROUND: Color = Color::Black;
+
+// CRT controller registers of the hardware cursor
+const CRTC_INDEX_PORT: u16 = 0x3d4;
+const CRTC_DATA_PORT: u16 = 0x3d5;
+const CURSOR_START: u8 = 0x0a;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+const CURSOR_DISABLED: u8 = 1 << 5;
 
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode((DEFAULT_BACKGROUND as u8) << 4 | DEFAULT_FOREGROUND as u8),
+};
+
+/// Colors selected by escape sequences
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Attributes {
+    foreground: Color,
+    background: Color,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Attributes {
+    pub(crate) const DEFAULT: Attributes = Attributes {
+        foreground: DEFAULT_FOREGROUND,
+        background: DEFAULT_BACKGROUND,
+        bold: false,
+        reverse: false,
+    };
+
+    /// Foreground and background color the text is shown in
+    pub(crate) fn colors(&self) -> (Color, Color) {
+        let foreground = if self.bold {
+            self.foreground.bright()
+        } else {
+            self.foreground
+        };
+        if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        }
+    }
+
+    fn color_code(&self) -> ColorCode {
+        let (foreground, background) = self.colors();
+        ColorCode::new(foreground, background)
+    }
+
+    /// Applies the parameters of an `ESC [ ... m` sequence
+    pub(crate) fn select_graphic_rendition(&mut self, params: &[u16]) {
+        // no parameters reset everything
+        if params.is_empty() {
+            *self = Attributes::DEFAULT;
+        }
+        for &param in params {
+            match param {
+                0 => *self = Attributes::DEFAULT,
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.foreground = Color::ansi(param - 30, false),
+                39 => self.foreground = DEFAULT_FOREGROUND,
+                40..=47 => self.background = Color::ansi(param - 40, false),
+                49 => self.background = DEFAULT_BACKGROUND,
+                90..=97 => self.foreground = Color::ansi(param - 90, true),
+                100..=107 => self.background = Color::ansi(param - 100, true),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Writes text to a virtual console
+///
+/// Understands newline, carriage return, backspace and tab, and the escape
+/// sequences that move the cursor, erase and select colors. Other characters
+/// are shown in code page 437 if the font has them.
+///
+/// Keeps its own copy of the screen, only the writer of the active console
+/// holds the VGA buffer and draws into it.
 pub struct Writer {
     column_position: usize,
+    row_position: usize,
+    /// cursor position stored by `ESC [ s`
+    saved_position: (usize, usize),
+    attributes: Attributes,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    parser: Parser,
+    cursor_visible: bool,
+    screen: [Line; BUFFER_HEIGHT],
+    /// lines that scrolled off the top, the newest last
+    scrollback: VecDeque<Line>,
+    /// lines the view is scrolled back into the history
+    scroll_offset: usize,
+    buffer: Option<&'static mut Buffer>,
 }
 
 impl Writer {
+    fn new() -> Self {
+        Writer {
+            column_position: 0,
+            // output starts at the bottom and scrolls up
+            row_position: BUFFER_HEIGHT - 1,
+            saved_position: (0, 0),
+            attributes: Attributes::DEFAULT,
+            color_code: Attributes::DEFAULT.color_code(),
+            parser: Parser::new(),
+            cursor_visible: true,
+            screen: [[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            scrollback: VecDeque::new(),
+            scroll_offset: 0,
+            buffer: None,
+        }
+    }
+
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable
-                0x20..=0x7e => self.write_byte(byte),
-                // newline
-                b'\n' => self.new_line(),
-                // non-printable
-                _ => self.write_byte(0xfe),
+        // new output is always shown
+        if self.scroll_offset != 0 {
+            self.scroll_offset = 0;
+            self.render();
+        }
+        for c in s.chars() {
+            match self.parser.advance(c) {
+                Some(Action::Print(c)) => self.write_char(c),
+                Some(Action::Csi(csi)) => self.control_sequence(&csi),
+                None => {}
+            }
+        }
+        self.update_cursor();
+    }
+
+    fn write_char(&mut self, c: char) {
+        match c {
+            '\n' | '\r' => self.write_byte(c as u8),
+            // moves back without erasing, like a terminal
+            '\x08' => {
+                self.column_position = self.column_position.min(BUFFER_WIDTH - 1).saturating_sub(1)
+            }
+            '\t' => {
+                let next = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column_position = next.min(BUFFER_WIDTH - 1);
             }
+            // non-printable
+            '\0'..='\x1f' | '\x7f' => self.write_byte(0xfe),
+            c => self.write_byte(cp437::from_char(c).unwrap_or(0xfe)),
         }
     }
 
+    /// Writes a character of code page 437 at the cursor
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
             byte => {
                 if self.column_position >= BUFFER_WIDTH {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.row_position;
                 let col = self.column_position;
 
-                // let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code: self.color_code,
-                });
+                self.put(
+                    row,
+                    col,
+                    ScreenChar {
+                        ascii_character: byte,
+                        color_code: self.color_code,
+                    },
+                );
                 self.column_position += 1;
             }
         }
     }
 
     fn new_line(&mut self) {
-        for row in 0..BUFFER_HEIGHT - 1 {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row + 1][col].read();
-                self.buffer.chars[row][col].write(character);
-            }
+        self.column_position = 0;
+        if self.row_position < BUFFER_HEIGHT - 1 {
+            self.row_position += 1;
+            return;
         }
+        self.push_scrollback(self.screen[0]);
+        self.screen.copy_within(1.., 0);
+        self.render();
         self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+    }
+
+    /// Keeps a line that scrolled off, without the heap it is lost
+    fn push_scrollback(&mut self, line: Line) {
+        if self.scrollback.len() == SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        } else if self.scrollback.try_reserve(1).is_err() {
+            return;
+        }
+        self.scrollback.push_back(line);
+    }
+
+    fn put(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        // the view only matches the screen while it isn't scrolled back
+        if let (Some(buffer), 0) = (&mut self.buffer, self.scroll_offset) {
+            buffer.chars[row][col].write(character);
+        }
+    }
+
+    /// Moves the view `lines` back into the history, or forward for negative values
+    fn scroll(&mut self, lines: isize) {
+        let offset = self.scroll_offset.saturating_add_signed(lines);
+        self.scroll_offset = offset.min(self.scrollback.len());
+        self.render();
+    }
+
+    /// Draws the view into the VGA buffer if this console is active
+    fn render(&mut self) {
+        let buffer = match &mut self.buffer {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let top = self.scrollback.len() - self.scroll_offset;
+        for row in 0..BUFFER_HEIGHT {
+            let line = match self.scrollback.get(top + row) {
+                Some(line) => line,
+                None => &self.screen[top + row - self.scrollback.len()],
+            };
+            for (col, &character) in line.iter().enumerate() {
+                buffer.chars[row][col].write(character);
+            }
+        }
+    }
+
+    /// Takes over the VGA buffer and shows this console
+    fn attach(&mut self, buffer: &'static mut Buffer) {
+        self.buffer = Some(buffer);
+        self.render();
+        self.update_cursor();
+        set_cursor_visible(self.cursor_visible);
     }
 
     fn clear_row(&mut self, row: usize) {
+        self.clear_cells(row, 0..BUFFER_WIDTH);
+    }
+
+    fn clear_cells(&mut self, row: usize, cols: Range<usize>) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        for col in cols {
+            self.put(row, col, blank);
+        }
+    }
+
+    fn control_sequence(&mut self, csi: &Csi) {
+        // a cursor behind the last column waits for the next character to wrap
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let row = self.row_position;
+        let count = usize::from(csi.param(0, 1));
+
+        match (csi.private, csi.action) {
+            (false, 'A') => self.row_position = row.saturating_sub(count),
+            (false, 'B') => self.row_position = (row + count).min(BUFFER_HEIGHT - 1),
+            (false, 'C') => self.column_position = (col + count).min(BUFFER_WIDTH - 1),
+            (false, 'D') => self.column_position = col.saturating_sub(count),
+            (false, 'E') => self.move_to(row + count, 0),
+            (false, 'F') => self.move_to(row.saturating_sub(count), 0),
+            (false, 'G') => self.move_to(row, count - 1),
+            (false, 'H' | 'f') => self.move_to(count - 1, usize::from(csi.param(1, 1)) - 1),
+            (false, 'J') => self.erase_display(csi.param(0, 0), row, col),
+            (false, 'K') => self.erase_line(csi.param(0, 0), row, col),
+            (false, 'm') => self.select_graphic_rendition(csi.params()),
+            (false, 's') => self.saved_position = (row, col),
+            (false, 'u') => (self.row_position, self.column_position) = self.saved_position,
+            (true, 'h' | 'l') if csi.param(0, 0) == 25 => {
+                self.cursor_visible = csi.action == 'h';
+                if self.buffer.is_some() {
+                    set_cursor_visible(self.cursor_visible);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row_position = row.min(BUFFER_HEIGHT - 1);
+        self.column_position = col.min(BUFFER_WIDTH - 1);
+    }
+
+    /// 0 erases from the cursor to the end, 1 from the start to the cursor, 2 all of it
+    fn erase_display(&mut self, mode: u16, row: usize, col: usize) {
+        match mode {
+            0 => {
+                self.clear_cells(row, col..BUFFER_WIDTH);
+                (row + 1..BUFFER_HEIGHT).for_each(|row| self.clear_row(row));
+            }
+            1 => {
+                (0..row).for_each(|row| self.clear_row(row));
+                self.clear_cells(row, 0..col + 1);
+            }
+            2 => (0..BUFFER_HEIGHT).for_each(|row| self.clear_row(row)),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16, row: usize, col: usize) {
+        match mode {
+            0 => self.clear_cells(row, col..BUFFER_WIDTH),
+            1 => self.clear_cells(row, 0..col + 1),
+            2 => self.clear_row(row),
+            _ => {}
         }
     }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        self.attributes.select_graphic_rendition(params);
+        self.color_code = self.attributes.color_code();
+    }
+
+    /// Moves the blinking hardware cursor to the cursor position
+    fn update_cursor(&self) {
+        if self.buffer.is_none() {
+            return;
+        }
+        let col = self.column_position.min(BUFFER_WIDTH - 1);
+        let position = (self.row_position * BUFFER_WIDTH + col) as u16;
+        write_crtc(CURSOR_LOCATION_LOW, position as u8);
+        write_crtc(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+}
+
+fn write_crtc(register: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(CRTC_INDEX_PORT).write(register);
+        Port::<u8>::new(CRTC_DATA_PORT).write(value);
+    }
+}
+
+fn set_cursor_visible(visible: bool) {
+    let start = unsafe {
+        Port::<u8>::new(CRTC_INDEX_PORT).write(CURSOR_START);
+        Port::<u8>::new(CRTC_DATA_PORT).read()
+    };
+    if visible {
+        write_crtc(CURSOR_START, start & !CURSOR_DISABLED);
+    } else {
+        write_crtc(CURSOR_START, start | CURSOR_DISABLED);
+    }
 }
 
 impl fmt::Write for Writer {
@@ -143,14 +536,46 @@ impl fmt::Write for Writer {
     }
 }
 
-lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::LightGray, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+/// The virtual consoles, one of them is shown
+pub struct Consoles {
+    writers: [Writer; CONSOLE_COUNT],
+    active: usize,
+}
+
+impl Consoles {
+    fn switch(&mut self, console: usize) {
+        if console == self.active || console >= CONSOLE_COUNT {
+            return;
+        }
+        // in graphics mode the framebuffer console draws it
+        let buffer = self.writers[self.active].buffer.take();
+        self.active = console;
+        if let Some(buffer) = buffer {
+            self.writers[console].attach(buffer);
+        }
+    }
+}
+
+/// Stops drawing into the text buffer, the display shows graphics from now on
+pub(crate) fn release_text_buffer() {
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        let active = consoles.active;
+        consoles.writers[active].buffer = None;
     });
 }
 
+lazy_static! {
+    pub static ref CONSOLES: Mutex<Consoles> = {
+        let mut writers: [Writer; CONSOLE_COUNT] = core::array::from_fn(|_| Writer::new());
+        writers[KERNEL_CONSOLE].buffer = Some(unsafe { &mut *(0xb8000 as *mut Buffer) });
+        Mutex::new(Consoles {
+            writers,
+            active: KERNEL_CONSOLE,
+        })
+    };
+}
+
 #[test_case]
 fn test_print_simple() {
     print!("vga buffer output tested");
@@ -180,11 +605,91 @@ fn test_println_output() {
     use core::fmt::Write;
     let s = "this is a test output string";
     interrupts::without_interrupts(|| {
-        let mut writer = WRITER.lock();
+        let mut consoles = CONSOLES.lock();
+        let writer = &mut consoles.writers[KERNEL_CONSOLE];
         writeln!(writer, "\n{s}").expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.screen[BUFFER_HEIGHT - 2][i];
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
 }
+
+#[test_case]
+fn test_escape_sequences() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        let writer = &mut consoles.writers[KERNEL_CONSOLE];
+        // top left corner, red on blue, then back to the defaults
+        write!(writer, "\x1b[s\x1b[1;1H\x1b[31;44mab\x1b[0m\x1b[Dc\x1b[u").unwrap();
+        let red_on_blue = ColorCode::new(Color::Red, Color::Blue);
+        let first = writer.screen[0][0];
+        assert_eq!(
+            (first.ascii_character, first.color_code),
+            (b'a', red_on_blue)
+        );
+        let second = writer.screen[0][1];
+        assert_eq!(second.ascii_character, b'c');
+        assert_eq!(second.color_code, Attributes::DEFAULT.color_code());
+
+        write!(writer, "\x1b[1;1H\x1b[2K\x1b[u").unwrap();
+        assert_eq!(writer.screen[0][0].ascii_character, b' ');
+    });
+}
+
+#[test_case]
+fn test_tab_backspace_and_unicode() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        let writer = &mut consoles.writers[KERNEL_CONSOLE];
+        writeln!(writer).unwrap();
+        write!(writer, "x\tab\x08é─€").unwrap();
+        let row = writer.row_position;
+        let chars: [u8; 4] = core::array::from_fn(|i| writer.screen[row][8 + i].ascii_character);
+        assert_eq!(chars, [b'a', 0x82, 0xc4, 0xfe]);
+        writeln!(writer).unwrap();
+    });
+}
+
+#[test_case]
+fn test_scrollback_keeps_old_lines() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        let writer = &mut consoles.writers[KERNEL_CONSOLE];
+        writeln!(writer, "\nscrolled off").unwrap();
+        for _ in 0..BUFFER_HEIGHT {
+            writeln!(writer).unwrap();
+        }
+        let line = writer.scrollback[writer.scrollback.len() - 2];
+        assert_eq!(line[..12].map(|c| c.ascii_character), *b"scrolled off");
+
+        writer.scroll(BUFFER_HEIGHT as isize);
+        let shown = writer.buffer.as_ref().unwrap().chars[BUFFER_HEIGHT - 2][0].read();
+        assert_eq!(shown.ascii_character, b's');
+        // output scrolls back down
+        writeln!(writer).unwrap();
+        assert_eq!(writer.scroll_offset, 0);
+    });
+}
+
+#[test_case]
+fn test_switch_console() {
+    print_to(1, format_args!("\x1b[1;1Hsecond console"));
+    interrupts::without_interrupts(|| {
+        let mut consoles = CONSOLES.lock();
+        let kernel_char = consoles.writers[KERNEL_CONSOLE].screen[0][0];
+        assert_eq!(consoles.writers[1].screen[0][0].ascii_character, b's');
+
+        consoles.switch(1);
+        let buffer = consoles.writers[1].buffer.as_ref().expect("not attached");
+        assert_eq!(buffer.chars[0][0].read().ascii_character, b's');
+        assert!(consoles.writers[KERNEL_CONSOLE].buffer.is_none());
+
+        consoles.switch(KERNEL_CONSOLE);
+        let buffer = consoles.writers[KERNEL_CONSOLE].buffer.as_ref().unwrap();
+        assert_eq!(buffer.chars[0][0].read(), kernel_char);
+    });
+}
@@ -0,0 +1,151 @@
+/// Most parameters a control sequence can have, the rest are dropped
+const MAX_PARAMS: usize = 8;
+
+/// What the writer has to do for a character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// show the character or handle the control character
+    Print(char),
+    Csi(Csi),
+}
+
+/// A control sequence, `ESC [` followed by parameters and the final character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// started with `?`, like the sequences that show and hide the cursor
+    pub private: bool,
+    pub action: char,
+}
+
+impl Csi {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Returns parameter `i`, or `default` if it is missing or zero
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        match self.params().get(i) {
+            Some(&value) if value != 0 => value,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// got the escape character
+    Escape,
+    /// inside a control sequence
+    Csi,
+}
+
+/// Splits the text into characters to print and escape sequences
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            state: State::Ground,
+            csi: Csi {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                action: '\0',
+            },
+        }
+    }
+
+    pub fn advance(&mut self, c: char) -> Option<Action> {
+        match (self.state, c) {
+            (State::Ground, '\x1b') => {
+                self.state = State::Escape;
+                None
+            }
+            (State::Ground, c) => Some(Action::Print(c)),
+            (State::Escape, '[') => {
+                self.state = State::Csi;
+                self.csi.params = [0; MAX_PARAMS];
+                self.csi.len = 0;
+                self.csi.private = false;
+                None
+            }
+            // other escape sequences are not supported
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                None
+            }
+            (State::Csi, '?') if self.csi.len == 0 => {
+                self.csi.private = true;
+                None
+            }
+            (State::Csi, digit @ '0'..='9') => {
+                if self.csi.len == 0 {
+                    self.csi.len = 1;
+                }
+                let param = &mut self.csi.params[self.csi.len - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(digit as u16 - '0' as u16);
+                None
+            }
+            (State::Csi, ';') => {
+                // an empty first parameter still counts
+                self.csi.len = (self.csi.len.max(1) + 1).min(MAX_PARAMS);
+                None
+            }
+            (State::Csi, action @ '\x40'..='\x7e') => {
+                self.state = State::Ground;
+                self.csi.action = action;
+                Some(Action::Csi(self.csi))
+            }
+            // anything else aborts the sequence
+            (State::Csi, _) => {
+                self.state = State::Ground;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse(s: &str) -> alloc::vec::Vec<Action> {
+    let mut parser = Parser::new();
+    s.chars().filter_map(|c| parser.advance(c)).collect()
+}
+
+#[test_case]
+fn test_parse_control_sequences() {
+    let actions = parse("a\x1b[12;5Hb\x1b[m\x1b[?25l");
+    assert_eq!(actions.len(), 5);
+    assert_eq!(actions[0], Action::Print('a'));
+    match actions[1] {
+        Action::Csi(csi) => {
+            assert_eq!(csi.action, 'H');
+            assert_eq!(csi.params(), [12, 5]);
+        }
+        action => panic!("expected a control sequence, got {:?}", action),
+    }
+    assert_eq!(actions[2], Action::Print('b'));
+    match actions[3] {
+        Action::Csi(csi) => assert_eq!((csi.action, csi.param(0, 0)), ('m', 0)),
+        action => panic!("expected a control sequence, got {:?}", action),
+    }
+    match actions[4] {
+        Action::Csi(csi) => assert!(csi.private && csi.action == 'l' && csi.param(0, 0) == 25),
+        action => panic!("expected a control sequence, got {:?}", action),
+    }
+}
+
+#[test_case]
+fn test_missing_parameters_use_the_default() {
+    match parse("\x1b[;7H")[..] {
+        [Action::Csi(csi)] => assert_eq!((csi.param(0, 1), csi.param(1, 1)), (1, 7)),
+        ref actions => panic!("expected one control sequence, got {:?}", actions),
+    }
+}
@@ -0,0 +1,47 @@
+/// Characters of code page 437 from 0x80 to 0xff, the ones below are ASCII
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Glyphs the font has in place of the control characters 0x01 to 0x1f
+const LOW: [char; 31] = [
+    '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼', //
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// Returns the byte of code page 437 that shows `c`, if the font has it
+pub fn from_char(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' => Some(c as u8),
+        '⌂' => Some(0x7f),
+        // the same glyphs under other names
+        'β' => Some(0xe1),
+        'μ' => Some(0xe6),
+        'Ø' | '∅' => Some(0xed),
+        '∈' => Some(0xee),
+        _ => {
+            if let Some(i) = HIGH.iter().position(|&high| high == c) {
+                Some(0x80 + i as u8)
+            } else {
+                LOW.iter().position(|&low| low == c).map(|i| 0x01 + i as u8)
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_cp437_translation() {
+    assert_eq!(from_char('A'), Some(b'A'));
+    assert_eq!(from_char('é'), Some(0x82));
+    assert_eq!(from_char('─'), Some(0xc4));
+    assert_eq!(from_char('■'), Some(0xfe));
+    assert_eq!(from_char('♥'), Some(0x03));
+    assert_eq!(from_char('€'), None);
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::acpi;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    acpi::init().expect("no ACPI tables");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn tables_are_found() {
+    let acpi = acpi::get().unwrap();
+    assert!(acpi.signatures().any(|signature| &signature == b"FACP"));
+    assert!(acpi.madt().is_some());
+}
+
+#[test_case]
+fn fadt_has_power_management() {
+    let fadt = acpi::get().unwrap().fadt().expect("no FADT");
+    assert_ne!(fadt.pm1a_control_block, 0);
+    assert_ne!(fadt.dsdt.as_u64(), 0);
+}
+
+#[test_case]
+fn hpet_is_described() {
+    // QEMU provides an HPET by default
+    let hpet = acpi::get().unwrap().hpet().expect("no HPET");
+    assert_ne!(hpet.address.as_u64(), 0);
+}
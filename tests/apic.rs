@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::{acpi, interrupts::apic, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    apic::init().expect("APIC initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_describes_the_machine() {
+    let madt = acpi::get().unwrap().madt().expect("no MADT");
+    assert!(madt.processors.iter().any(|p| p.apic_id == apic::id()));
+    assert!(!madt.io_apics.is_empty());
+}
+
+#[test_case]
+fn timer_interrupts_arrive_through_the_io_apic() {
+    assert!(apic::is_enabled());
+    // only returns if the timer keeps ticking
+    thread::sleep_ticks(3);
+}
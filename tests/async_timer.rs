@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::future;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rustkernel::task::{self, executor::Executor, timer};
+use rustkernel::thread;
+use rustkernel::time::{Duration, Instant};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+    // runs the tasks the tests spawn
+    thread::spawn_thread(|| Executor::new().run());
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+/// Sleeps until `done` is set, panics after a few seconds
+fn wait_for(done: &AtomicBool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done.load(Ordering::SeqCst) {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test_case]
+fn sleep_waits_for_the_duration() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    task::spawn(async {
+        let start = Instant::now();
+        timer::sleep(Duration::from_millis(50)).await;
+        ELAPSED.store(start.elapsed().as_nanos() as u64, Ordering::SeqCst);
+        DONE.store(true, Ordering::SeqCst);
+    });
+    wait_for(&DONE);
+    assert!(ELAPSED.load(Ordering::SeqCst) >= 50_000_000);
+}
+
+#[test_case]
+fn sleep_until_runs_periodically() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static ROUNDS: AtomicU64 = AtomicU64::new(0);
+
+    task::spawn(async {
+        let period = Duration::from_millis(20);
+        let mut next = Instant::now();
+        for _ in 0..5 {
+            next += period;
+            timer::sleep_until(next).await;
+            assert!(Instant::now() >= next);
+            ROUNDS.fetch_add(1, Ordering::SeqCst);
+        }
+        DONE.store(true, Ordering::SeqCst);
+    });
+    wait_for(&DONE);
+    assert_eq!(ROUNDS.load(Ordering::SeqCst), 5);
+}
+
+#[test_case]
+fn timeout_expires() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    task::spawn(async {
+        let result = timer::timeout(future::pending::<()>(), Duration::from_millis(30)).await;
+        assert_eq!(result, Err(timer::Elapsed));
+        DONE.store(true, Ordering::SeqCst);
+    });
+    wait_for(&DONE);
+}
+
+#[test_case]
+fn timeout_returns_the_result() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    task::spawn(async {
+        let slow = async {
+            timer::sleep(Duration::from_millis(10)).await;
+            42
+        };
+        let result = timer::timeout(slow, Duration::from_secs(1)).await;
+        assert_eq!(result, Ok(42));
+        DONE.store(true, Ordering::SeqCst);
+    });
+    wait_for(&DONE);
+}
@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::memory::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+use x86_64::PhysAddr;
+
+entry_point!(main);
+
+static ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { rustkernel::memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+    *ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+fn with_allocator(f: impl FnOnce(&mut BitmapFrameAllocator)) {
+    f(ALLOCATOR
+        .lock()
+        .as_mut()
+        .expect("allocator not initialized"))
+}
+
+#[test_case]
+fn counts() {
+    with_allocator(|frames| {
+        assert!(frames.free_frames() > 0);
+        assert_eq!(
+            frames.free_frames() + frames.used_frames(),
+            frames.usable_frames()
+        );
+    });
+}
+
+#[test_case]
+fn freed_frames_are_reused() {
+    with_allocator(|frames| {
+        let free = frames.free_frames();
+        let frame = frames.allocate_frame().expect("out of frames");
+        assert_eq!(frames.free_frames(), free - 1);
+
+        unsafe { frames.deallocate_frame(frame) };
+        assert_eq!(frames.free_frames(), free);
+        assert_eq!(frames.allocate_frame(), Some(frame));
+        unsafe { frames.deallocate_frame(frame) };
+    });
+}
+
+#[test_case]
+fn no_leak() {
+    // allocates more frames than a qemu default machine has, if nothing is freed
+    with_allocator(|frames| {
+        let free = frames.free_frames();
+        for _ in 0..100_000 {
+            let frame = frames.allocate_frame().expect("out of frames");
+            unsafe { frames.deallocate_frame(frame) };
+        }
+        assert_eq!(frames.free_frames(), free);
+    });
+}
+
+#[test_case]
+fn contiguous() {
+    with_allocator(|frames| {
+        let free = frames.free_frames();
+        let range = frames
+            .allocate_contiguous(16, 16)
+            .expect("no contiguous frames");
+        assert_eq!(range.count(), 16);
+        assert_eq!(range.start.start_address().as_u64() % (16 * 4096), 0);
+        assert_eq!(frames.free_frames(), free - 16);
+
+        let single = frames.allocate_frame().expect("out of frames");
+        assert!(single < range.start || single >= range.end);
+
+        unsafe {
+            frames.deallocate_frame(single);
+            frames.deallocate_contiguous(range);
+        }
+        assert_eq!(frames.free_frames(), free);
+    });
+}
+
+#[test_case]
+fn low_memory_is_kept_for_allocate_below() {
+    const ONE_MIB: u64 = 0x10_0000;
+    with_allocator(|frames| {
+        let frame = frames.allocate_frame().expect("out of frames");
+        assert!(frame.start_address().as_u64() >= ONE_MIB);
+
+        let low = frames
+            .allocate_below(PhysAddr::new(ONE_MIB))
+            .expect("no free frame below 1 MiB");
+        assert!(low.start_address().as_u64() < ONE_MIB);
+
+        unsafe {
+            frames.deallocate_frame(low);
+            frames.deallocate_frame(frame);
+        }
+    });
+}
@@ -0,0 +1,143 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::framebuffer::{bochs, console, Rgb};
+use rustkernel::println;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn pixels_read_back_in_24_bit_mode() {
+    assert!(bochs::is_present(), "QEMU should emulate the Bochs display");
+    let mut framebuffer = bochs::set_mode(800, 600, 24).expect("mode not set");
+    assert_eq!((framebuffer.width(), framebuffer.height()), (800, 600));
+
+    let color = Rgb::new(0x12, 0x34, 0x56);
+    framebuffer.fill_rect(790, 590, 20, 20, color);
+    assert_eq!(framebuffer.pixel(799, 599), Some(color));
+    assert_eq!(framebuffer.pixel(800, 599), None);
+
+    framebuffer.scroll_up(10, Rgb::BLACK);
+    assert_eq!(framebuffer.pixel(799, 589), Some(color));
+    assert_eq!(framebuffer.pixel(799, 599), Some(Rgb::BLACK));
+}
+
+#[test_case]
+fn console_takes_over_print() {
+    assert_eq!(console::init(640, 480, 32).expect("no console"), (80, 30));
+    assert!(console::is_active());
+    // scrolls a few times
+    for i in 0..100 {
+        println!("line {} \x1b[32mgreen\x1b[0m é─", i);
+    }
+}
+
+#[test_case]
+fn compositor_stacks_windows() {
+    use rustkernel::framebuffer::compositor;
+    use rustkernel::framebuffer::surface::{Rect, Rgba};
+
+    if !console::is_active() {
+        console::init(640, 480, 32).expect("no console");
+    }
+    let black = Rgba::new(0, 0, 0, 0xff);
+    let red = Rgba::new(0xff, 0, 0, 0xff);
+    let half_blue = Rgba::new(0, 0, 0xff, 0x80);
+    compositor::with(|compositor| {
+        // hides whatever the console shows
+        let backdrop = compositor.create_window(Rect::new(0, 0, 400, 400), black);
+        let bottom = compositor.create_window(Rect::new(10, 10, 20, 20), red);
+        let top = compositor.create_window(Rect::new(20, 20, 20, 20), half_blue);
+        compositor.compose();
+        assert_eq!(
+            compositor.screen_pixel(22, 22),
+            Some(Rgba::new(0x7f, 0, 0x80, 0xff))
+        );
+
+        compositor.move_window(top, 300, 300);
+        compositor.raise_window(bottom);
+        compositor.compose();
+        assert_eq!(compositor.screen_pixel(22, 22), Some(red));
+        // over the backdrop
+        assert_eq!(
+            compositor.screen_pixel(310, 310),
+            Some(Rgba::new(0, 0, 0x80, 0xff))
+        );
+        compositor.remove_window(top);
+        compositor.remove_window(bottom);
+        compositor.remove_window(backdrop);
+        compositor.compose();
+    })
+    .expect("no compositor");
+}
+
+#[test_case]
+fn virtual_consoles_keep_their_text() {
+    use rustkernel::framebuffer::compositor;
+    use rustkernel::framebuffer::surface::Rgba;
+    use rustkernel::vga_buffer::{print_to, scroll, switch_console, KERNEL_CONSOLE};
+
+    if !console::is_active() {
+        console::init(640, 480, 32).expect("no console");
+    }
+    // the middle of the top left cell, the block covers it
+    let first_cell = || {
+        compositor::with(|compositor| {
+            compositor.compose();
+            compositor.screen_pixel(4, 8)
+        })
+        .flatten()
+    };
+    let black = Some(Rgba::new(0, 0, 0, 0xff));
+
+    print_to(1, format_args!("\x1b[2J\x1b[1;1H█"));
+    // the cursor would be drawn inverted
+    print_to(2, format_args!("\x1b[2J\x1b[5;5H"));
+    switch_console(2);
+    assert_eq!(first_cell(), black);
+    switch_console(1);
+    assert_ne!(first_cell(), black);
+
+    // the block scrolls into the history and back into view
+    for _ in 0..40 {
+        print_to(1, format_args!("\n"));
+    }
+    assert_eq!(first_cell(), black);
+    scroll(100);
+    assert_ne!(first_cell(), black);
+    scroll(-100);
+    assert_eq!(first_cell(), black);
+
+    switch_console(KERNEL_CONSOLE);
+}
@@ -13,7 +13,7 @@ entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use rustkernel::allocator;
-    use rustkernel::memory::BootInfoFrameAllocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     rustkernel::init();
@@ -21,9 +21,10 @@ fn main(boot_info: &'static BootInfo) -> ! {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
 
     let mut mapper = unsafe { rustkernel::memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -75,3 +76,17 @@ fn many_boxes_long_lived() {
     }
     assert_eq!(*long_lived, 42);
 }
+
+#[test_case]
+fn heap_grows() {
+    // more than the initial heap, in one allocation and in many small ones
+    let big = alloc::vec![1u8; HEAP_SIZE * 4];
+    assert!(big.iter().all(|&b| b == 1));
+
+    let boxes: Vec<Box<[u64; 16]>> = (0..HEAP_SIZE / 64)
+        .map(|i| Box::new([i as u64; 16]))
+        .collect();
+    for (i, b) in boxes.iter().enumerate() {
+        assert_eq!(b[15], i as u64);
+    }
+}
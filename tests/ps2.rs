@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::ps2::{self, MouseType};
+use rustkernel::{acpi, interrupts, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    interrupts::apic::init().expect("APIC not available");
+    time::calibrate();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn finds_keyboard_and_wheel_mouse() {
+    let devices = ps2::init().expect("PS/2 controller not initialized");
+    assert!(devices.keyboard);
+    // QEMU emulates an IntelliMouse
+    assert_eq!(devices.mouse, Some(MouseType::Wheel));
+}
+
+#[test_case]
+fn init_can_be_repeated() {
+    ps2::init().expect("second initialization failed");
+}
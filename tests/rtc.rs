@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::time::{self, rtc, Duration, Instant, TickSource};
+use rustkernel::{acpi, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn date_is_plausible() {
+    let date = rtc::read_date_time();
+    assert!(date.year >= 2024, "RTC says {}", date);
+    assert!((1..=12).contains(&date.month));
+    assert!((1..=31).contains(&date.day));
+    assert!(date.hour < 24 && date.minute < 60 && date.second < 60);
+}
+
+#[test_case]
+fn wall_clock_follows_the_rtc() {
+    let rtc = rtc::read_date_time().to_unix_timestamp();
+    let now = time::now().unix_timestamp();
+    assert!(now.abs_diff(rtc) <= 1, "RTC {} wall clock {}", rtc, now);
+}
+
+#[test_case]
+fn wall_clock_advances() {
+    let start = time::now();
+    thread::sleep(Duration::from_millis(20));
+    let elapsed = time::now().duration_since_epoch() - start.duration_since_epoch();
+    assert!(
+        elapsed >= Duration::from_millis(20),
+        "advanced {:?}",
+        elapsed
+    );
+}
+
+// has to run last, the PIT stays off afterwards
+#[test_case]
+fn rtc_drives_the_tick() {
+    time::use_rtc_tick().expect("RTC interrupt not routed");
+    assert_eq!(time::tick_source(), TickSource::Rtc);
+
+    let start = (time::ticks(), Instant::now());
+    thread::sleep(Duration::from_millis(100));
+    assert!(time::ticks() > start.0 + 5);
+    assert!(start.1.elapsed() >= Duration::from_millis(100));
+}
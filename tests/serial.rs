@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use futures_util::StreamExt;
+use rustkernel::task::serial::{SerialStream, SerialWriter};
+use rustkernel::task::{self, executor::Executor};
+use rustkernel::time::{Duration, Instant};
+use rustkernel::{acpi, interrupts, serial, thread};
+use x86_64::instructions::interrupts::without_interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    interrupts::apic::init().expect("APIC not available");
+    thread::init();
+    // runs the tasks the tests spawn
+    thread::spawn_thread(|| Executor::new().run());
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn serial_interrupt_is_routed() {
+    task::serial::init().expect("IRQ 4 not routed");
+}
+
+/// Waits up to 5 seconds for `done`
+///
+/// COM1 also reports the test results, so loopback mode has to be turned off
+/// again before anything can fail.
+fn wait_in_loopback(done: &AtomicBool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done.load(Ordering::SeqCst) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    task::serial::set_loopback(false);
+    done.load(Ordering::SeqCst)
+}
+
+#[test_case]
+fn writer_waits_for_the_transmit_interrupt() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    task::serial::init().expect("IRQ 4 not routed");
+    // keeps the bytes off the host, nobody reads them back yet
+    task::serial::set_loopback(true);
+    task::spawn(async {
+        let mut writer = SerialWriter::new();
+        // more than the queue holds, so the interrupt has to drain it
+        for _ in 0..64 {
+            writer.write(&[b'.'; 64]).await;
+        }
+        writer.flush().await;
+        DONE.store(true, Ordering::SeqCst);
+    });
+
+    assert!(wait_in_loopback(&DONE), "transmit queue not drained");
+}
+
+#[test_case]
+fn stream_receives_in_loopback() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static MATCHED: AtomicBool = AtomicBool::new(false);
+
+    task::serial::init().expect("IRQ 4 not routed");
+    task::serial::set_loopback(true);
+    // created here, so nothing is dropped before the task runs
+    let mut stream = SerialStream::new();
+    task::spawn(async move {
+        let mut received = [0; 4];
+        for byte in received.iter_mut() {
+            *byte = stream.next().await.expect("stream ended");
+        }
+        MATCHED.store(&received == b"ping", Ordering::SeqCst);
+        DONE.store(true, Ordering::SeqCst);
+    });
+    without_interrupts(|| {
+        let mut serial = serial::SERIAL1.lock();
+        for &byte in b"ping" {
+            serial.send(byte);
+        }
+    });
+
+    assert!(wait_in_loopback(&DONE), "nothing received");
+    assert!(MATCHED.load(Ordering::SeqCst), "received the wrong bytes");
+}
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use rustkernel::{acpi, cpu, interrupts::apic, smp, task, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    apic::init().expect("APIC initialization failed");
+    thread::init();
+    smp::init().expect("starting the application processors failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+/// Sleeps until `done` returns true, panics after a few seconds
+fn wait_until(done: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if done() {
+            return;
+        }
+        thread::sleep_ticks(1);
+    }
+    panic!("timed out");
+}
+
+#[test_case]
+fn all_processors_are_running() {
+    let madt = acpi::get().unwrap().madt().unwrap();
+    let enabled = madt.processors.iter().filter(|p| p.enabled).count();
+    assert!(enabled > 1, "run the tests with more than one cpu");
+    assert_eq!(cpu::count(), enabled);
+    assert!(cpu::current().is_bsp());
+    assert_eq!(cpu::current().apic_id(), apic::id());
+}
+
+#[test_case]
+fn shared_tasks_run_on_application_processors() {
+    const TASKS: usize = 16;
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+    static CPUS: AtomicU64 = AtomicU64::new(0);
+
+    for _ in 0..TASKS {
+        task::spawn(async {
+            let cpu = cpu::current();
+            assert_eq!(cpu.apic_id(), apic::id());
+            CPUS.fetch_or(1 << cpu.index(), Ordering::SeqCst);
+            FINISHED.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    wait_until(|| FINISHED.load(Ordering::SeqCst) == TASKS);
+
+    // the bootstrap processor runs no executor here
+    let cpus = CPUS.load(Ordering::SeqCst);
+    assert_eq!(cpus & 1, 0);
+    assert_ne!(cpus, 0);
+}
@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use rustkernel::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { rustkernel::memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn spawn_and_join() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    let id = thread::spawn_thread(|| DONE.store(true, Ordering::SeqCst));
+    assert_eq!(thread::join(id), Some(thread::ExitStatus::Exited(0)));
+    assert!(DONE.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn detached_threads_leave_no_status() {
+    let before = thread::unjoined_count();
+    let ids: alloc::vec::Vec<_> = (0..10)
+        .map(|_| {
+            let id = thread::spawn_thread(|| {});
+            thread::detach(id);
+            id
+        })
+        .collect();
+    for id in ids {
+        assert_eq!(thread::join(id), None);
+    }
+    assert_eq!(thread::unjoined_count(), before);
+}
+
+#[test_case]
+fn many_threads() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let ids: alloc::vec::Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn_thread(|| {
+                for _ in 0..100 {
+                    COUNTER.fetch_add(1, Ordering::SeqCst);
+                    thread::yield_now();
+                }
+            })
+        })
+        .collect();
+    for id in ids {
+        thread::join(id);
+    }
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 400);
+}
+
+#[test_case]
+fn busy_thread_is_preempted() {
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    let id = thread::spawn_thread(|| {
+        STARTED.store(true, Ordering::SeqCst);
+        while !STOP.load(Ordering::SeqCst) {}
+    });
+
+    // neither loop yields, only the timer interrupt can switch between them
+    while !STARTED.load(Ordering::SeqCst) {}
+    STOP.store(true, Ordering::SeqCst);
+    thread::join(id);
+}
+
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    let local = volatile::Volatile::new(depth);
+    recurse(local.read() + 1) + 1
+}
+
+#[test_case]
+fn stack_overflow_kills_thread() {
+    // the guard page catches the overflow, only the overflowing thread is terminated
+    let id = thread::spawn_thread(|| {
+        recurse(0);
+    });
+    assert_eq!(thread::join(id), Some(thread::ExitStatus::Killed));
+
+    let id = thread::spawn_thread(|| {});
+    thread::join(id);
+}
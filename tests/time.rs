@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::time::{self, ClockSource, Duration, Instant};
+use rustkernel::{acpi, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    acpi::init().expect("no ACPI tables");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn ticks_advance() {
+    let start = time::ticks();
+    thread::sleep_ticks(2);
+    assert!(time::ticks() >= start + 2);
+}
+
+#[test_case]
+fn tick_is_close_to_the_requested_rate() {
+    let error = time::tick_duration().as_nanos() as i128 - (1_000_000_000 / time::TICK_HZ) as i128;
+    assert!(error.abs() < 10_000);
+}
+
+#[test_case]
+fn tsc_is_calibrated() {
+    // QEMU provides an HPET by default
+    assert_eq!(time::calibrate(), ClockSource::Hpet);
+    let hz = time::tsc_frequency().expect("TSC not calibrated");
+    assert!(hz > 100_000_000, "TSC runs at {} Hz", hz);
+}
+
+#[test_case]
+fn instant_is_monotonic() {
+    let mut last = Instant::now();
+    for _ in 0..10_000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn sleep_lasts_at_least_the_duration() {
+    let duration = Duration::from_millis(50);
+    let start = Instant::now();
+    thread::sleep(duration);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= duration, "slept for {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(1), "slept for {:?}", elapsed);
+}
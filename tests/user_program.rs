@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustkernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rustkernel::{elf, thread};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use rustkernel::allocator;
+    use rustkernel::memory::{self, BitmapFrameAllocator};
+    use x86_64::VirtAddr;
+
+    rustkernel::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) };
+
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustkernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn run_embedded_program() {
+    // a fault would kill the program instead
+    let id = elf::loader::spawn(elf::loader::HELLO, &["hello"], &["TEST=1"]);
+    assert_eq!(thread::join(id), Some(thread::ExitStatus::Exited(0)));
+}
+
+#[test_case]
+fn failed_load_unmaps_segments() {
+    use alloc::vec::Vec;
+    use rustkernel::memory::address_space::AddressSpace;
+    use x86_64::{structures::paging::Page, VirtAddr};
+
+    // move the last segment into kernel space, the ones before it are mapped by then
+    let mut data = Vec::from(elf::loader::HELLO);
+    let (first, last_header) = {
+        let elf = elf::ElfFile::parse(&data).expect("parsing failed");
+        let header = elf.header();
+        let first = elf.load_segments().next().expect("no segments").vaddr;
+        let last = header.phoff + (header.phnum as u64 - 1) * header.phentsize as u64;
+        (first, last as usize)
+    };
+    let vaddr = last_header + 16;
+    data[vaddr..vaddr + 8].copy_from_slice(&0xffff_8000_0000_0000u64.to_le_bytes());
+
+    let mut address_space = AddressSpace::new().expect("creating address space failed");
+    let result = elf::loader::load(&mut address_space, &data, &[], &[]);
+    assert!(matches!(
+        result,
+        Err(elf::loader::LoadError::NotInUserSpace)
+    ));
+    assert!(address_space
+        .translate(Page::containing_address(VirtAddr::new(first)))
+        .is_none());
+}
+
+#[test_case]
+fn reject_garbage() {
+    use rustkernel::memory::address_space::AddressSpace;
+
+    let mut address_space = AddressSpace::new().expect("creating address space failed");
+    let result = elf::loader::load(&mut address_space, &[0; 128], &[], &[]);
+    assert!(matches!(
+        result,
+        Err(elf::loader::LoadError::Elf(elf::ElfError::BadMagic))
+    ));
+}
+
+#[test_case]
+fn demand_paging() {
+    use alloc::sync::Arc;
+    use rustkernel::memory::address_space::AddressSpace;
+    use spin::Mutex;
+    use x86_64::{
+        structures::paging::{Page, PageTableFlags},
+        VirtAddr,
+    };
+
+    let id = thread::spawn_thread(|| {
+        let address_space = AddressSpace::new().expect("creating address space failed");
+        let address_space = Arc::new(Mutex::new(address_space));
+        thread::set_address_space(address_space.clone());
+
+        let start = VirtAddr::new(0x0000_1000_0000_0000);
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        address_space
+            .lock()
+            .reserve(start, 4 * 4096, flags)
+            .expect("reserving failed");
+        assert!(address_space
+            .lock()
+            .translate(Page::containing_address(start))
+            .is_none());
+
+        // the first access faults and maps a zeroed frame
+        let ptr = (start + 4096u64).as_mut_ptr::<u64>();
+        unsafe {
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(42);
+            assert_eq!(ptr.read_volatile(), 42);
+        }
+
+        let mut address_space = address_space.lock();
+        assert!(address_space
+            .translate(Page::containing_address(start))
+            .is_none());
+        assert!(address_space
+            .translate(Page::containing_address(start + 4096u64))
+            .is_some());
+    });
+    thread::join(id);
+}
+
+#[test_case]
+fn fault_on_present_page() {
+    use rustkernel::memory::{
+        address_space::AddressSpace,
+        vma::{Access, FaultError},
+    };
+    use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+    let mut address_space = AddressSpace::new().expect("creating address space failed");
+    let start = VirtAddr::new(0x0000_1000_0000_0000);
+    address_space
+        .reserve(start, 4096, PageTableFlags::NO_EXECUTE)
+        .expect("reserving failed");
+
+    // the second fault finds the page mapped by the first one
+    assert_eq!(address_space.handle_fault(start, Access::Read), Ok(()));
+    assert_eq!(
+        address_space.handle_fault(start + 8u64, Access::Read),
+        Ok(())
+    );
+    assert!(matches!(
+        address_space.handle_fault(start, Access::Write),
+        Err(FaultError::AccessViolation(_))
+    ));
+}
@@ -0,0 +1,109 @@
+#!/usr/bin/env python3
+"""Writes the function symbols of a kernel binary into its `.ksyms` section.
+
+The kernel reserves the section (see src/backtrace/symbols.rs) and looks up
+the names in it to print backtraces. The table has a fixed size, so filling it
+in after linking moves nothing. Binaries without the section are left alone.
+
+usage: embed_symbols.py <kernel elf>
+"""
+
+import re
+import struct
+import subprocess
+import sys
+
+SECTION = ".ksyms"
+MAGIC = b"KSYM"
+# address, size, name offset and name length
+ENTRY = struct.Struct("<QQII")
+HEADER = struct.Struct("<4sI")
+# `MAX_NAME` of the kernel, longer names are cut off
+MAX_NAME = 128
+SHT_PROGBITS = 1
+
+NM_LINE = re.compile(r"^([0-9a-f]+) (?:([0-9a-f]+) )?([tTwW]) (.*)$")
+HASH = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def find_section(elf, name):
+    """Returns the file offset, size and type of section `name`, or None"""
+    if elf[:4] != b"\x7fELF" or elf[4] != 2 or elf[5] != 1:
+        sys.exit("not a 64-bit little endian ELF file")
+    (shoff,) = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3A)
+
+    def header(index):
+        # name, type, flags, address, offset and size
+        return struct.unpack_from("<IIQQQQ", elf, shoff + index * shentsize)
+
+    names = header(shstrndx)[4]
+    for index in range(shnum):
+        name_offset, kind, _, _, offset, size = header(index)
+        start = names + name_offset
+        if elf[start : elf.index(b"\0", start)] == name.encode():
+            return offset, size, kind
+    return None
+
+
+def symbols(path):
+    """Function symbols sorted by address, one per address"""
+    nm = ["nm", "--defined-only", "--print-size", "--numeric-sort", "--demangle", path]
+    output = subprocess.run(nm, capture_output=True, text=True, check=True).stdout
+    by_address = {}
+    for line in output.splitlines():
+        match = NM_LINE.match(line)
+        if not match:
+            continue
+        address, size, _, name = match.groups()
+        address, size = int(address, 16), int(size or "0", 16)
+        name = HASH.sub("", name).encode()[:MAX_NAME]
+        # aliases share the address, the one with a size is more useful
+        if address not in by_address or size > by_address[address][0]:
+            by_address[address] = (size, name)
+    return sorted((address, size, name) for address, (size, name) in by_address.items())
+
+
+def build_table(entries):
+    names_start = HEADER.size + len(entries) * ENTRY.size
+    table = bytearray(HEADER.pack(MAGIC, len(entries)))
+    names = bytearray()
+    for address, size, name in entries:
+        table += ENTRY.pack(address, size, names_start + len(names), len(name))
+        names += name
+    return bytes(table + names)
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__.strip().splitlines()[-1])
+    path = sys.argv[1]
+    with open(path, "rb") as file:
+        elf = bytearray(file.read())
+
+    section = find_section(elf, SECTION)
+    if section is None:
+        return
+    offset, size, kind = section
+    if kind != SHT_PROGBITS:
+        sys.exit(f"{path}: {SECTION} is not stored in the file")
+
+    try:
+        entries = symbols(path)
+    except FileNotFoundError:
+        print("warning: nm not found, backtraces won't show names", file=sys.stderr)
+        return
+    table = build_table(entries)
+    if len(table) > size:
+        sys.exit(
+            f"{path}: the symbol table needs {len(table)} bytes but {SECTION} has {size}, "
+            "raise TABLE_SIZE in src/backtrace/symbols.rs"
+        )
+
+    elf[offset : offset + size] = table.ljust(size, b"\0")
+    with open(path, "wb") as file:
+        file.write(elf)
+
+
+if __name__ == "__main__":
+    main()
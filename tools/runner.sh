@@ -0,0 +1,9 @@
+#!/bin/sh
+# Cargo runner: embeds the symbol table for backtraces, then boots the kernel with bootimage
+set -e
+if command -v python3 >/dev/null 2>&1; then
+    python3 "$(dirname "$0")/embed_symbols.py" "$1"
+else
+    echo "warning: python3 not found, backtraces won't show symbol names" >&2
+fi
+exec bootimage runner "$@"
@@ -0,0 +1,53 @@
+# Minimal user program for the ELF loader
+#
+# Build with:
+#   as --64 -o hello.o hello.s
+#   ld -static -nostdlib -z max-page-size=0x1000 -Ttext-segment=0x8000000000 -o hello.elf hello.o
+#
+# Syscall numbers match `syscall::Syscall`.
+
+.intel_syntax noprefix
+
+.section .text
+.global _start
+_start:
+    # write(1, msg, msg_len)
+    mov rax, 0
+    mov rdi, 1
+    lea rsi, [rip + msg]
+    mov rdx, offset msg_len
+    syscall
+
+    # write(1, argv[0], strlen(argv[0])) if argc > 0
+    cmp qword ptr [rsp], 0
+    je 2f
+    mov rsi, [rsp + 8]
+    xor rdx, rdx
+1:
+    cmp byte ptr [rsi + rdx], 0
+    je 1f
+    inc rdx
+    jmp 1b
+1:
+    mov rax, 0
+    mov rdi, 1
+    syscall
+
+    mov rax, 0
+    mov rdi, 1
+    lea rsi, [rip + newline]
+    mov rdx, 1
+    syscall
+2:
+    # exit(0)
+    mov rax, 1
+    xor edi, edi
+    syscall
+    ud2
+
+.section .rodata
+msg:
+    .ascii "hello from user space, my name is "
+msg_len = . - msg
+newline:
+    .ascii "\n"